@@ -0,0 +1,19 @@
+create table user_session (
+    id serial primary key,
+    user_id integer not null,
+    revoked_at timestamptz,
+    created_at timestamptz not null default current_timestamp,
+    foreign key (user_id) references users (id)
+);
+
+-- Refresh tokens of a session form a chain: each refresh marks the presented token as used
+-- and issues the next one. Only the SHA-256 hash of a token is stored.
+create table refresh_token (
+    id serial primary key,
+    session_id integer not null,
+    token_hash varchar(64) not null unique,
+    used_at timestamptz,
+    expires_at timestamptz not null,
+    created_at timestamptz not null default current_timestamp,
+    foreign key (session_id) references user_session (id) on delete cascade
+);
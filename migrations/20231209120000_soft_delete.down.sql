@@ -0,0 +1,3 @@
+alter table message drop constraint message_parent_id_fkey;
+alter table users drop column is_admin;
+alter table message drop column deleted_at;
@@ -0,0 +1,14 @@
+alter table message add column deleted_at timestamptz;
+
+alter table users add column is_admin boolean not null default false;
+
+-- Replies whose parent was hard deleted before this migration become top-level messages.
+update message
+set parent_id = null
+where parent_id is not null
+and parent_id not in (select id from message);
+
+-- Soft deletes keep the parent row, so only an admin purge removes a thread.
+alter table message
+add constraint message_parent_id_fkey
+foreign key (parent_id) references message (id) on delete cascade;
@@ -0,0 +1,2 @@
+drop table message_revision;
+alter table message drop column edited_at;
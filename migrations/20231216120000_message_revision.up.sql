@@ -0,0 +1,14 @@
+alter table message add column edited_at timestamptz;
+
+-- One row per edit: the body that was replaced, who replaced it and when.
+create table message_revision (
+    id serial primary key,
+    message_id integer not null,
+    message text not null,
+    editor_id integer not null,
+    edited_at timestamptz not null default current_timestamp,
+    foreign key (message_id) references message (id) on delete cascade,
+    foreign key (editor_id) references users (id)
+);
+
+create index message_revision_message_id_idx on message_revision (message_id);
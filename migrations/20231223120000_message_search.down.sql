@@ -0,0 +1,3 @@
+alter table message drop column search_japanese;
+alter table message drop column search_english;
+drop function message_ngrams;
@@ -0,0 +1,24 @@
+-- Postgres has no Japanese text search parser, so Japanese text is indexed as
+-- overlapping unigrams and bigrams of its characters.
+create function message_ngrams(body text) returns text
+language sql immutable parallel safe
+as $$
+    select coalesce(string_agg(gram, ' '), '')
+    from (
+        select substr(body, i, 1) as gram
+        from generate_series(1, char_length(body)) as i
+        union all
+        select substr(body, i, 2)
+        from generate_series(1, char_length(body) - 1) as i
+    ) grams
+    where gram !~ '\s'
+$$;
+
+alter table message
+add column search_english tsvector
+    generated always as (to_tsvector('english', message)) stored,
+add column search_japanese tsvector
+    generated always as (to_tsvector('simple', message_ngrams(message))) stored;
+
+create index message_search_english_idx on message using gin (search_english);
+create index message_search_japanese_idx on message using gin (search_japanese);
@@ -0,0 +1,3 @@
+alter table message drop column channel_id;
+drop table channel_member;
+drop table channel;
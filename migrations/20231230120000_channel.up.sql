@@ -0,0 +1,38 @@
+create table channel (
+    id serial primary key,
+    name varchar(255) not null unique,
+    created_by integer,
+    created_at timestamptz not null default current_timestamp,
+    foreign key (created_by) references users (id)
+);
+
+create table channel_member (
+    channel_id integer not null,
+    user_id integer not null,
+    joined_at timestamptz not null default current_timestamp,
+    primary key (channel_id, user_id),
+    foreign key (channel_id) references channel (id) on delete cascade,
+    foreign key (user_id) references users (id) on delete cascade
+);
+
+create index channel_member_user_id_idx on channel_member (user_id);
+
+-- Messages posted before channels existed move to a general channel every existing user belongs to.
+insert into channel (name) values ('general');
+
+insert into channel_member (channel_id, user_id)
+select c.id, u.id
+from channel c, users u;
+
+alter table message add column channel_id integer;
+
+update message
+set channel_id = (select id from channel where name = 'general');
+
+alter table message alter column channel_id set not null;
+
+alter table message
+add constraint message_channel_id_fkey
+foreign key (channel_id) references channel (id) on delete cascade;
+
+create index message_channel_id_idx on message (channel_id);
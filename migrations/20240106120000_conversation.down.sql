@@ -0,0 +1,8 @@
+create index message_channel_id_idx on message (channel_id);
+drop index message_channel_id_message_time_idx;
+
+alter table channel_member drop column last_read_at;
+
+delete from channel where is_direct;
+alter table channel alter column name set not null;
+alter table channel drop column is_direct;
@@ -0,0 +1,9 @@
+-- Direct conversations are private channels without a name.
+alter table channel add column is_direct boolean not null default false;
+alter table channel alter column name drop not null;
+
+-- Messages posted after last_read_at count as unread.
+alter table channel_member add column last_read_at timestamptz;
+
+create index message_channel_id_message_time_idx on message (channel_id, message_time, id);
+drop index message_channel_id_idx;
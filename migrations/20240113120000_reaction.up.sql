@@ -0,0 +1,10 @@
+-- One row per user and emoji on a message.
+create table message_reaction (
+    message_id integer not null,
+    user_id integer not null,
+    emoji varchar(64) not null,
+    created_at timestamptz not null default current_timestamp,
+    primary key (message_id, user_id, emoji),
+    foreign key (message_id) references message (id) on delete cascade,
+    foreign key (user_id) references users (id) on delete cascade
+);
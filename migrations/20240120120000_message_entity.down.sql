@@ -0,0 +1,2 @@
+drop table message_tag;
+drop table message_mention;
@@ -0,0 +1,32 @@
+-- Users mentioned with @name and tags used with #tag in a message body.
+create table message_mention (
+    message_id integer not null,
+    user_id integer not null,
+    primary key (message_id, user_id),
+    foreign key (message_id) references message (id) on delete cascade,
+    foreign key (user_id) references users (id) on delete cascade
+);
+
+create index message_mention_user_id_idx on message_mention (user_id);
+
+create table message_tag (
+    message_id integer not null,
+    tag varchar(64) not null,
+    primary key (message_id, tag),
+    foreign key (message_id) references message (id) on delete cascade
+);
+
+create index message_tag_tag_idx on message_tag (tag);
+
+-- Extract entities from existing messages. The application parses new
+-- messages itself, so this only has to be close enough.
+insert into message_mention (message_id, user_id)
+select distinct m.id, u.id
+from message m
+cross join lateral regexp_matches(m.message, '(?:^|[^\w@])@(\w+)', 'g') as match
+inner join users u on lower(u.name) = lower(match[1]);
+
+insert into message_tag (message_id, tag)
+select distinct m.id, lower(match[1])
+from message m
+cross join lateral regexp_matches(m.message, '(?:^|[^\w#])#(\w{1,64})(?!\w)', 'g') as match;
@@ -0,0 +1,2 @@
+drop table notification;
+drop type notification_kind;
@@ -0,0 +1,19 @@
+create type notification_kind as enum ('reply', 'mention');
+
+-- Tells a user that `actor_id` replied to or mentioned them in `message_id`.
+create table notification (
+    id serial primary key,
+    user_id integer not null,
+    kind notification_kind not null,
+    message_id integer not null,
+    actor_id integer not null,
+    created_at timestamptz not null default current_timestamp,
+    read_at timestamptz,
+    unique (user_id, message_id, kind),
+    foreign key (user_id) references users (id) on delete cascade,
+    foreign key (message_id) references message (id) on delete cascade,
+    foreign key (actor_id) references users (id) on delete cascade
+);
+
+create index notification_user_id_created_at_idx on notification (user_id, created_at, id);
+create index notification_unread_idx on notification (user_id) where read_at is null;
@@ -0,0 +1,9 @@
+drop table moderator_action;
+drop type moderator_action_kind;
+
+alter table users add column is_admin boolean not null default false;
+
+update users set is_admin = true where role = 'admin';
+
+alter table users drop column role;
+drop type user_role;
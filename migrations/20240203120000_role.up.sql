@@ -0,0 +1,23 @@
+-- Roles are ordered: admins can do everything moderators can.
+create type user_role as enum ('user', 'moderator', 'admin');
+
+alter table users add column role user_role not null default 'user';
+
+update users set role = 'admin' where is_admin;
+
+alter table users drop column is_admin;
+
+-- Edits and deletes of other users' messages by moderators.
+create type moderator_action_kind as enum ('edit_message', 'delete_message');
+
+create table moderator_action (
+    id serial primary key,
+    moderator_id integer not null,
+    kind moderator_action_kind not null,
+    message_id integer not null,
+    created_at timestamptz not null default current_timestamp,
+    foreign key (moderator_id) references users (id),
+    foreign key (message_id) references message (id) on delete cascade
+);
+
+create index moderator_action_message_id_idx on moderator_action (message_id);
@@ -0,0 +1,5 @@
+drop table message_report;
+drop type report_action;
+drop function is_moderator;
+alter table users drop column suspended_at;
+alter table message drop column hidden_at;
@@ -0,0 +1,31 @@
+-- Hidden messages are only shown to moderators.
+alter table message add column hidden_at timestamptz;
+
+-- Suspended users cannot log in.
+alter table users add column suspended_at timestamptz;
+
+create function is_moderator(user_id integer) returns boolean
+language sql stable parallel safe
+as $$
+    select coalesce((select role >= 'moderator' from users where id = user_id), false)
+$$;
+
+create type report_action as enum ('dismiss', 'hide_message', 'suspend_author');
+
+-- A user's report of a message. Open until a moderator resolves it with an action.
+create table message_report (
+    id serial primary key,
+    message_id integer not null,
+    reporter_id integer not null,
+    reason text not null,
+    created_at timestamptz not null default current_timestamp,
+    resolved_at timestamptz,
+    resolved_by integer,
+    action report_action,
+    unique (message_id, reporter_id),
+    foreign key (message_id) references message (id) on delete cascade,
+    foreign key (reporter_id) references users (id) on delete cascade,
+    foreign key (resolved_by) references users (id)
+);
+
+create index message_report_open_idx on message_report (created_at, id) where resolved_at is null;
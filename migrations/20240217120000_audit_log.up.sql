@@ -0,0 +1,30 @@
+-- Who changed what, and from where. Ids are not foreign keys, so events
+-- outlive the users and rows they refer to.
+create table audit_event (
+    id serial primary key,
+    actor_id integer,
+    action text not null,
+    target_id integer,
+    before jsonb,
+    after jsonb,
+    ip text,
+    user_agent text,
+    created_at timestamptz not null default current_timestamp
+);
+
+create index audit_event_created_at_idx on audit_event (created_at desc, id desc);
+create index audit_event_actor_id_idx on audit_event (actor_id, created_at desc, id desc);
+create index audit_event_target_idx on audit_event (action, target_id);
+
+-- The log is append-only.
+create function audit_event_append_only() returns trigger
+language plpgsql
+as $$
+begin
+    raise exception 'audit_event is append-only';
+end;
+$$;
+
+create trigger audit_event_append_only
+before update or delete or truncate on audit_event
+for each statement execute function audit_event_append_only();
@@ -0,0 +1,33 @@
+-- Deleting a user keeps their messages, revisions and moderation records without an author.
+alter table message alter column user_id drop not null;
+alter table message drop constraint message_user_id_fkey;
+alter table message
+add constraint message_user_id_fkey
+foreign key (user_id) references users (id) on delete set null;
+
+alter table message_revision alter column editor_id drop not null;
+alter table message_revision drop constraint message_revision_editor_id_fkey;
+alter table message_revision
+add constraint message_revision_editor_id_fkey
+foreign key (editor_id) references users (id) on delete set null;
+
+alter table moderator_action alter column moderator_id drop not null;
+alter table moderator_action drop constraint moderator_action_moderator_id_fkey;
+alter table moderator_action
+add constraint moderator_action_moderator_id_fkey
+foreign key (moderator_id) references users (id) on delete set null;
+
+alter table message_report drop constraint message_report_resolved_by_fkey;
+alter table message_report
+add constraint message_report_resolved_by_fkey
+foreign key (resolved_by) references users (id) on delete set null;
+
+alter table channel drop constraint channel_created_by_fkey;
+alter table channel
+add constraint channel_created_by_fkey
+foreign key (created_by) references users (id) on delete set null;
+
+alter table user_session drop constraint user_session_user_id_fkey;
+alter table user_session
+add constraint user_session_user_id_fkey
+foreign key (user_id) references users (id) on delete cascade;
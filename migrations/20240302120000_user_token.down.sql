@@ -0,0 +1,3 @@
+drop table user_token;
+drop type user_token_kind;
+alter table users drop column email_verified_at;
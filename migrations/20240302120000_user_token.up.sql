@@ -0,0 +1,20 @@
+-- Set once the user proves they own the address. Changing the email clears it.
+alter table users add column email_verified_at timestamptz;
+
+create type user_token_kind as enum ('password_reset', 'email_verification');
+
+-- Single-use tokens sent by email. Only the SHA-256 hash of a token is stored.
+create table user_token (
+    id serial primary key,
+    user_id integer not null,
+    kind user_token_kind not null,
+    token_hash varchar(64) not null unique,
+    -- The address a verification token was sent to.
+    email varchar(255),
+    expires_at timestamptz not null,
+    used_at timestamptz,
+    created_at timestamptz not null default current_timestamp,
+    foreign key (user_id) references users (id) on delete cascade
+);
+
+create index user_token_user_id_idx on user_token (user_id, kind);
@@ -0,0 +1,2 @@
+drop table login_failure;
+drop type login_failure_scope;
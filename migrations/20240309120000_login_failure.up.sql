@@ -0,0 +1,12 @@
+create type login_failure_scope as enum ('account', 'ip');
+
+-- Recent failed logins per email and per IP address. Emails are tracked whether or
+-- not an account exists, so lockouts do not reveal which emails are registered.
+create table login_failure (
+    scope login_failure_scope not null,
+    subject varchar(255) not null,
+    failures integer not null,
+    last_failed_at timestamptz not null,
+    locked_until timestamptz,
+    primary key (scope, subject)
+);
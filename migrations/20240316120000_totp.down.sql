@@ -0,0 +1,8 @@
+delete from user_token where kind = 'login_challenge';
+alter type user_token_kind rename to user_token_kind_old;
+create type user_token_kind as enum ('password_reset', 'email_verification');
+alter table user_token alter column kind type user_token_kind using kind::text::user_token_kind;
+drop type user_token_kind_old;
+
+drop table totp_recovery_code;
+drop table user_totp;
@@ -0,0 +1,26 @@
+-- TOTP (RFC 6238) second factor. The secret is needed to compute codes, so unlike
+-- passwords and tokens it cannot be stored hashed.
+create table user_totp (
+    user_id integer primary key,
+    secret bytea not null,
+    -- Null until the user confirms the enrollment with a code.
+    confirmed_at timestamptz,
+    -- Time step of the last accepted code, so a code cannot be used twice.
+    last_used_step bigint,
+    created_at timestamptz not null default current_timestamp,
+    foreign key (user_id) references users (id) on delete cascade
+);
+
+-- Single-use codes for when the authenticator is lost. Only the SHA-256 hash is stored.
+create table totp_recovery_code (
+    id serial primary key,
+    user_id integer not null,
+    code_hash varchar(64) not null,
+    used_at timestamptz,
+    foreign key (user_id) references users (id) on delete cascade
+);
+
+create index totp_recovery_code_user_id_idx on totp_recovery_code (user_id);
+
+-- Issued by login to users with TOTP, and exchanged for tokens together with a code.
+alter type user_token_kind add value 'login_challenge';
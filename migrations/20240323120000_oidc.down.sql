@@ -0,0 +1,2 @@
+drop table user_identity;
+drop table oidc_login;
@@ -0,0 +1,24 @@
+-- Logins through the OpenID Connect provider that were started but not finished yet.
+-- The state is sent to the provider, so only its SHA-256 hash is stored.
+create table oidc_login (
+    state_hash varchar(64) primary key,
+    -- PKCE verifier and nonce, checked against the provider's responses.
+    code_verifier varchar(64) not null,
+    nonce varchar(64) not null,
+    expires_at timestamptz not null,
+    created_at timestamptz not null default current_timestamp
+);
+
+-- Accounts at identity providers that users log in with, by the provider's issuer
+-- and its id for the account.
+create table user_identity (
+    id serial primary key,
+    user_id integer not null,
+    issuer varchar(255) not null,
+    subject varchar(255) not null,
+    created_at timestamptz not null default current_timestamp,
+    unique (issuer, subject),
+    foreign key (user_id) references users (id) on delete cascade
+);
+
+create index user_identity_user_id_idx on user_identity (user_id);
@@ -0,0 +1,13 @@
+update message_revision r
+set editor_id = w.editor_id, edited_at = w.edited_at
+from (
+    select r.id,
+        lead(r.editor_id, 1, m.editor_id) over w as editor_id,
+        lead(r.edited_at, 1, m.edited_at) over w as edited_at
+    from message_revision r
+    inner join message m on m.id = r.message_id
+    window w as (partition by r.message_id order by r.edited_at, r.id)
+) w
+where w.id = r.id;
+
+alter table message drop column editor_id;
@@ -0,0 +1,29 @@
+-- Who wrote the current body of a message, once it has been edited, so the
+-- revision kept when it is replaced names its author.
+alter table message add column editor_id integer;
+alter table message
+add constraint message_editor_id_fkey
+foreign key (editor_id) references users (id) on delete set null;
+
+update message m
+set editor_id = r.editor_id
+from (
+    select distinct on (message_id) message_id, editor_id
+    from message_revision
+    order by message_id, edited_at desc, id desc
+) r
+where r.message_id = m.id;
+
+-- Revisions named who replaced each body and when. Name who wrote it and when
+-- instead: the previous editor, or the author for the original body.
+update message_revision r
+set editor_id = w.editor_id, edited_at = w.edited_at
+from (
+    select r.id,
+        lag(r.editor_id, 1, m.user_id) over w as editor_id,
+        lag(r.edited_at, 1, m.message_time) over w as edited_at
+    from message_revision r
+    inner join message m on m.id = r.message_id
+    window w as (partition by r.message_id order by r.edited_at, r.id)
+) w
+where w.id = r.id;
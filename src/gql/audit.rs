@@ -0,0 +1,31 @@
+use crate::{
+    gql::auth::current_user,
+    models::audit::{AuditContext, ClientInfo},
+};
+use actix_web::{http::header, HttpRequest};
+use async_graphql::Context;
+
+// The peer address is used rather than forwarded headers, which clients can set freely.
+pub fn client_info(req: &HttpRequest) -> ClientInfo {
+    ClientInfo {
+        ip: req.peer_addr().map(|addr| addr.ip().to_string()),
+        user_agent: req
+            .headers()
+            .get(header::USER_AGENT)
+            .and_then(|value| value.to_str().ok())
+            .map(str::to_string),
+    }
+}
+
+// Get the client of the current request from the context.
+pub fn current_client(ctx: &Context<'_>) -> ClientInfo {
+    ctx.data_opt::<ClientInfo>().cloned().unwrap_or_default()
+}
+
+// The current user and client, recorded with the changes they make.
+pub fn context(ctx: &Context<'_>) -> AuditContext {
+    AuditContext {
+        actor_id: current_user(ctx).ok().map(|user| user.id),
+        client: current_client(ctx),
+    }
+}
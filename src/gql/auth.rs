@@ -0,0 +1,85 @@
+use crate::{
+    gql::error::graphql_error,
+    models::{
+        error::ModelError,
+        users::{verify_token, Role},
+    },
+};
+use actix_web::{http::header, HttpRequest};
+use async_graphql::{Context, Guard};
+use sqlx::PgPool;
+
+// The user authenticated by the bearer token of the current request.
+#[derive(Debug, Clone, Copy)]
+pub struct AuthUser {
+    pub id: i32,
+    pub session_id: i32,
+    // Read from the database with the session, so a role change applies right away.
+    pub role: Role,
+}
+
+impl AuthUser {
+    // Verify the `Authorization: Bearer ...` header once per request.
+    // Requests without a valid token are treated as anonymous.
+    pub async fn from_request(req: &HttpRequest, pool: &PgPool) -> Option<Self> {
+        let value = req.headers().get(header::AUTHORIZATION)?.to_str().ok()?;
+        Self::from_bearer(value, pool).await
+    }
+
+    // Subscriptions send the header in the connection_init payload instead,
+    // e.g. `{"Authorization": "Bearer ..."}`.
+    pub async fn from_connection_init(payload: &serde_json::Value, pool: &PgPool) -> Option<Self> {
+        let value = payload
+            .get("Authorization")
+            .or_else(|| payload.get("authorization"))?
+            .as_str()?;
+        Self::from_bearer(value, pool).await
+    }
+
+    async fn from_bearer(value: &str, pool: &PgPool) -> Option<Self> {
+        let token = value.strip_prefix("Bearer ")?;
+        let identity = verify_token(token.trim(), pool).await.ok()?;
+        Some(AuthUser {
+            id: identity.user_id,
+            session_id: identity.session_id,
+            role: identity.role,
+        })
+    }
+}
+
+// Get the authenticated user from the context.
+pub fn current_user(ctx: &Context<'_>) -> Result<AuthUser, ModelError> {
+    ctx.data_opt::<AuthUser>()
+        .copied()
+        .ok_or_else(|| ModelError::unauthenticated("Unauthorized."))
+}
+
+// Require a logged-in user for the field.
+pub struct LoginGuard;
+
+#[async_graphql::async_trait::async_trait]
+impl Guard for LoginGuard {
+    async fn check(&self, ctx: &Context<'_>) -> async_graphql::Result<()> {
+        if ctx.data_opt::<AuthUser>().is_some() {
+            Ok(())
+        } else {
+            Err(graphql_error(&ModelError::unauthenticated("Unauthorized.")))
+        }
+    }
+}
+
+// Require a logged-in user with at least the given role for the field,
+// e.g. `#[graphql(guard = "RequireRole(Role::Moderator)")]`.
+pub struct RequireRole(pub Role);
+
+#[async_graphql::async_trait::async_trait]
+impl Guard for RequireRole {
+    async fn check(&self, ctx: &Context<'_>) -> async_graphql::Result<()> {
+        let user = current_user(ctx).map_err(|e| graphql_error(&e))?;
+        if user.role >= self.0 {
+            Ok(())
+        } else {
+            Err(graphql_error(&ModelError::forbidden("Forbidden.")))
+        }
+    }
+}
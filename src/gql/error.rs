@@ -0,0 +1,110 @@
+use crate::models::error::ModelError;
+use async_graphql::{
+    async_trait::async_trait,
+    extensions::{Extension, ExtensionContext, ExtensionFactory, NextRequest},
+    ErrorExtensionValues, ErrorExtensions, Response, ServerError,
+};
+use std::sync::Arc;
+
+// Error returned by resolvers.
+// Model errors are reported to clients with a machine readable `extensions.code`.
+// Internal errors are logged and replaced with a generic message.
+pub struct ApiError(async_graphql::Error);
+
+impl From<ModelError> for ApiError {
+    fn from(e: ModelError) -> Self {
+        ApiError(graphql_error(&e))
+    }
+}
+
+impl From<Arc<ModelError>> for ApiError {
+    fn from(e: Arc<ModelError>) -> Self {
+        ApiError(graphql_error(&e))
+    }
+}
+
+impl From<ApiError> for async_graphql::Error {
+    fn from(e: ApiError) -> Self {
+        e.0
+    }
+}
+
+pub fn graphql_error(e: &ModelError) -> async_graphql::Error {
+    let (code, message) = match e {
+        ModelError::NotFound(message) => ("NOT_FOUND", message.as_str()),
+        ModelError::Unauthenticated(message) => ("UNAUTHENTICATED", message.as_str()),
+        ModelError::Forbidden(message) => ("FORBIDDEN", message.as_str()),
+        ModelError::Validation(message) => ("VALIDATION", message.as_str()),
+        ModelError::Conflict(message) => ("CONFLICT", message.as_str()),
+        ModelError::Locked(message) => ("LOCKED", message.as_str()),
+        ModelError::Internal(e) => {
+            log::error!("{:?}", e);
+            ("INTERNAL", "Internal server error.")
+        }
+    };
+    async_graphql::Error::new(message).extend_with(|_, extensions| extensions.set("code", code))
+}
+
+// Errors raised by async-graphql itself, such as invalid connection arguments,
+// carry no code and are caused by the request.
+pub fn with_validation_code(e: async_graphql::Error) -> async_graphql::Error {
+    let has_code = e
+        .extensions
+        .as_ref()
+        .is_some_and(|extensions| extensions.get("code").is_some());
+    if has_code {
+        e
+    } else {
+        e.extend_with(|_, extensions| extensions.set("code", "VALIDATION"))
+    }
+}
+
+// Error for a request rejected before it is executed.
+pub fn request_error(code: &str, message: &str) -> ServerError {
+    let mut extensions = ErrorExtensionValues::default();
+    extensions.set("code", code);
+    let mut error = ServerError::new(message, None);
+    error.extensions = Some(extensions);
+    error
+}
+
+// A model error in the same shape, for routes outside of GraphQL.
+pub fn model_request_error(e: &ModelError) -> ServerError {
+    let error = graphql_error(e);
+    let mut server_error = ServerError::new(error.message, None);
+    server_error.extensions = error.extensions;
+    server_error
+}
+
+// Give errors raised before execution a code too: parse and validation errors,
+// and queries over the depth or complexity limit.
+pub struct ErrorCodes;
+
+impl ExtensionFactory for ErrorCodes {
+    fn create(&self) -> Arc<dyn Extension> {
+        Arc::new(ErrorCodes)
+    }
+}
+
+#[async_trait]
+impl Extension for ErrorCodes {
+    async fn request(&self, ctx: &ExtensionContext<'_>, next: NextRequest<'_>) -> Response {
+        let mut response = next.run(ctx).await;
+        for error in &mut response.errors {
+            let extensions = error.extensions.get_or_insert_with(Default::default);
+            if extensions.get("code").is_none() {
+                extensions.set("code", request_error_code(&error.message));
+            }
+        }
+        response
+    }
+}
+
+// async-graphql only reports the limits by message.
+fn request_error_code(message: &str) -> &'static str {
+    match message {
+        "Query is nested too deep." => "QUERY_TOO_DEEP",
+        "Query is too complex." => "QUERY_TOO_COMPLEX",
+        _ => "VALIDATION",
+    }
+}
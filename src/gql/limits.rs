@@ -0,0 +1,23 @@
+use crate::models::pagination::{DEFAULT_PAGE_SIZE, MAX_LIST_SIZE, MAX_PAGE_SIZE};
+
+// Reactions are not capped, so a message is assumed to have this many kinds.
+pub const REACTION_LIST_SIZE: usize = 20;
+
+// Complexity of a connection: its selection counted once per requested item.
+pub fn page_complexity(first: Option<i32>, last: Option<i32>, child_complexity: usize) -> usize {
+    let size = first
+        .or(last)
+        .map(|size| size.clamp(0, MAX_PAGE_SIZE as i32) as usize)
+        .unwrap_or(DEFAULT_PAGE_SIZE);
+    size.max(1) * child_complexity
+}
+
+// Lists that are not paginated can hold up to MAX_LIST_SIZE items.
+pub fn list_complexity(child_complexity: usize) -> usize {
+    bounded_list_complexity(MAX_LIST_SIZE as usize, child_complexity)
+}
+
+// Lists with a smaller limit of their own.
+pub fn bounded_list_complexity(size: usize, child_complexity: usize) -> usize {
+    size * child_complexity
+}
@@ -0,0 +1,197 @@
+use crate::models::{
+    error::ModelError,
+    message::{MessageModel, MessageModelResponse},
+    pagination::{Cursor, Page, PageArgs},
+    reaction::ReactionModel,
+    users::{UsersModel, UsersModelResponse},
+};
+use async_graphql::dataloader::Loader;
+use sqlx::PgPool;
+use std::{collections::HashMap, sync::Arc};
+
+// Batch loaders used by nested message fields, so a page of messages
+// resolves each field with a single query.
+
+pub struct UserLoader(pub PgPool);
+
+#[async_graphql::async_trait::async_trait]
+impl Loader<i32> for UserLoader {
+    type Value = UsersModelResponse;
+    type Error = Arc<ModelError>;
+
+    async fn load(&self, keys: &[i32]) -> Result<HashMap<i32, Self::Value>, Self::Error> {
+        let rows = UsersModel::find_by_ids(keys, &self.0).await?;
+        Ok(rows.into_iter().map(|row| (row.id, row)).collect())
+    }
+}
+
+pub struct MessageLoader(pub PgPool);
+
+#[async_graphql::async_trait::async_trait]
+impl Loader<i32> for MessageLoader {
+    type Value = MessageModelResponse;
+    type Error = Arc<ModelError>;
+
+    async fn load(&self, keys: &[i32]) -> Result<HashMap<i32, Self::Value>, Self::Error> {
+        let rows = MessageModel::find_by_ids(keys, &self.0).await?;
+        Ok(rows.into_iter().map(|row| (row.id, row)).collect())
+    }
+}
+
+// Messages the viewer can read, for fields that do not check it themselves.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct VisibleMessageKey {
+    pub message_id: i32,
+    pub viewer_id: i32,
+}
+
+pub struct VisibleMessageLoader(pub PgPool);
+
+#[async_graphql::async_trait::async_trait]
+impl Loader<VisibleMessageKey> for VisibleMessageLoader {
+    type Value = MessageModelResponse;
+    type Error = Arc<ModelError>;
+
+    async fn load(
+        &self,
+        keys: &[VisibleMessageKey],
+    ) -> Result<HashMap<VisibleMessageKey, Self::Value>, Self::Error> {
+        let mut groups: HashMap<i32, Vec<i32>> = HashMap::new();
+        for key in keys {
+            groups
+                .entry(key.viewer_id)
+                .or_default()
+                .push(key.message_id);
+        }
+
+        let mut messages = HashMap::new();
+        for (viewer_id, message_ids) in groups {
+            let rows =
+                MessageModel::find_by_ids_for_viewer(&message_ids, viewer_id, &self.0).await?;
+            messages.extend(rows.into_iter().map(|row| {
+                (
+                    VisibleMessageKey {
+                        message_id: row.id,
+                        viewer_id,
+                    },
+                    row,
+                )
+            }));
+        }
+        Ok(messages)
+    }
+}
+
+pub struct ReplyCountLoader(pub PgPool);
+
+#[async_graphql::async_trait::async_trait]
+impl Loader<i32> for ReplyCountLoader {
+    type Value = i64;
+    type Error = Arc<ModelError>;
+
+    async fn load(&self, keys: &[i32]) -> Result<HashMap<i32, Self::Value>, Self::Error> {
+        Ok(MessageModel::count_replies(keys, &self.0).await?)
+    }
+}
+
+pub struct ThreadRootLoader(pub PgPool);
+
+#[async_graphql::async_trait::async_trait]
+impl Loader<i32> for ThreadRootLoader {
+    type Value = i32;
+    type Error = Arc<ModelError>;
+
+    async fn load(&self, keys: &[i32]) -> Result<HashMap<i32, Self::Value>, Self::Error> {
+        Ok(MessageModel::find_thread_root_ids(keys, &self.0).await?)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct RepliesKey {
+    pub parent_id: i32,
+    pub after: Option<Cursor>,
+    pub first: Option<usize>,
+}
+
+pub struct RepliesLoader(pub PgPool);
+
+#[async_graphql::async_trait::async_trait]
+impl Loader<RepliesKey> for RepliesLoader {
+    type Value = Page<MessageModelResponse>;
+    type Error = Arc<ModelError>;
+
+    async fn load(
+        &self,
+        keys: &[RepliesKey],
+    ) -> Result<HashMap<RepliesKey, Self::Value>, Self::Error> {
+        // Keys with the same page arguments are loaded together.
+        let mut groups: HashMap<(Option<Cursor>, Option<usize>), Vec<i32>> = HashMap::new();
+        for key in keys {
+            groups
+                .entry((key.after, key.first))
+                .or_default()
+                .push(key.parent_id);
+        }
+
+        let mut pages = HashMap::new();
+        for ((after, first), parent_ids) in groups {
+            let args = PageArgs::new(after, None, first, None)?;
+            let rows = MessageModel::find_reply_pages(&parent_ids, args, &self.0).await?;
+            pages.extend(rows.into_iter().map(|(parent_id, page)| {
+                (
+                    RepliesKey {
+                        parent_id,
+                        after,
+                        first,
+                    },
+                    page,
+                )
+            }));
+        }
+        Ok(pages)
+    }
+}
+
+// Reactions depend on the viewer, who is part of the key because loaders are
+// shared by every request.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct ReactionsKey {
+    pub message_id: i32,
+    pub viewer_id: i32,
+}
+
+pub struct ReactionsLoader(pub PgPool);
+
+#[async_graphql::async_trait::async_trait]
+impl Loader<ReactionsKey> for ReactionsLoader {
+    type Value = Vec<ReactionModel>;
+    type Error = Arc<ModelError>;
+
+    async fn load(
+        &self,
+        keys: &[ReactionsKey],
+    ) -> Result<HashMap<ReactionsKey, Self::Value>, Self::Error> {
+        let mut groups: HashMap<i32, Vec<i32>> = HashMap::new();
+        for key in keys {
+            groups
+                .entry(key.viewer_id)
+                .or_default()
+                .push(key.message_id);
+        }
+
+        let mut reactions = HashMap::new();
+        for (viewer_id, message_ids) in groups {
+            let rows = ReactionModel::find_by_message_ids(&message_ids, viewer_id, &self.0).await?;
+            reactions.extend(rows.into_iter().map(|(message_id, rows)| {
+                (
+                    ReactionsKey {
+                        message_id,
+                        viewer_id,
+                    },
+                    rows,
+                )
+            }));
+        }
+        Ok(reactions)
+    }
+}
@@ -1,2 +1,9 @@
+pub mod auth;
+pub mod audit;
+pub mod error;
+pub mod limits;
+pub mod loaders;
+pub mod pagination;
 pub mod queries;
-pub mod mutations;
\ No newline at end of file
+pub mod mutations;
+pub mod subscriptions;
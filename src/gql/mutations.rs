@@ -1,60 +1,494 @@
-use crate::models::{
-    message::{MessageModel, MessageModelResponse},
-    users::{UsersModel, UsersModelResponse},
+use crate::{
+    gql::{
+        audit,
+        auth::{current_user, LoginGuard, RequireRole},
+        error::ApiError,
+        queries::{Action, LoginResult},
+    },
+    mailer::{self, Email, Mailer},
+    models::{
+        channel::ChannelModel,
+        conversation::ConversationModel,
+        login_failure::LoginFailureModel,
+        message::{MessageModel, MessageModelResponse},
+        notification::NotificationModel,
+        reaction::ReactionModel,
+        report::ReportModel,
+        session::{SessionModel, TokenPair},
+        token::UserTokenModel,
+        totp::{TotpEnrollment, TotpModel},
+        users::{DeletionPolicy, Role, UsersModel, UsersModelResponse},
+    },
 };
-use anyhow::{Error, Result};
-use async_graphql::Object;
+use async_graphql::{Enum, Object};
 use sqlx::postgres::PgPool;
+use std::sync::Arc;
 
 pub struct MutationRoot;
 
+#[derive(Enum, Copy, Clone, Eq, PartialEq)]
+#[graphql(name = "Role", remote = "Role")]
+enum UserRole {
+    User,
+    Moderator,
+    Admin,
+}
+
 #[Object]
 impl MutationRoot {
+    #[graphql(guard = "LoginGuard")]
     async fn create_message(
         &self,
         ctx: &async_graphql::Context<'_>,
-        user_id: i32,
+        channel_id: i32,
         message: String,
         parent_id: Option<i32>,
-        token: String,
-    ) -> Result<MessageModelResponse, Error> {
+    ) -> Result<MessageModelResponse, ApiError> {
         let pool = ctx.data::<PgPool>().expect("Failed to get pool.");
-        let row = MessageModel::create(user_id, message, parent_id, pool, token).await?;
+        let user = current_user(ctx)?;
+        let row = MessageModel::create(
+            user.id,
+            channel_id,
+            message,
+            parent_id,
+            &audit::context(ctx),
+            pool,
+        )
+        .await?;
         Ok(row)
     }
 
+    #[graphql(guard = "LoginGuard")]
     async fn modify_message(
         &self,
         ctx: &async_graphql::Context<'_>,
         id: i32,
         message: String,
-        token: String,
-    ) -> Result<MessageModelResponse, Error> {
+    ) -> Result<MessageModelResponse, ApiError> {
         let pool = ctx.data::<PgPool>().expect("Failed to get pool.");
-        let row = MessageModel::modify(id, message, pool, token).await?;
+        let user = current_user(ctx)?;
+        let row = MessageModel::modify(id, user.id, user.role, message, &audit::context(ctx), pool)
+            .await?;
         Ok(row)
     }
 
+    #[graphql(guard = "LoginGuard")]
     async fn delete_message(
         &self,
         ctx: &async_graphql::Context<'_>,
         id: i32,
-        token: String,
-    ) -> Result<i32, Error> {
+    ) -> Result<i32, ApiError> {
+        let pool = ctx.data::<PgPool>().expect("Failed to get pool.");
+        let user = current_user(ctx)?;
+        let row = MessageModel::delete(id, user.id, user.role, &audit::context(ctx), pool).await?;
+        Ok(row)
+    }
+
+    #[graphql(guard = "LoginGuard")]
+    async fn add_reaction(
+        &self,
+        ctx: &async_graphql::Context<'_>,
+        message_id: i32,
+        emoji: String,
+    ) -> Result<MessageModelResponse, ApiError> {
         let pool = ctx.data::<PgPool>().expect("Failed to get pool.");
-        let row = MessageModel::delete(id, pool, token).await?;
+        let user = current_user(ctx)?;
+        let row =
+            ReactionModel::add(message_id, user.id, emoji, &audit::context(ctx), pool).await?;
         Ok(row)
     }
 
+    #[graphql(guard = "LoginGuard")]
+    async fn remove_reaction(
+        &self,
+        ctx: &async_graphql::Context<'_>,
+        message_id: i32,
+        emoji: String,
+    ) -> Result<MessageModelResponse, ApiError> {
+        let pool = ctx.data::<PgPool>().expect("Failed to get pool.");
+        let user = current_user(ctx)?;
+        let row =
+            ReactionModel::remove(message_id, user.id, emoji, &audit::context(ctx), pool).await?;
+        Ok(row)
+    }
+
+    // Permanently remove a message and its replies.
+    #[graphql(guard = "RequireRole(Role::Admin)")]
+    async fn purge_message(
+        &self,
+        ctx: &async_graphql::Context<'_>,
+        id: i32,
+    ) -> Result<Vec<i32>, ApiError> {
+        let pool = ctx.data::<PgPool>().expect("Failed to get pool.");
+        let ids = MessageModel::purge(id, &audit::context(ctx), pool).await?;
+        Ok(ids)
+    }
+
+    #[graphql(guard = "LoginGuard")]
+    async fn create_channel(
+        &self,
+        ctx: &async_graphql::Context<'_>,
+        name: String,
+    ) -> Result<ChannelModel, ApiError> {
+        let pool = ctx.data::<PgPool>().expect("Failed to get pool.");
+        let user = current_user(ctx)?;
+        let row = ChannelModel::create(name, user.id, &audit::context(ctx), pool).await?;
+        Ok(row)
+    }
+
+    #[graphql(guard = "LoginGuard")]
+    async fn join_channel(
+        &self,
+        ctx: &async_graphql::Context<'_>,
+        id: i32,
+    ) -> Result<ChannelModel, ApiError> {
+        let pool = ctx.data::<PgPool>().expect("Failed to get pool.");
+        let user = current_user(ctx)?;
+        let row = ChannelModel::join(id, user.id, &audit::context(ctx), pool).await?;
+        Ok(row)
+    }
+
+    #[graphql(guard = "LoginGuard")]
+    async fn leave_channel(
+        &self,
+        ctx: &async_graphql::Context<'_>,
+        id: i32,
+    ) -> Result<bool, ApiError> {
+        let pool = ctx.data::<PgPool>().expect("Failed to get pool.");
+        let user = current_user(ctx)?;
+        ChannelModel::leave(id, user.id, &audit::context(ctx), pool).await?;
+        Ok(true)
+    }
+
+    // Start a direct conversation, or return the existing one with the same participants.
+    #[graphql(guard = "LoginGuard")]
+    async fn create_conversation(
+        &self,
+        ctx: &async_graphql::Context<'_>,
+        participant_ids: Vec<i32>,
+    ) -> Result<ConversationModel, ApiError> {
+        let pool = ctx.data::<PgPool>().expect("Failed to get pool.");
+        let user = current_user(ctx)?;
+        let row =
+            ConversationModel::create(user.id, participant_ids, &audit::context(ctx), pool).await?;
+        Ok(row)
+    }
+
+    #[graphql(guard = "LoginGuard")]
+    async fn mark_conversation_read(
+        &self,
+        ctx: &async_graphql::Context<'_>,
+        id: i32,
+    ) -> Result<ConversationModel, ApiError> {
+        let pool = ctx.data::<PgPool>().expect("Failed to get pool.");
+        let user = current_user(ctx)?;
+        let row = ConversationModel::mark_read(id, user.id, &audit::context(ctx), pool).await?;
+        Ok(row)
+    }
+
+    // Returns the notifications marked as read.
+    #[graphql(guard = "LoginGuard")]
+    async fn mark_notifications_read(
+        &self,
+        ctx: &async_graphql::Context<'_>,
+        ids: Vec<i32>,
+    ) -> Result<Vec<NotificationModel>, ApiError> {
+        let pool = ctx.data::<PgPool>().expect("Failed to get pool.");
+        let user = current_user(ctx)?;
+        let rows = NotificationModel::mark_read(&ids, user.id, &audit::context(ctx), pool).await?;
+        Ok(rows)
+    }
+
+    #[graphql(guard = "LoginGuard")]
+    async fn report_message(
+        &self,
+        ctx: &async_graphql::Context<'_>,
+        message_id: i32,
+        reason: String,
+    ) -> Result<ReportModel, ApiError> {
+        let pool = ctx.data::<PgPool>().expect("Failed to get pool.");
+        let user = current_user(ctx)?;
+        let report =
+            ReportModel::create(message_id, user.id, reason, &audit::context(ctx), pool).await?;
+        Ok(report)
+    }
+
+    // Resolves every open report of the same message with the action.
+    #[graphql(guard = "RequireRole(Role::Moderator)")]
+    async fn resolve_report(
+        &self,
+        ctx: &async_graphql::Context<'_>,
+        id: i32,
+        action: Action,
+    ) -> Result<ReportModel, ApiError> {
+        let pool = ctx.data::<PgPool>().expect("Failed to get pool.");
+        let user = current_user(ctx)?;
+        let report =
+            ReportModel::resolve(id, user.id, action.into(), &audit::context(ctx), pool).await?;
+        Ok(report)
+    }
+
+    // Returns the new role, which applies from the user's next request.
+    #[graphql(guard = "RequireRole(Role::Admin)")]
+    async fn set_user_role(
+        &self,
+        ctx: &async_graphql::Context<'_>,
+        user_id: i32,
+        role: UserRole,
+    ) -> Result<UserRole, ApiError> {
+        let pool = ctx.data::<PgPool>().expect("Failed to get pool.");
+        let role = UsersModel::set_role(user_id, role.into(), &audit::context(ctx), pool).await?;
+        Ok(role.into())
+    }
+
+    // Lift a lockout from failed logins. Returns whether the user's email was locked.
+    #[graphql(guard = "RequireRole(Role::Admin)")]
+    async fn unlock_account(
+        &self,
+        ctx: &async_graphql::Context<'_>,
+        user_id: i32,
+    ) -> Result<bool, ApiError> {
+        let pool = ctx.data::<PgPool>().expect("Failed to get pool.");
+        let unlocked = LoginFailureModel::unlock_user(user_id, &audit::context(ctx), pool).await?;
+        Ok(unlocked)
+    }
+
     async fn create_user(
         &self,
         ctx: &async_graphql::Context<'_>,
         name: String,
         email: String,
         password: String,
-    ) -> Result<UsersModelResponse, Error> {
+    ) -> Result<UsersModelResponse, ApiError> {
         let pool = ctx.data::<PgPool>().expect("Failed to get pool.");
-        let row = UsersModel::create(name, email, password, pool).await?;
+        let row = UsersModel::create(name, email, password, &audit::context(ctx), pool).await?;
+        send_verification_token(ctx, row.id, pool).await;
         Ok(row)
     }
+
+    // Omitted fields are kept.
+    #[graphql(guard = "LoginGuard")]
+    async fn update_profile(
+        &self,
+        ctx: &async_graphql::Context<'_>,
+        name: Option<String>,
+        email: Option<String>,
+    ) -> Result<UsersModelResponse, ApiError> {
+        let pool = ctx.data::<PgPool>().expect("Failed to get pool.");
+        let user = current_user(ctx)?;
+        let email_changed = email.is_some();
+        let row =
+            UsersModel::update_profile(user.id, name, email, &audit::context(ctx), pool).await?;
+        if email_changed && row.email_verified_at.is_none() {
+            send_verification_token(ctx, row.id, pool).await;
+        }
+        Ok(row)
+    }
+
+    // Logs out every session. Returns the tokens of a new session.
+    #[graphql(guard = "LoginGuard")]
+    async fn change_password(
+        &self,
+        ctx: &async_graphql::Context<'_>,
+        current_password: String,
+        new_password: String,
+    ) -> Result<TokenPair, ApiError> {
+        let pool = ctx.data::<PgPool>().expect("Failed to get pool.");
+        let user = current_user(ctx)?;
+        let tokens = UsersModel::change_password(
+            user.id,
+            current_password,
+            new_password,
+            &audit::context(ctx),
+            pool,
+        )
+        .await?;
+        Ok(tokens)
+    }
+
+    // Messages are kept without an author or erased, depending on ACCOUNT_DELETION_POLICY.
+    #[graphql(guard = "LoginGuard")]
+    async fn delete_account(
+        &self,
+        ctx: &async_graphql::Context<'_>,
+        password: String,
+    ) -> Result<bool, ApiError> {
+        let pool = ctx.data::<PgPool>().expect("Failed to get pool.");
+        let policy = ctx
+            .data_opt::<DeletionPolicy>()
+            .copied()
+            .unwrap_or_default();
+        let user = current_user(ctx)?;
+        UsersModel::delete_account(user.id, password, policy, &audit::context(ctx), pool).await?;
+        Ok(true)
+    }
+
+    // Always succeeds, so the response does not reveal whether the email is registered.
+    async fn request_password_reset(
+        &self,
+        ctx: &async_graphql::Context<'_>,
+        email: String,
+    ) -> Result<bool, ApiError> {
+        let pool = ctx.data::<PgPool>().expect("Failed to get pool.");
+        let issued =
+            UserTokenModel::issue_password_reset(&email, &audit::context(ctx), pool).await?;
+        if let Some(issued) = issued {
+            send_email(ctx, mailer::password_reset_email(&issued));
+        }
+        Ok(true)
+    }
+
+    // Logs out every session of the user.
+    async fn reset_password(
+        &self,
+        ctx: &async_graphql::Context<'_>,
+        token: String,
+        new_password: String,
+    ) -> Result<bool, ApiError> {
+        let pool = ctx.data::<PgPool>().expect("Failed to get pool.");
+        UserTokenModel::reset_password(&token, &new_password, &audit::context(ctx), pool).await?;
+        Ok(true)
+    }
+
+    async fn verify_email(
+        &self,
+        ctx: &async_graphql::Context<'_>,
+        token: String,
+    ) -> Result<bool, ApiError> {
+        let pool = ctx.data::<PgPool>().expect("Failed to get pool.");
+        UserTokenModel::verify_email(&token, &audit::context(ctx), pool).await?;
+        Ok(true)
+    }
+
+    // Send a new verification token, invalidating earlier ones.
+    #[graphql(guard = "LoginGuard")]
+    async fn send_verification_email(
+        &self,
+        ctx: &async_graphql::Context<'_>,
+    ) -> Result<bool, ApiError> {
+        let pool = ctx.data::<PgPool>().expect("Failed to get pool.");
+        let user = current_user(ctx)?;
+        let issued =
+            UserTokenModel::resend_email_verification(user.id, &audit::context(ctx), pool).await?;
+        send_email(ctx, mailer::verification_email(&issued));
+        Ok(true)
+    }
+
+    // Returns the secret for the authenticator app. Takes effect once confirmed.
+    #[graphql(guard = "LoginGuard")]
+    async fn enroll_totp(
+        &self,
+        ctx: &async_graphql::Context<'_>,
+    ) -> Result<TotpEnrollment, ApiError> {
+        let pool = ctx.data::<PgPool>().expect("Failed to get pool.");
+        let user = current_user(ctx)?;
+        let enrollment = TotpModel::enroll(user.id, &audit::context(ctx), pool).await?;
+        Ok(enrollment)
+    }
+
+    // Enables two-factor authentication and returns the recovery codes.
+    #[graphql(guard = "LoginGuard")]
+    async fn confirm_totp(
+        &self,
+        ctx: &async_graphql::Context<'_>,
+        code: String,
+    ) -> Result<Vec<String>, ApiError> {
+        let pool = ctx.data::<PgPool>().expect("Failed to get pool.");
+        let user = current_user(ctx)?;
+        let recovery_codes = TotpModel::confirm(user.id, &code, &audit::context(ctx), pool).await?;
+        Ok(recovery_codes)
+    }
+
+    // Takes a code from the authenticator app or a recovery code.
+    #[graphql(guard = "LoginGuard")]
+    async fn disable_totp(
+        &self,
+        ctx: &async_graphql::Context<'_>,
+        code: String,
+    ) -> Result<bool, ApiError> {
+        let pool = ctx.data::<PgPool>().expect("Failed to get pool.");
+        let user = current_user(ctx)?;
+        TotpModel::disable(user.id, &code, &audit::context(ctx), pool).await?;
+        Ok(true)
+    }
+
+    // Users with two-factor authentication get a challenge for `completeLogin`.
+    async fn login(
+        &self,
+        ctx: &async_graphql::Context<'_>,
+        email: String,
+        password: String,
+    ) -> Result<LoginResult, ApiError> {
+        let pool = ctx.data::<PgPool>().expect("Failed to get pool.");
+        let outcome = UsersModel::login(email, password, &audit::current_client(ctx), pool).await?;
+        Ok(outcome.into())
+    }
+
+    // Takes a code from the authenticator app or a recovery code.
+    async fn complete_login(
+        &self,
+        ctx: &async_graphql::Context<'_>,
+        challenge: String,
+        code: String,
+    ) -> Result<TokenPair, ApiError> {
+        let pool = ctx.data::<PgPool>().expect("Failed to get pool.");
+        let tokens =
+            UsersModel::complete_login(challenge, code, &audit::current_client(ctx), pool).await?;
+        Ok(tokens)
+    }
+
+    async fn refresh_token(
+        &self,
+        ctx: &async_graphql::Context<'_>,
+        refresh_token: String,
+    ) -> Result<TokenPair, ApiError> {
+        let pool = ctx.data::<PgPool>().expect("Failed to get pool.");
+        let tokens = SessionModel::refresh(refresh_token, &audit::context(ctx), pool).await?;
+        Ok(tokens)
+    }
+
+    // Revoke the session of the current access token.
+    #[graphql(guard = "LoginGuard")]
+    async fn logout(&self, ctx: &async_graphql::Context<'_>) -> Result<bool, ApiError> {
+        let pool = ctx.data::<PgPool>().expect("Failed to get pool.");
+        let user = current_user(ctx)?;
+        SessionModel::revoke(user.session_id, user.id, &audit::context(ctx), pool).await?;
+        Ok(true)
+    }
+
+    // Revoke every session of the current user.
+    #[graphql(guard = "LoginGuard")]
+    async fn logout_all_sessions(
+        &self,
+        ctx: &async_graphql::Context<'_>,
+    ) -> Result<bool, ApiError> {
+        let pool = ctx.data::<PgPool>().expect("Failed to get pool.");
+        let user = current_user(ctx)?;
+        SessionModel::revoke_all(user.id, &audit::context(ctx), pool).await?;
+        Ok(true)
+    }
+}
+
+// Send a verification token for a change that is already saved. Failures are only
+// logged, since retrying the change would fail, and `sendVerificationEmail` sends a new
+// token.
+async fn send_verification_token(ctx: &async_graphql::Context<'_>, user_id: i32, pool: &PgPool) {
+    match UserTokenModel::issue_email_verification(user_id, pool).await {
+        Ok(issued) => send_email(ctx, mailer::verification_email(&issued)),
+        Err(e) => log::error!("Failed to issue verification token: {:?}", e),
+    }
+}
+
+// Emails are sent in the background, so slow delivery does not delay the response or
+// reveal whether an address is registered. Failures are only logged.
+fn send_email(ctx: &async_graphql::Context<'_>, email: Email) {
+    let mailer = ctx
+        .data::<Arc<dyn Mailer>>()
+        .expect("Failed to get mailer.")
+        .clone();
+    tokio::spawn(async move {
+        if let Err(e) = mailer.send(email).await {
+            log::error!("Failed to send email: {:?}", e);
+        }
+    });
 }
@@ -0,0 +1,67 @@
+use crate::models::{
+    pagination::{Cursor, Page},
+    search::SearchCursor,
+};
+use anyhow::Error;
+use async_graphql::{
+    connection::{Connection, CursorType, Edge},
+    OutputType,
+};
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use chrono::{TimeZone, Utc};
+
+// Cursors are opaque to clients: base64 of "<time in microseconds>:<id>".
+impl CursorType for Cursor {
+    type Error = Error;
+
+    fn decode_cursor(s: &str) -> Result<Self, Self::Error> {
+        decode(s).ok_or_else(|| Error::msg("Invalid cursor."))
+    }
+
+    fn encode_cursor(&self) -> String {
+        URL_SAFE_NO_PAD.encode(format!("{}:{}", self.time.timestamp_micros(), self.id))
+    }
+}
+
+fn decode(s: &str) -> Option<Cursor> {
+    let decoded = String::from_utf8(URL_SAFE_NO_PAD.decode(s).ok()?).ok()?;
+    let (time, id) = decoded.split_once(':')?;
+    let time = Utc.timestamp_micros(time.parse().ok()?).single()?;
+    Some(Cursor::new(time, id.parse().ok()?))
+}
+
+// Search cursors are base64 of "<rank>:<id>".
+impl CursorType for SearchCursor {
+    type Error = Error;
+
+    fn decode_cursor(s: &str) -> Result<Self, Self::Error> {
+        decode_search(s).ok_or_else(|| Error::msg("Invalid cursor."))
+    }
+
+    fn encode_cursor(&self) -> String {
+        URL_SAFE_NO_PAD.encode(format!("{}:{}", self.rank, self.id))
+    }
+}
+
+fn decode_search(s: &str) -> Option<SearchCursor> {
+    let decoded = String::from_utf8(URL_SAFE_NO_PAD.decode(s).ok()?).ok()?;
+    let (rank, id) = decoded.split_once(':')?;
+    Some(SearchCursor {
+        rank: rank.parse().ok()?,
+        id: id.parse().ok()?,
+    })
+}
+
+// Convert a page of rows into a Relay connection.
+pub fn into_connection<C: CursorType + Send + Sync, T: OutputType>(
+    page: Page<T>,
+    cursor: impl Fn(&T) -> C,
+) -> Connection<C, T> {
+    let mut connection = Connection::new(page.has_previous_page, page.has_next_page);
+    connection.edges.extend(
+        page.rows
+            .into_iter()
+            .map(|row| Edge::new(cursor(&row), row)),
+    );
+    connection
+}
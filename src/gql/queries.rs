@@ -1,9 +1,39 @@
-use crate::models::{
-    message::{MessageModel, MessageModelResponse},
-    users::{UsersModel, UsersModelResponse},
+use crate::{
+    gql::{
+        auth::{current_user, LoginGuard, RequireRole},
+        error::{with_validation_code, ApiError},
+        limits::{bounded_list_complexity, list_complexity, page_complexity, REACTION_LIST_SIZE},
+        loaders::{
+            MessageLoader, ReactionsKey, ReactionsLoader, RepliesKey, RepliesLoader,
+            ReplyCountLoader, ThreadRootLoader, UserLoader, VisibleMessageKey,
+            VisibleMessageLoader,
+        },
+        pagination::into_connection,
+    },
+    models::{
+        audit::{AuditEventModel, AuditFilter},
+        channel::ChannelModel,
+        conversation::{ConversationModel, MAX_PARTICIPANTS},
+        entity::{TagModel, TrendingWindow, MAX_TRENDING_TAGS},
+        error::ModelError,
+        message::{MessageModel, MessageModelResponse},
+        notification::{NotificationKind, NotificationModel},
+        pagination::{Cursor, PageArgs},
+        reaction::ReactionModel,
+        report::{ReportAction, ReportModel},
+        revision::MessageRevisionModel,
+        search::{MessageSearchResult, SearchCursor, SearchLanguage, SearchModel},
+        session::TokenPair,
+        token::MfaChallenge,
+        totp::TotpEnrollment,
+        users::{LoginOutcome, Role, UsersModelResponse},
+    },
+};
+use async_graphql::{
+    connection::{query, Connection},
+    dataloader::DataLoader,
+    Context, Enum, InputObject, Json, Object, Union,
 };
-use anyhow::{Error, Result};
-use async_graphql::{Context, Object};
 use chrono::{DateTime, Utc};
 use sqlx::postgres::PgPool;
 
@@ -11,40 +41,387 @@ pub struct QueryRoot;
 
 #[Object]
 impl QueryRoot {
+    #[graphql(guard = "LoginGuard", complexity = "list_complexity(child_complexity)")]
     async fn find_by_user_id_and_time_range(
         &self,
         ctx: &Context<'_>,
         user_id: i32,
         start_time: Option<DateTime<Utc>>,
         end_time: Option<DateTime<Utc>>,
-    ) -> Result<Vec<MessageModelResponse>, Error> {
+    ) -> Result<Vec<MessageModelResponse>, ApiError> {
         let pool = ctx.data::<PgPool>().expect("Failed to get pool.");
-        let rows =
-            MessageModel::find_by_user_id_and_time_range(user_id, start_time, end_time, pool)
-                .await?;
+        let viewer = current_user(ctx)?;
+        let rows = MessageModel::find_by_user_id_and_time_range(
+            user_id, start_time, end_time, viewer.id, pool,
+        )
+        .await?;
         Ok(rows)
     }
 
+    #[graphql(guard = "LoginGuard", complexity = "list_complexity(child_complexity)")]
     async fn find_messages_by_id(
         &self,
         ctx: &Context<'_>,
         id: i32,
-    ) -> Result<Vec<MessageModelResponse>, Error> {
+    ) -> Result<Vec<MessageModelResponse>, ApiError> {
+        let pool = ctx.data::<PgPool>().expect("Failed to get pool.");
+        let viewer = current_user(ctx)?;
+        let rows = MessageModel::find_messages_by_id(id, viewer.id, pool).await?;
+        Ok(rows)
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    #[graphql(
+        guard = "LoginGuard",
+        complexity = "page_complexity(first, last, child_complexity)"
+    )]
+    async fn user_messages(
+        &self,
+        ctx: &Context<'_>,
+        user_id: i32,
+        start_time: Option<DateTime<Utc>>,
+        end_time: Option<DateTime<Utc>>,
+        after: Option<String>,
+        before: Option<String>,
+        first: Option<i32>,
+        last: Option<i32>,
+    ) -> async_graphql::Result<Connection<Cursor, MessageModelResponse>> {
+        let pool = ctx.data::<PgPool>().expect("Failed to get pool.");
+        let viewer = current_user(ctx)?;
+        query(
+            after,
+            before,
+            first,
+            last,
+            |after, before, first, last| async move {
+                let args = PageArgs::new(after, before, first, last)?;
+                let page = MessageModel::find_page_by_user_id_and_time_range(
+                    user_id, start_time, end_time, args, viewer.id, pool,
+                )
+                .await?;
+                Ok::<_, ApiError>(into_connection(page, message_cursor))
+            },
+        )
+        .await
+        .map_err(with_validation_code)
+    }
+
+    #[graphql(
+        guard = "LoginGuard",
+        complexity = "page_complexity(first, last, child_complexity)"
+    )]
+    async fn thread_messages(
+        &self,
+        ctx: &Context<'_>,
+        id: i32,
+        after: Option<String>,
+        before: Option<String>,
+        first: Option<i32>,
+        last: Option<i32>,
+    ) -> async_graphql::Result<Connection<Cursor, MessageModelResponse>> {
+        let pool = ctx.data::<PgPool>().expect("Failed to get pool.");
+        let viewer = current_user(ctx)?;
+        query(
+            after,
+            before,
+            first,
+            last,
+            |after, before, first, last| async move {
+                let args = PageArgs::new(after, before, first, last)?;
+                let page = MessageModel::find_message_page_by_id(id, args, viewer.id, pool).await?;
+                Ok::<_, ApiError>(into_connection(page, message_cursor))
+            },
+        )
+        .await
+        .map_err(with_validation_code)
+    }
+
+    #[graphql(
+        guard = "LoginGuard",
+        complexity = "page_complexity(first, last, child_complexity)"
+    )]
+    async fn messages_mentioning(
+        &self,
+        ctx: &Context<'_>,
+        user_id: i32,
+        after: Option<String>,
+        before: Option<String>,
+        first: Option<i32>,
+        last: Option<i32>,
+    ) -> async_graphql::Result<Connection<Cursor, MessageModelResponse>> {
+        let pool = ctx.data::<PgPool>().expect("Failed to get pool.");
+        let viewer = current_user(ctx)?;
+        query(
+            after,
+            before,
+            first,
+            last,
+            |after, before, first, last| async move {
+                let args = PageArgs::new(after, before, first, last)?;
+                let page =
+                    MessageModel::find_page_by_mentioned_user_id(user_id, args, viewer.id, pool)
+                        .await?;
+                Ok::<_, ApiError>(into_connection(page, message_cursor))
+            },
+        )
+        .await
+        .map_err(with_validation_code)
+    }
+
+    #[graphql(
+        guard = "LoginGuard",
+        complexity = "page_complexity(first, last, child_complexity)"
+    )]
+    async fn messages_by_tag(
+        &self,
+        ctx: &Context<'_>,
+        tag: String,
+        after: Option<String>,
+        before: Option<String>,
+        first: Option<i32>,
+        last: Option<i32>,
+    ) -> async_graphql::Result<Connection<Cursor, MessageModelResponse>> {
+        let pool = ctx.data::<PgPool>().expect("Failed to get pool.");
+        let viewer = current_user(ctx)?;
+        // Accept the tag with or without its leading #.
+        let tag = tag.trim_start_matches('#').to_string();
+        query(
+            after,
+            before,
+            first,
+            last,
+            |after, before, first, last| async move {
+                let args = PageArgs::new(after, before, first, last)?;
+                let page = MessageModel::find_page_by_tag(&tag, args, viewer.id, pool).await?;
+                Ok::<_, ApiError>(into_connection(page, message_cursor))
+            },
+        )
+        .await
+        .map_err(with_validation_code)
+    }
+
+    // Most used tags in the viewer's channels.
+    #[graphql(
+        guard = "LoginGuard",
+        complexity = "bounded_list_complexity(MAX_TRENDING_TAGS as usize, child_complexity)"
+    )]
+    async fn trending_tags(
+        &self,
+        ctx: &Context<'_>,
+        #[graphql(default_with = "Window::Day")] window: Window,
+    ) -> Result<Vec<TagModel>, ApiError> {
+        let pool = ctx.data::<PgPool>().expect("Failed to get pool.");
+        let viewer = current_user(ctx)?;
+        let rows = TagModel::find_trending(window.into(), viewer.id, pool).await?;
+        Ok(rows)
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    #[graphql(
+        guard = "LoginGuard",
+        complexity = "page_complexity(first, None, child_complexity)"
+    )]
+    async fn search_messages(
+        &self,
+        ctx: &Context<'_>,
+        #[graphql(name = "query")] text: String,
+        user_id: Option<i32>,
+        from: Option<DateTime<Utc>>,
+        to: Option<DateTime<Utc>>,
+        first: Option<i32>,
+        after: Option<String>,
+        #[graphql(default_with = "Language::English")] language: Language,
+    ) -> async_graphql::Result<Connection<SearchCursor, MessageSearchResult>> {
+        let pool = ctx.data::<PgPool>().expect("Failed to get pool.");
+        let viewer = current_user(ctx)?;
+        query(
+            after,
+            None,
+            first,
+            None,
+            |after, _: Option<SearchCursor>, first, _| async move {
+                let page = SearchModel::search_messages(
+                    text,
+                    language.into(),
+                    user_id,
+                    from,
+                    to,
+                    after,
+                    first,
+                    viewer.id,
+                    pool,
+                )
+                .await?;
+                Ok::<_, ApiError>(into_connection(page, |row: &MessageSearchResult| {
+                    SearchCursor {
+                        rank: row.rank,
+                        id: row.message.id,
+                    }
+                }))
+            },
+        )
+        .await
+        .map_err(with_validation_code)
+    }
+
+    #[graphql(guard = "LoginGuard", complexity = "list_complexity(child_complexity)")]
+    async fn message_revisions(
+        &self,
+        ctx: &Context<'_>,
+        id: i32,
+    ) -> Result<Vec<MessageRevisionModel>, ApiError> {
+        let pool = ctx.data::<PgPool>().expect("Failed to get pool.");
+        let viewer = current_user(ctx)?;
+        let rows = MessageRevisionModel::find_by_message_id(id, viewer.id, pool).await?;
+        Ok(rows)
+    }
+
+    // The current user, with their email.
+    #[graphql(guard = "LoginGuard")]
+    async fn me(&self, ctx: &Context<'_>) -> Result<UsersModelResponse, ApiError> {
+        let loader = ctx.data_unchecked::<DataLoader<UserLoader>>();
+        let user = current_user(ctx)?;
+        let row = loader.load_one(user.id).await?;
+        row.ok_or_else(|| ModelError::not_found("User not found.").into())
+    }
+
+    #[graphql(complexity = "list_complexity(child_complexity)")]
+    async fn channels(&self, ctx: &Context<'_>) -> Result<Vec<ChannelModel>, ApiError> {
+        let pool = ctx.data::<PgPool>().expect("Failed to get pool.");
+        let rows = ChannelModel::find_all(pool).await?;
+        Ok(rows)
+    }
+
+    // Channels the current user belongs to.
+    #[graphql(guard = "LoginGuard", complexity = "list_complexity(child_complexity)")]
+    async fn my_channels(&self, ctx: &Context<'_>) -> Result<Vec<ChannelModel>, ApiError> {
+        let pool = ctx.data::<PgPool>().expect("Failed to get pool.");
+        let user = current_user(ctx)?;
+        let rows = ChannelModel::find_by_user_id(user.id, pool).await?;
+        Ok(rows)
+    }
+
+    #[graphql(
+        guard = "LoginGuard",
+        complexity = "page_complexity(first, last, child_complexity)"
+    )]
+    async fn channel_messages(
+        &self,
+        ctx: &Context<'_>,
+        channel_id: i32,
+        after: Option<String>,
+        before: Option<String>,
+        first: Option<i32>,
+        last: Option<i32>,
+    ) -> async_graphql::Result<Connection<Cursor, MessageModelResponse>> {
         let pool = ctx.data::<PgPool>().expect("Failed to get pool.");
-        let rows = MessageModel::find_messages_by_id(id, pool).await?;
+        let viewer = current_user(ctx)?;
+        query(
+            after,
+            before,
+            first,
+            last,
+            |after, before, first, last| async move {
+                let args = PageArgs::new(after, before, first, last)?;
+                let page = MessageModel::find_page_by_channel_id(channel_id, args, viewer.id, pool)
+                    .await?;
+                Ok::<_, ApiError>(into_connection(page, message_cursor))
+            },
+        )
+        .await
+        .map_err(with_validation_code)
+    }
+
+    // Direct conversations of the current user, most recently active first.
+    #[graphql(guard = "LoginGuard", complexity = "list_complexity(child_complexity)")]
+    async fn conversations(&self, ctx: &Context<'_>) -> Result<Vec<ConversationModel>, ApiError> {
+        let pool = ctx.data::<PgPool>().expect("Failed to get pool.");
+        let user = current_user(ctx)?;
+        let rows = ConversationModel::find_by_user_id(user.id, pool).await?;
         Ok(rows)
     }
 
-    async fn login(
+    // Replies to and mentions of the current user, newest first.
+    #[graphql(
+        guard = "LoginGuard",
+        complexity = "page_complexity(first, None, child_complexity)"
+    )]
+    async fn notifications(
         &self,
         ctx: &Context<'_>,
-        email: String,
-        password: String,
-    ) -> Result<String, Error> {
+        first: Option<i32>,
+        after: Option<String>,
+        #[graphql(default)] unread_only: bool,
+    ) -> async_graphql::Result<Connection<Cursor, NotificationModel>> {
         let pool = ctx.data::<PgPool>().expect("Failed to get pool.");
-        let token = UsersModel::login(email, password, pool).await?;
-        Ok(token)
+        let user = current_user(ctx)?;
+        query(after, None, first, None, |after, _, first, _| async move {
+            let args = PageArgs::new(after, None, first, None)?;
+            let page =
+                NotificationModel::find_page_by_user_id(user.id, unread_only, args, pool).await?;
+            Ok::<_, ApiError>(into_connection(page, |row: &NotificationModel| {
+                Cursor::new(row.created_at, row.id)
+            }))
+        })
+        .await
+        .map_err(with_validation_code)
     }
+
+    #[graphql(guard = "LoginGuard")]
+    async fn unread_notification_count(&self, ctx: &Context<'_>) -> Result<i64, ApiError> {
+        let pool = ctx.data::<PgPool>().expect("Failed to get pool.");
+        let user = current_user(ctx)?;
+        let count = NotificationModel::count_unread(user.id, pool).await?;
+        Ok(count)
+    }
+
+    // Open reports, oldest first.
+    #[graphql(
+        guard = "RequireRole(Role::Moderator)",
+        complexity = "list_complexity(child_complexity)"
+    )]
+    async fn moderation_queue(&self, ctx: &Context<'_>) -> Result<Vec<ReportModel>, ApiError> {
+        let pool = ctx.data::<PgPool>().expect("Failed to get pool.");
+        let rows = ReportModel::find_open(pool).await?;
+        Ok(rows)
+    }
+
+    // Recorded changes and logins, newest first.
+    #[graphql(
+        guard = "RequireRole(Role::Admin)",
+        complexity = "page_complexity(first, None, child_complexity)"
+    )]
+    async fn audit_log(
+        &self,
+        ctx: &Context<'_>,
+        filter: Option<AuditLogFilter>,
+        first: Option<i32>,
+        after: Option<String>,
+    ) -> async_graphql::Result<Connection<Cursor, AuditEventModel>> {
+        let pool = ctx.data::<PgPool>().expect("Failed to get pool.");
+        let filter = filter.map(Into::into).unwrap_or_default();
+        query(after, None, first, None, |after, _, first, _| async move {
+            let args = PageArgs::new(after, None, first, None)?;
+            let page = AuditEventModel::find_page(filter, args, pool).await?;
+            Ok::<_, ApiError>(into_connection(page, |row: &AuditEventModel| {
+                Cursor::new(row.created_at, row.id)
+            }))
+        })
+        .await
+        .map_err(with_validation_code)
+    }
+}
+
+fn message_cursor(row: &MessageModelResponse) -> Cursor {
+    Cursor::new(row.message_time, row.id)
+}
+
+fn is_moderator(ctx: &Context<'_>) -> bool {
+    current_user(ctx).is_ok_and(|user| user.role >= Role::Moderator)
+}
+
+fn is_self_or_admin(ctx: &Context<'_>, user_id: i32) -> bool {
+    current_user(ctx).is_ok_and(|user| user.id == user_id || user.role >= Role::Admin)
 }
 
 #[Object]
@@ -53,12 +430,38 @@ impl MessageModelResponse {
         self.id
     }
 
-    async fn user_id(&self) -> i32 {
+    async fn user_id(&self) -> Option<i32> {
         self.user_id
     }
 
-    async fn message(&self) -> String {
-        self.message.clone()
+    async fn channel_id(&self) -> i32 {
+        self.channel_id
+    }
+
+    // Deleted messages are rendered as tombstones without a body.
+    // Hidden messages only have a body for moderators.
+    async fn message(&self, ctx: &Context<'_>) -> Option<String> {
+        if self.deleted_at.is_some() || (self.hidden_at.is_some() && !is_moderator(ctx)) {
+            None
+        } else {
+            Some(self.message.clone())
+        }
+    }
+
+    async fn is_deleted(&self) -> bool {
+        self.deleted_at.is_some()
+    }
+
+    async fn is_hidden(&self) -> bool {
+        self.hidden_at.is_some()
+    }
+
+    async fn edited(&self) -> bool {
+        self.edited_at.is_some()
+    }
+
+    async fn edited_at(&self) -> Option<DateTime<Utc>> {
+        self.edited_at
     }
 
     async fn parent_id(&self) -> Option<i32> {
@@ -68,6 +471,401 @@ impl MessageModelResponse {
     async fn message_time(&self) -> DateTime<Utc> {
         self.message_time
     }
+
+    // None once the author's account is deleted.
+    async fn author(&self, ctx: &Context<'_>) -> Result<Option<UsersModelResponse>, ApiError> {
+        let user_id = match self.user_id {
+            Some(user_id) => user_id,
+            None => return Ok(None),
+        };
+        let loader = ctx.data_unchecked::<DataLoader<UserLoader>>();
+        Ok(loader.load_one(user_id).await?)
+    }
+
+    async fn parent(&self, ctx: &Context<'_>) -> Result<Option<MessageModelResponse>, ApiError> {
+        let parent_id = match self.parent_id {
+            Some(parent_id) => parent_id,
+            None => return Ok(None),
+        };
+        let loader = ctx.data_unchecked::<DataLoader<MessageLoader>>();
+        Ok(loader.load_one(parent_id).await?)
+    }
+
+    #[graphql(complexity = "page_complexity(first, None, child_complexity)")]
+    async fn replies(
+        &self,
+        ctx: &Context<'_>,
+        first: Option<i32>,
+        after: Option<String>,
+    ) -> async_graphql::Result<Connection<Cursor, MessageModelResponse>> {
+        let loader = ctx.data_unchecked::<DataLoader<RepliesLoader>>();
+        query(after, None, first, None, |after, _, first, _| async move {
+            let key = RepliesKey {
+                parent_id: self.id,
+                after,
+                first,
+            };
+            let page = loader
+                .load_one(key)
+                .await?
+                .ok_or_else(|| ModelError::not_found("Message not found."))?;
+            Ok::<_, ApiError>(into_connection(page, message_cursor))
+        })
+        .await
+        .map_err(with_validation_code)
+    }
+
+    // Reactions grouped by emoji. Deleted and hidden messages have none.
+    #[graphql(complexity = "bounded_list_complexity(REACTION_LIST_SIZE, child_complexity)")]
+    async fn reactions(&self, ctx: &Context<'_>) -> Result<Vec<ReactionModel>, ApiError> {
+        if self.deleted_at.is_some() || self.hidden_at.is_some() {
+            return Ok(vec![]);
+        }
+        let key = ReactionsKey {
+            message_id: self.id,
+            viewer_id: current_user(ctx)?.id,
+        };
+        let loader = ctx.data_unchecked::<DataLoader<ReactionsLoader>>();
+        Ok(loader.load_one(key).await?.unwrap_or_default())
+    }
+
+    async fn reply_count(&self, ctx: &Context<'_>) -> Result<i64, ApiError> {
+        let loader = ctx.data_unchecked::<DataLoader<ReplyCountLoader>>();
+        Ok(loader.load_one(self.id).await?.unwrap_or(0))
+    }
+
+    async fn thread_root(&self, ctx: &Context<'_>) -> Result<MessageModelResponse, ApiError> {
+        let root_id = ctx
+            .data_unchecked::<DataLoader<ThreadRootLoader>>()
+            .load_one(self.id)
+            .await?
+            .unwrap_or(self.id);
+        if root_id == self.id {
+            return Ok(self.clone());
+        }
+        let root = ctx
+            .data_unchecked::<DataLoader<MessageLoader>>()
+            .load_one(root_id)
+            .await?;
+        root.ok_or_else(|| ModelError::not_found("Message not found.").into())
+    }
+}
+
+#[derive(Enum, Copy, Clone, Eq, PartialEq)]
+#[graphql(name = "SearchLanguage", remote = "SearchLanguage")]
+enum Language {
+    English,
+    Japanese,
+}
+
+#[derive(Enum, Copy, Clone, Eq, PartialEq)]
+#[graphql(name = "TrendingWindow", remote = "TrendingWindow")]
+enum Window {
+    Hour,
+    Day,
+    Week,
+}
+
+#[Object]
+impl TagModel {
+    async fn tag(&self) -> String {
+        self.tag.clone()
+    }
+
+    async fn count(&self) -> i64 {
+        self.count
+    }
+}
+
+#[Object]
+impl MessageSearchResult {
+    async fn message(&self) -> MessageModelResponse {
+        self.message.clone()
+    }
+
+    async fn rank(&self) -> f32 {
+        self.rank
+    }
+
+    async fn headline(&self) -> String {
+        self.headline.clone()
+    }
+}
+
+#[Object]
+impl MessageRevisionModel {
+    async fn id(&self) -> i32 {
+        self.id
+    }
+
+    async fn message_id(&self) -> i32 {
+        self.message_id
+    }
+
+    async fn message(&self) -> String {
+        self.message.clone()
+    }
+
+    async fn editor_id(&self) -> Option<i32> {
+        self.editor_id
+    }
+
+    async fn editor(&self, ctx: &Context<'_>) -> Result<Option<UsersModelResponse>, ApiError> {
+        let editor_id = match self.editor_id {
+            Some(editor_id) => editor_id,
+            None => return Ok(None),
+        };
+        let loader = ctx.data_unchecked::<DataLoader<UserLoader>>();
+        Ok(loader.load_one(editor_id).await?)
+    }
+
+    async fn edited_at(&self) -> DateTime<Utc> {
+        self.edited_at
+    }
+}
+
+#[Object]
+impl ReactionModel {
+    async fn emoji(&self) -> String {
+        self.emoji.clone()
+    }
+
+    async fn count(&self) -> i64 {
+        self.count
+    }
+
+    async fn reacted(&self) -> bool {
+        self.reacted
+    }
+}
+
+#[derive(Enum, Copy, Clone, Eq, PartialEq)]
+#[graphql(remote = "NotificationKind")]
+enum Kind {
+    Reply,
+    Mention,
+}
+
+#[Object]
+impl NotificationModel {
+    async fn id(&self) -> i32 {
+        self.id
+    }
+
+    async fn kind(&self) -> Kind {
+        self.kind.into()
+    }
+
+    async fn message_id(&self) -> i32 {
+        self.message_id
+    }
+
+    // Null once the user is no longer a member of the message's channel.
+    async fn message(&self, ctx: &Context<'_>) -> Result<Option<MessageModelResponse>, ApiError> {
+        let loader = ctx.data_unchecked::<DataLoader<VisibleMessageLoader>>();
+        let key = VisibleMessageKey {
+            message_id: self.message_id,
+            viewer_id: self.user_id,
+        };
+        Ok(loader.load_one(key).await?)
+    }
+
+    async fn actor(&self, ctx: &Context<'_>) -> Result<UsersModelResponse, ApiError> {
+        let loader = ctx.data_unchecked::<DataLoader<UserLoader>>();
+        let user = loader.load_one(self.actor_id).await?;
+        user.ok_or_else(|| ModelError::not_found("User not found.").into())
+    }
+
+    async fn read(&self) -> bool {
+        self.read_at.is_some()
+    }
+
+    async fn created_at(&self) -> DateTime<Utc> {
+        self.created_at
+    }
+
+    async fn read_at(&self) -> Option<DateTime<Utc>> {
+        self.read_at
+    }
+}
+
+#[derive(Enum, Copy, Clone, Eq, PartialEq)]
+#[graphql(remote = "ReportAction")]
+pub(super) enum Action {
+    Dismiss,
+    HideMessage,
+    SuspendAuthor,
+}
+
+#[Object]
+impl ReportModel {
+    async fn id(&self) -> i32 {
+        self.id
+    }
+
+    async fn message_id(&self) -> i32 {
+        self.message_id
+    }
+
+    async fn message(&self, ctx: &Context<'_>) -> Result<MessageModelResponse, ApiError> {
+        let loader = ctx.data_unchecked::<DataLoader<MessageLoader>>();
+        let message = loader.load_one(self.message_id).await?;
+        message.ok_or_else(|| ModelError::not_found("Message not found.").into())
+    }
+
+    // The whole thread of the reported message, oldest first.
+    #[graphql(
+        guard = "RequireRole(Role::Moderator)",
+        complexity = "list_complexity(child_complexity)"
+    )]
+    async fn thread(&self, ctx: &Context<'_>) -> Result<Vec<MessageModelResponse>, ApiError> {
+        let pool = ctx.data::<PgPool>().expect("Failed to get pool.");
+        let rows = ReportModel::find_thread(self.id, pool).await?;
+        Ok(rows)
+    }
+
+    async fn reporter(&self, ctx: &Context<'_>) -> Result<UsersModelResponse, ApiError> {
+        let loader = ctx.data_unchecked::<DataLoader<UserLoader>>();
+        let user = loader.load_one(self.reporter_id).await?;
+        user.ok_or_else(|| ModelError::not_found("User not found.").into())
+    }
+
+    async fn reason(&self) -> String {
+        self.reason.clone()
+    }
+
+    async fn created_at(&self) -> DateTime<Utc> {
+        self.created_at
+    }
+
+    async fn resolved_at(&self) -> Option<DateTime<Utc>> {
+        self.resolved_at
+    }
+
+    async fn resolved_by(&self) -> Option<i32> {
+        self.resolved_by
+    }
+
+    async fn action(&self) -> Option<Action> {
+        self.action.map(Into::into)
+    }
+}
+
+#[derive(InputObject)]
+struct AuditLogFilter {
+    actor_id: Option<i32>,
+    // The mutation name, or `login`.
+    action: Option<String>,
+    target_id: Option<i32>,
+    since: Option<DateTime<Utc>>,
+    until: Option<DateTime<Utc>>,
+}
+
+impl From<AuditLogFilter> for AuditFilter {
+    fn from(filter: AuditLogFilter) -> Self {
+        AuditFilter {
+            actor_id: filter.actor_id,
+            action: filter.action,
+            target_id: filter.target_id,
+            since: filter.since,
+            until: filter.until,
+        }
+    }
+}
+
+#[Object]
+impl AuditEventModel {
+    async fn id(&self) -> i32 {
+        self.id
+    }
+
+    async fn actor_id(&self) -> Option<i32> {
+        self.actor_id
+    }
+
+    async fn actor(&self, ctx: &Context<'_>) -> Result<Option<UsersModelResponse>, ApiError> {
+        let actor_id = match self.actor_id {
+            Some(actor_id) => actor_id,
+            None => return Ok(None),
+        };
+        let loader = ctx.data_unchecked::<DataLoader<UserLoader>>();
+        Ok(loader.load_one(actor_id).await?)
+    }
+
+    async fn action(&self) -> String {
+        self.action.clone()
+    }
+
+    async fn target_id(&self) -> Option<i32> {
+        self.target_id
+    }
+
+    async fn before(&self) -> Option<Json<serde_json::Value>> {
+        self.before.clone().map(Json)
+    }
+
+    async fn after(&self) -> Option<Json<serde_json::Value>> {
+        self.after.clone().map(Json)
+    }
+
+    async fn ip(&self) -> Option<String> {
+        self.ip.clone()
+    }
+
+    async fn user_agent(&self) -> Option<String> {
+        self.user_agent.clone()
+    }
+
+    async fn created_at(&self) -> DateTime<Utc> {
+        self.created_at
+    }
+}
+
+#[Object]
+impl ChannelModel {
+    async fn id(&self) -> i32 {
+        self.id
+    }
+
+    async fn name(&self) -> String {
+        self.name.clone()
+    }
+
+    async fn created_by(&self) -> Option<i32> {
+        self.created_by
+    }
+
+    async fn created_at(&self) -> DateTime<Utc> {
+        self.created_at
+    }
+}
+
+#[Object]
+impl ConversationModel {
+    async fn id(&self) -> i32 {
+        self.id
+    }
+
+    #[graphql(complexity = "bounded_list_complexity(MAX_PARTICIPANTS, child_complexity)")]
+    async fn participants(&self, ctx: &Context<'_>) -> Result<Vec<UsersModelResponse>, ApiError> {
+        let loader = ctx.data_unchecked::<DataLoader<UserLoader>>();
+        let mut users = loader
+            .load_many(self.participant_ids.iter().copied())
+            .await?;
+        Ok(self
+            .participant_ids
+            .iter()
+            .filter_map(|id| users.remove(id))
+            .collect())
+    }
+
+    async fn last_message_at(&self) -> DateTime<Utc> {
+        self.last_message_at
+    }
+
+    async fn unread_count(&self) -> i64 {
+        self.unread_count
+    }
 }
 
 #[Object]
@@ -80,7 +878,60 @@ impl UsersModelResponse {
         self.name.clone()
     }
 
-    async fn email(&self) -> String {
-        self.email.clone()
+    // Only the user and admins see the email.
+    async fn email(&self, ctx: &Context<'_>) -> Option<String> {
+        is_self_or_admin(ctx, self.id).then(|| self.email.clone())
+    }
+
+    async fn email_verified(&self, ctx: &Context<'_>) -> Option<bool> {
+        is_self_or_admin(ctx, self.id).then_some(self.email_verified_at.is_some())
+    }
+}
+
+#[Object]
+impl TokenPair {
+    async fn access_token(&self) -> String {
+        self.access_token.clone()
+    }
+
+    async fn refresh_token(&self) -> String {
+        self.refresh_token.clone()
+    }
+}
+
+#[derive(Union)]
+pub(super) enum LoginResult {
+    Tokens(TokenPair),
+    MfaChallenge(MfaChallenge),
+}
+
+impl From<LoginOutcome> for LoginResult {
+    fn from(outcome: LoginOutcome) -> Self {
+        match outcome {
+            LoginOutcome::Tokens(tokens) => LoginResult::Tokens(tokens),
+            LoginOutcome::MfaRequired(challenge) => LoginResult::MfaChallenge(challenge),
+        }
+    }
+}
+
+#[Object]
+impl MfaChallenge {
+    async fn challenge(&self) -> String {
+        self.challenge.clone()
+    }
+
+    async fn expires_at(&self) -> DateTime<Utc> {
+        self.expires_at
+    }
+}
+
+#[Object]
+impl TotpEnrollment {
+    async fn secret(&self) -> String {
+        self.secret.clone()
+    }
+
+    async fn uri(&self) -> String {
+        self.uri.clone()
     }
 }
@@ -0,0 +1,147 @@
+use crate::{
+    gql::{
+        auth::{current_user, LoginGuard},
+        error::ApiError,
+    },
+    models::{
+        channel::ChannelModel,
+        events::{self, MessageEvent, MessageEventKind},
+        message::MessageModelResponse,
+        notification::NotificationModel,
+    },
+};
+use async_graphql::{Context, Subscription};
+use futures_util::{Stream, StreamExt};
+use sqlx::PgPool;
+use std::sync::Arc;
+use tokio_stream::wrappers::BroadcastStream;
+
+pub struct SubscriptionRoot;
+
+#[Subscription]
+impl SubscriptionRoot {
+    #[graphql(guard = "LoginGuard")]
+    async fn message_created(
+        &self,
+        ctx: &Context<'_>,
+        user_id: Option<i32>,
+        thread_root_id: Option<i32>,
+        channel_id: Option<i32>,
+    ) -> async_graphql::Result<impl Stream<Item = MessageModelResponse>> {
+        let filter = EventFilter::new(ctx, user_id, thread_root_id, channel_id)?;
+        Ok(message_events(MessageEventKind::Created, filter).map(|e| e.message))
+    }
+
+    #[graphql(guard = "LoginGuard")]
+    async fn message_updated(
+        &self,
+        ctx: &Context<'_>,
+        user_id: Option<i32>,
+        thread_root_id: Option<i32>,
+        channel_id: Option<i32>,
+    ) -> async_graphql::Result<impl Stream<Item = MessageModelResponse>> {
+        let filter = EventFilter::new(ctx, user_id, thread_root_id, channel_id)?;
+        Ok(message_events(MessageEventKind::Updated, filter).map(|e| e.message))
+    }
+
+    #[graphql(guard = "LoginGuard")]
+    async fn message_deleted(
+        &self,
+        ctx: &Context<'_>,
+        user_id: Option<i32>,
+        thread_root_id: Option<i32>,
+        channel_id: Option<i32>,
+    ) -> async_graphql::Result<impl Stream<Item = i32>> {
+        let filter = EventFilter::new(ctx, user_id, thread_root_id, channel_id)?;
+        Ok(message_events(MessageEventKind::Deleted, filter).map(|e| e.message.id))
+    }
+
+    // Sent when a reaction is added to or removed from a message.
+    #[graphql(guard = "LoginGuard")]
+    async fn message_reactions_changed(
+        &self,
+        ctx: &Context<'_>,
+        user_id: Option<i32>,
+        thread_root_id: Option<i32>,
+        channel_id: Option<i32>,
+    ) -> async_graphql::Result<impl Stream<Item = MessageModelResponse>> {
+        let filter = EventFilter::new(ctx, user_id, thread_root_id, channel_id)?;
+        Ok(message_events(MessageEventKind::Reacted, filter).map(|e| e.message))
+    }
+
+    // New notifications of the current user.
+    #[graphql(guard = "LoginGuard")]
+    async fn notification_received(
+        &self,
+        ctx: &Context<'_>,
+    ) -> async_graphql::Result<impl Stream<Item = NotificationModel>> {
+        let user_id = current_user(ctx)?.id;
+        Ok(
+            BroadcastStream::new(events::subscribe_notifications()).filter_map(
+                move |notification| async move {
+                    notification
+                        .ok()
+                        .filter(|notification| notification.user_id == user_id)
+                },
+            ),
+        )
+    }
+}
+
+struct EventFilter {
+    user_id: Option<i32>,
+    thread_root_id: Option<i32>,
+    channel_id: Option<i32>,
+    viewer_id: i32,
+    pool: PgPool,
+}
+
+impl EventFilter {
+    fn new(
+        ctx: &Context<'_>,
+        user_id: Option<i32>,
+        thread_root_id: Option<i32>,
+        channel_id: Option<i32>,
+    ) -> Result<Self, ApiError> {
+        let pool = ctx.data::<PgPool>().expect("Failed to get pool.");
+        Ok(EventFilter {
+            user_id,
+            thread_root_id,
+            channel_id,
+            viewer_id: current_user(ctx)?.id,
+            pool: pool.clone(),
+        })
+    }
+
+    fn matches(&self, event: &MessageEvent) -> bool {
+        self.user_id
+            .is_none_or(|id| event.message.user_id == Some(id))
+            && self
+                .thread_root_id
+                .is_none_or(|id| event.thread_root_id == id)
+            && self
+                .channel_id
+                .is_none_or(|id| event.message.channel_id == id)
+    }
+}
+
+// Stream message events of the given kind, filtered by author, thread and channel.
+// Only events of channels the viewer belongs to are sent. Membership can change
+// while subscribed, so it is checked for every event.
+// Events missed by a lagging subscriber are skipped.
+fn message_events(kind: MessageEventKind, filter: EventFilter) -> impl Stream<Item = MessageEvent> {
+    let filter = Arc::new(filter);
+    BroadcastStream::new(events::subscribe()).filter_map(move |event| {
+        let filter = filter.clone();
+        async move {
+            let event = event
+                .ok()
+                .filter(|event| event.kind == kind && filter.matches(event))?;
+            let is_member =
+                ChannelModel::is_member(event.message.channel_id, filter.viewer_id, &filter.pool)
+                    .await
+                    .unwrap_or(false);
+            is_member.then_some(event)
+        }
+    })
+}
@@ -1,47 +1,209 @@
-use actix_web::{guard, web, App, HttpResponse, HttpServer, Result};
-use async_graphql::{http::GraphiQLSource, EmptySubscription, Schema};
-use async_graphql_actix_web::GraphQL;
+use actix_web::{
+    guard, http::header, web, App, Either, HttpRequest, HttpResponse, HttpServer, Result,
+};
+use async_graphql::{dataloader::DataLoader, http::GraphiQLSource, Data, Response, Schema};
+use async_graphql_actix_web::{GraphQLRequest, GraphQLResponse, GraphQLSubscription};
 use dotenvy::dotenv;
-use gql::{mutations::MutationRoot, queries::QueryRoot};
+use gql::{
+    audit::client_info,
+    auth::AuthUser,
+    error::{request_error, ErrorCodes},
+    loaders::{
+        MessageLoader, ReactionsLoader, RepliesLoader, ReplyCountLoader, ThreadRootLoader,
+        UserLoader, VisibleMessageLoader,
+    },
+    mutations::MutationRoot,
+    queries::QueryRoot,
+    subscriptions::SubscriptionRoot,
+};
+use models::{
+    audit::ClientInfo,
+    events,
+    jwt::{self, JwtKeys},
+    users::DeletionPolicy,
+};
+use oidc::{OidcClient, OidcConfig};
+use rate_limit::RateLimiter;
 use sqlx::PgPool;
-use std::env;
+use std::{env, str::FromStr};
 
 mod gql;
+mod mailer;
 mod models;
+mod oidc;
+mod rate_limit;
+
+type AppSchema = Schema<QueryRoot, MutationRoot, SubscriptionRoot>;
 
 #[actix_web::main]
 async fn main() -> std::io::Result<()> {
     env_logger::init();
     dotenv().expect(".env file not found");
 
+    jwt::init(JwtKeys::from_env().expect("Failed to load JWT keys."));
     let pool = PgPool::connect(&env::var("DATABASE_URL").unwrap_or("".to_string()))
         .await
         .expect("Failed to connect to Postgres.");
-    let schema = Schema::build(QueryRoot, MutationRoot, EmptySubscription)
-        .data(pool)
+    // Share message events between server instances through Postgres.
+    if env::var("MESSAGE_EVENTS").as_deref() == Ok("postgres") {
+        events::listen(&pool)
+            .await
+            .expect("Failed to listen for message events.");
+    }
+    let schema = Schema::build(QueryRoot, MutationRoot, SubscriptionRoot)
+        .data(pool.clone())
+        .data(deletion_policy())
+        .data(mailer::from_env())
+        .data(DataLoader::new(UserLoader(pool.clone()), tokio::spawn))
+        .data(DataLoader::new(MessageLoader(pool.clone()), tokio::spawn))
+        .data(DataLoader::new(
+            VisibleMessageLoader(pool.clone()),
+            tokio::spawn,
+        ))
+        .data(DataLoader::new(
+            ReplyCountLoader(pool.clone()),
+            tokio::spawn,
+        ))
+        .data(DataLoader::new(
+            ThreadRootLoader(pool.clone()),
+            tokio::spawn,
+        ))
+        .data(DataLoader::new(RepliesLoader(pool.clone()), tokio::spawn))
+        .data(DataLoader::new(ReactionsLoader(pool.clone()), tokio::spawn))
+        .limit_depth(env_number("MAX_QUERY_DEPTH", 15))
+        .limit_complexity(env_number("MAX_QUERY_COMPLEXITY", 5000))
+        .extension(ErrorCodes)
         .finish();
+    let rate_limiter = web::Data::new(RateLimiter::new(
+        env_number("RATE_LIMIT_BURST", 60),
+        env_number("RATE_LIMIT_PER_SECOND", 10.0),
+    ));
+    let oidc = OidcConfig::from_env().map(|config| web::Data::new(OidcClient::new(config)));
     let address = address();
 
     HttpServer::new(move || {
         App::new()
+            .app_data(web::Data::new(schema.clone()))
+            .app_data(web::Data::new(pool.clone()))
+            .app_data(rate_limiter.clone())
+            .service(web::resource("/").guard(guard::Post()).to(index))
             .service(
-                web::resource("/")
-                    .guard(guard::Post())
-                    .to(GraphQL::new(schema.clone())),
+                web::resource("/ws")
+                    .guard(guard::Get())
+                    .guard(guard::Header("upgrade", "websocket"))
+                    .to(index_ws),
             )
             .service(web::resource("/").guard(guard::Get()).to(index_graphiql))
+            .service(
+                web::resource("/.well-known/jwks.json")
+                    .guard(guard::Get())
+                    .to(jwks),
+            )
+            .configure(|cfg| {
+                if let Some(oidc) = &oidc {
+                    cfg.app_data(oidc.clone());
+                    oidc::routes(cfg);
+                }
+            })
     })
     .bind(address)?
     .run()
     .await
 }
 
+fn env_number<T: FromStr>(name: &str, default: T) -> T {
+    env::var(name)
+        .map(|value| {
+            value
+                .parse()
+                .unwrap_or_else(|_| panic!("Invalid {}.", name))
+        })
+        .unwrap_or(default)
+}
+
 fn address() -> String {
     std::env::var("ADDRESS").unwrap_or_else(|_| "127.0.0.1:8000".into())
 }
 
+// `anonymize` (the default) or `delete`.
+fn deletion_policy() -> DeletionPolicy {
+    std::env::var("ACCOUNT_DELETION_POLICY")
+        .map(|policy| policy.parse().expect("Invalid ACCOUNT_DELETION_POLICY."))
+        .unwrap_or_default()
+}
+
+async fn index(
+    schema: web::Data<AppSchema>,
+    pool: web::Data<PgPool>,
+    rate_limiter: web::Data<RateLimiter>,
+    req: HttpRequest,
+    gql_request: GraphQLRequest,
+) -> Either<GraphQLResponse, HttpResponse> {
+    let client = client_info(&req);
+    // The address is checked before the token, so throttled requests do not reach the
+    // database.
+    if let Err(retry_after) = rate_limiter.check(&rate_limit::ip_key(client.ip.as_deref())) {
+        return Either::Right(too_many_requests(retry_after));
+    }
+    let user = AuthUser::from_request(&req, &pool).await;
+    if let Some(user) = user {
+        if let Err(retry_after) = rate_limiter.check(&rate_limit::user_key(user.id)) {
+            return Either::Right(too_many_requests(retry_after));
+        }
+    }
+
+    let mut request = gql_request.into_inner().data(client);
+    if let Some(user) = user {
+        request = request.data(user);
+    }
+    Either::Left(schema.execute(request).await.into())
+}
+
+fn too_many_requests(retry_after: std::time::Duration) -> HttpResponse {
+    let response = Response::from_errors(vec![request_error("RATE_LIMITED", "Too many requests.")]);
+    HttpResponse::TooManyRequests()
+        .insert_header((header::RETRY_AFTER, retry_after.as_secs_f64().ceil() as u64))
+        .json(response)
+}
+
+async fn index_ws(
+    schema: web::Data<AppSchema>,
+    pool: web::Data<PgPool>,
+    req: HttpRequest,
+    payload: web::Payload,
+) -> Result<HttpResponse> {
+    let pool = PgPool::clone(&pool);
+    let client = client_info(&req);
+    GraphQLSubscription::new(AppSchema::clone(&*schema))
+        .on_connection_init(move |value| on_connection_init(value, client, pool))
+        .start(&req, payload)
+}
+
+async fn on_connection_init(
+    value: serde_json::Value,
+    client: ClientInfo,
+    pool: PgPool,
+) -> async_graphql::Result<Data> {
+    let mut data = Data::default();
+    data.insert(client);
+    if let Some(user) = AuthUser::from_connection_init(&value, &pool).await {
+        data.insert(user);
+    }
+    Ok(data)
+}
+
+// Public keys for other services to verify access tokens with.
+async fn jwks() -> HttpResponse {
+    HttpResponse::Ok().json(jwt::keys().jwks())
+}
+
 async fn index_graphiql() -> Result<HttpResponse> {
     Ok(HttpResponse::Ok()
         .content_type("text/html; charset=utf-8")
-        .body(GraphiQLSource::build().endpoint("/").finish()))
+        .body(
+            GraphiQLSource::build()
+                .endpoint("/")
+                .subscription_endpoint("/ws")
+                .finish(),
+        ))
 }
@@ -0,0 +1,246 @@
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use serde_json::Value;
+use sqlx::{query, query_as, PgConnection, PgExecutor, PgPool};
+
+use super::{
+    error::ModelError,
+    pagination::{Page, PageArgs},
+};
+
+// Where a request came from, recorded with its audit events.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ClientInfo {
+    pub ip: Option<String>,
+    pub user_agent: Option<String>,
+}
+
+// A change to record. `action` is the name of the mutation, e.g. `modifyMessage`.
+#[derive(Debug, Clone)]
+pub struct AuditEntry {
+    pub actor_id: Option<i32>,
+    pub action: String,
+    pub target_id: Option<i32>,
+    pub before: Option<Value>,
+    pub after: Option<Value>,
+}
+
+// Who is making a change and from where. Changes are recorded with it in the
+// transaction that makes them, so only saved changes are logged.
+#[derive(Debug, Clone, Default)]
+pub struct AuditContext {
+    pub actor_id: Option<i32>,
+    pub client: ClientInfo,
+}
+
+impl AuditContext {
+    pub(super) async fn record(
+        &self,
+        action: &str,
+        target_id: Option<i32>,
+        before: Option<Value>,
+        after: Option<Value>,
+        conn: &mut PgConnection,
+    ) -> Result<(), ModelError> {
+        let entry = AuditEntry {
+            actor_id: self.actor_id,
+            action: action.to_string(),
+            target_id,
+            before,
+            after,
+        };
+        AuditEventModel::record(entry, &self.client, conn).await
+    }
+}
+
+#[derive(Debug, Clone, serde::Deserialize, serde::Serialize)]
+pub struct AuditEventModel {
+    pub id: i32,
+    #[serde(rename = "actorId")]
+    pub actor_id: Option<i32>,
+    pub action: String,
+    #[serde(rename = "targetId")]
+    pub target_id: Option<i32>,
+    pub before: Option<Value>,
+    pub after: Option<Value>,
+    pub ip: Option<String>,
+    #[serde(rename = "userAgent")]
+    pub user_agent: Option<String>,
+    #[serde(rename = "createdAt")]
+    pub created_at: DateTime<Utc>,
+}
+
+// Conditions on the listed events. Unset fields match everything.
+#[derive(Debug, Clone, Default)]
+pub struct AuditFilter {
+    pub actor_id: Option<i32>,
+    pub action: Option<String>,
+    pub target_id: Option<i32>,
+    pub since: Option<DateTime<Utc>>,
+    pub until: Option<DateTime<Utc>>,
+}
+
+impl AuditEventModel {
+    pub async fn record(
+        entry: AuditEntry,
+        client: &ClientInfo,
+        executor: impl PgExecutor<'_>,
+    ) -> Result<(), ModelError> {
+        query!(
+            r#"
+            insert into audit_event (actor_id, action, target_id, before, after, ip, user_agent)
+            values ($1, $2, $3, $4, $5, $6, $7)
+            "#,
+            entry.actor_id,
+            entry.action,
+            entry.target_id,
+            entry.before,
+            entry.after,
+            client.ip,
+            client.user_agent
+        )
+        .execute(executor)
+        .await?;
+
+        Ok(())
+    }
+
+    // Find a page of events, newest first. Only forward paging is supported.
+    pub async fn find_page(
+        filter: AuditFilter,
+        args: PageArgs,
+        pool: &PgPool,
+    ) -> Result<Page<AuditEventModel>, ModelError> {
+        let rows = query_as!(
+            AuditEventModel,
+            r#"
+            select id, actor_id, action, target_id, before, after, ip, user_agent, created_at
+            from audit_event
+            where ($1::integer is null or actor_id = $1)
+            and ($2::text is null or action = $2)
+            and ($3::integer is null or target_id = $3)
+            and ($4::timestamptz is null or created_at >= $4)
+            and ($5::timestamptz is null or created_at < $5)
+            and ($6::timestamptz is null or (created_at, id) < ($6, $7))
+            order by created_at desc, id desc
+            limit $8
+            "#,
+            filter.actor_id,
+            filter.action,
+            filter.target_id,
+            filter.since,
+            filter.until,
+            args.after_time(),
+            args.after_id(),
+            args.limit()
+        )
+        .fetch_all(pool)
+        .await?;
+
+        Ok(Page::new(rows, &args))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::{session::SessionModel, users::UsersModel};
+    use serde_json::json;
+
+    #[sqlx::test]
+    async fn audit_log(pool: PgPool) -> Result<()> {
+        let user = UsersModel::create(
+            "test".to_string(),
+            "test@example.com".to_string(),
+            "password".to_string(),
+            &AuditContext::default(),
+            &pool,
+        )
+        .await?;
+        let client = ClientInfo {
+            ip: Some("127.0.0.1".to_string()),
+            user_agent: Some("test".to_string()),
+        };
+        AuditEventModel::record(
+            AuditEntry {
+                actor_id: Some(user.id),
+                action: "modifyMessage".to_string(),
+                target_id: Some(1),
+                before: Some(json!({ "message": "old" })),
+                after: Some(json!({ "message": "new" })),
+            },
+            &client,
+            &pool,
+        )
+        .await?;
+
+        // Logins are recorded, including failed ones.
+        let tokens = UsersModel::login(
+            "test@example.com".to_string(),
+            "password".to_string(),
+            &client,
+            &pool,
+        )
+        .await?
+        .unwrap_tokens();
+        let result = UsersModel::login(
+            "test@example.com".to_string(),
+            "wrong".to_string(),
+            &ClientInfo::default(),
+            &pool,
+        )
+        .await;
+        assert!(result.is_err());
+        // Refreshes are made by the user of the session, without an access token.
+        SessionModel::refresh(tokens.refresh_token, &AuditContext::default(), &pool).await?;
+        // Changes that fail are not recorded.
+        let result = UsersModel::change_password(
+            user.id,
+            "wrong".to_string(),
+            "new password".to_string(),
+            &AuditContext::default(),
+            &pool,
+        )
+        .await;
+        assert!(result.is_err());
+
+        let page =
+            AuditEventModel::find_page(AuditFilter::default(), PageArgs::default(), &pool).await?;
+        assert_eq!(
+            page.rows
+                .iter()
+                .map(|e| (e.action.as_str(), e.actor_id))
+                .collect::<Vec<_>>(),
+            vec![
+                ("refreshToken", Some(user.id)),
+                ("login", None),
+                ("login", Some(user.id)),
+                ("modifyMessage", Some(user.id)),
+                ("createUser", None),
+            ]
+        );
+        assert_eq!(page.rows[1].target_id, Some(user.id));
+        assert_eq!(
+            page.rows[1].after,
+            Some(json!({
+                "email": "test@example.com",
+                "succeeded": false,
+                "mfaRequired": false,
+                "locked": false,
+            }))
+        );
+        assert_eq!(page.rows[2].ip.as_deref(), Some("127.0.0.1"));
+        assert_eq!(page.rows[4].target_id, Some(user.id));
+        let filter = AuditFilter {
+            action: Some("modifyMessage".to_string()),
+            ..Default::default()
+        };
+        let page = AuditEventModel::find_page(filter, PageArgs::default(), &pool).await?;
+        assert_eq!(page.rows[0].before, Some(json!({ "message": "old" })));
+
+        // Events cannot be changed.
+        let result = query!("delete from audit_event").execute(&pool).await;
+        assert!(result.is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,309 @@
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use serde_json::json;
+use sqlx::{query, query_as, PgConnection, PgPool};
+
+use super::{
+    audit::AuditContext,
+    error::{is_unique_violation, ModelError},
+    pagination::MAX_LIST_SIZE,
+};
+
+const MAX_NAME_LENGTH: usize = 255;
+// Channel every user joins when their account is created.
+const DEFAULT_CHANNEL: &str = "general";
+
+#[derive(Debug, Clone, serde::Deserialize, serde::Serialize)]
+pub struct ChannelModel {
+    pub id: i32,
+    pub name: String,
+    #[serde(rename = "createdBy")]
+    pub created_by: Option<i32>,
+    #[serde(rename = "createdAt")]
+    pub created_at: DateTime<Utc>,
+}
+
+impl ChannelModel {
+    // Create a channel with the creator as its first member.
+    pub async fn create(
+        name: String,
+        user_id: i32,
+        audit: &AuditContext,
+        pool: &PgPool,
+    ) -> Result<ChannelModel, ModelError> {
+        let name = name.trim().to_string();
+        if name.is_empty() || name.chars().count() > MAX_NAME_LENGTH {
+            return Err(ModelError::validation(
+                "Channel name must be between 1 and 255 characters.",
+            ));
+        }
+
+        let mut tx = pool.begin().await?;
+        let row = query_as!(
+            ChannelModel,
+            r#"
+            insert into channel (name, created_by)
+            values ($1, $2)
+            returning id, name as "name!", created_by, created_at
+            "#,
+            name,
+            user_id
+        )
+        .fetch_one(&mut *tx)
+        .await
+        .map_err(|e| {
+            if is_unique_violation(&e) {
+                ModelError::conflict("Channel already exists.")
+            } else {
+                e.into()
+            }
+        })?;
+        query!(
+            r#"
+            insert into channel_member (channel_id, user_id)
+            values ($1, $2)
+            "#,
+            row.id,
+            user_id
+        )
+        .execute(&mut *tx)
+        .await?;
+        audit
+            .record(
+                "createChannel",
+                Some(row.id),
+                None,
+                Some(json!(row)),
+                &mut tx,
+            )
+            .await?;
+        tx.commit().await?;
+
+        Ok(row)
+    }
+
+    // Joining a channel twice is a no-op.
+    pub async fn join(
+        id: i32,
+        user_id: i32,
+        audit: &AuditContext,
+        pool: &PgPool,
+    ) -> Result<ChannelModel, ModelError> {
+        let row = find_by_id(id, pool).await?;
+        let mut tx = pool.begin().await?;
+        query!(
+            r#"
+            insert into channel_member (channel_id, user_id)
+            values ($1, $2)
+            on conflict do nothing
+            "#,
+            id,
+            user_id
+        )
+        .execute(&mut *tx)
+        .await?;
+        audit
+            .record("joinChannel", Some(id), None, None, &mut tx)
+            .await?;
+        tx.commit().await?;
+
+        Ok(row)
+    }
+
+    // Also used to leave direct conversations.
+    pub async fn leave(
+        id: i32,
+        user_id: i32,
+        audit: &AuditContext,
+        pool: &PgPool,
+    ) -> Result<(), ModelError> {
+        let mut tx = pool.begin().await?;
+        let result = query!(
+            r#"
+            delete from channel_member
+            where channel_id = $1 and user_id = $2
+            "#,
+            id,
+            user_id
+        )
+        .execute(&mut *tx)
+        .await?;
+        if result.rows_affected() == 0 {
+            return Err(ModelError::not_found("Not a member of the channel."));
+        }
+        audit
+            .record("leaveChannel", Some(id), None, None, &mut tx)
+            .await?;
+        tx.commit().await?;
+
+        Ok(())
+    }
+
+    // List every public channel, so users can find channels to join.
+    pub async fn find_all(pool: &PgPool) -> Result<Vec<ChannelModel>, ModelError> {
+        let rows = query_as!(
+            ChannelModel,
+            r#"
+            select id, name as "name!", created_by, created_at
+            from channel
+            where not is_direct
+            order by name
+            limit $1
+            "#,
+            MAX_LIST_SIZE
+        )
+        .fetch_all(pool)
+        .await?;
+
+        Ok(rows)
+    }
+
+    // List the public channels the user belongs to.
+    pub async fn find_by_user_id(
+        user_id: i32,
+        pool: &PgPool,
+    ) -> Result<Vec<ChannelModel>, ModelError> {
+        let rows = query_as!(
+            ChannelModel,
+            r#"
+            select c.id, c.name as "name!", c.created_by, c.created_at
+            from channel c
+            inner join channel_member cm on cm.channel_id = c.id
+            where cm.user_id = $1
+            and not c.is_direct
+            order by c.name
+            limit $2
+            "#,
+            user_id,
+            MAX_LIST_SIZE
+        )
+        .fetch_all(pool)
+        .await?;
+
+        Ok(rows)
+    }
+
+    pub async fn is_member(id: i32, user_id: i32, pool: &PgPool) -> Result<bool, ModelError> {
+        let row = query!(
+            r#"
+            select exists (
+                select 1
+                from channel_member
+                where channel_id = $1 and user_id = $2
+            ) as "is_member!"
+            "#,
+            id,
+            user_id
+        )
+        .fetch_one(pool)
+        .await?;
+
+        Ok(row.is_member)
+    }
+}
+
+// Add a new user to the default channel, like the users that existed when channels
+// were introduced.
+pub(super) async fn join_default(user_id: i32, conn: &mut PgConnection) -> Result<(), ModelError> {
+    query!(
+        r#"
+        insert into channel_member (channel_id, user_id)
+        select id, $2
+        from channel
+        where name = $1
+        and not is_direct
+        on conflict do nothing
+        "#,
+        DEFAULT_CHANNEL,
+        user_id
+    )
+    .execute(conn)
+    .await?;
+    Ok(())
+}
+
+async fn find_by_id(id: i32, pool: &PgPool) -> Result<ChannelModel, ModelError> {
+    query_as!(
+        ChannelModel,
+        r#"
+        select id, name as "name!", created_by, created_at
+        from channel
+        where id = $1
+        and not is_direct
+        "#,
+        id
+    )
+    .fetch_optional(pool)
+    .await?
+    .ok_or_else(|| ModelError::not_found("Channel not found."))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::users::UsersModel;
+
+    #[sqlx::test]
+    async fn membership(pool: PgPool) -> Result<()> {
+        // Create users.
+        let owner = UsersModel::create(
+            "owner".to_string(),
+            "owner@example.com".to_string(),
+            "password".to_string(),
+            &AuditContext::default(),
+            &pool,
+        )
+        .await?;
+        let other = UsersModel::create(
+            "other".to_string(),
+            "other@example.com".to_string(),
+            "password".to_string(),
+            &AuditContext::default(),
+            &pool,
+        )
+        .await?;
+        // New users start in the default channel.
+        let general = ChannelModel::find_by_user_id(other.id, &pool).await?;
+        assert_eq!(
+            general.iter().map(|c| c.name.as_str()).collect::<Vec<_>>(),
+            vec![DEFAULT_CHANNEL]
+        );
+        // Create channel.
+        let channel = ChannelModel::create(
+            "random".to_string(),
+            owner.id,
+            &AuditContext::default(),
+            &pool,
+        )
+        .await?;
+        assert!(ChannelModel::is_member(channel.id, owner.id, &pool).await?);
+        assert!(!ChannelModel::is_member(channel.id, other.id, &pool).await?);
+        // Create with the same name and an empty name.
+        let result = ChannelModel::create(
+            "random".to_string(),
+            other.id,
+            &AuditContext::default(),
+            &pool,
+        )
+        .await;
+        assert!(matches!(result, Err(ModelError::Conflict(_))));
+        let result =
+            ChannelModel::create(" ".to_string(), other.id, &AuditContext::default(), &pool).await;
+        assert!(matches!(result, Err(ModelError::Validation(_))));
+        // Join twice.
+        ChannelModel::join(channel.id, other.id, &AuditContext::default(), &pool).await?;
+        ChannelModel::join(channel.id, other.id, &AuditContext::default(), &pool).await?;
+        let channels = ChannelModel::find_by_user_id(other.id, &pool).await?;
+        assert_eq!(
+            channels.iter().map(|c| c.id).collect::<Vec<_>>(),
+            vec![general[0].id, channel.id]
+        );
+        // Leave.
+        ChannelModel::leave(channel.id, other.id, &AuditContext::default(), &pool).await?;
+        assert!(!ChannelModel::is_member(channel.id, other.id, &pool).await?);
+        // Join missing channel.
+        let result = ChannelModel::join(0, other.id, &AuditContext::default(), &pool).await;
+        assert!(matches!(result, Err(ModelError::NotFound(_))));
+        Ok(())
+    }
+}
@@ -0,0 +1,399 @@
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use serde_json::json;
+use sqlx::{query, query_as, PgExecutor, PgPool};
+
+use super::{audit::AuditContext, error::ModelError, pagination::MAX_LIST_SIZE, users::UsersModel};
+
+// Participants of a direct conversation, including its creator.
+pub const MAX_PARTICIPANTS: usize = 10;
+
+// A private channel between a few users. Messages are posted to it like to
+// any other channel, using its id as the channel id.
+#[derive(Debug, Clone, serde::Deserialize, serde::Serialize)]
+pub struct ConversationModel {
+    pub id: i32,
+    #[serde(rename = "participantIds")]
+    pub participant_ids: Vec<i32>,
+    #[serde(rename = "lastMessageAt")]
+    pub last_message_at: DateTime<Utc>,
+    #[serde(rename = "unreadCount")]
+    pub unread_count: i64,
+}
+
+impl ConversationModel {
+    // Start a conversation between the user and the participants.
+    // An existing conversation between exactly the same users is reused.
+    pub async fn create(
+        user_id: i32,
+        participant_ids: Vec<i32>,
+        audit: &AuditContext,
+        pool: &PgPool,
+    ) -> Result<ConversationModel, ModelError> {
+        let mut participant_ids = participant_ids;
+        participant_ids.push(user_id);
+        participant_ids.sort_unstable();
+        participant_ids.dedup();
+        if participant_ids.len() < 2 || participant_ids.len() > MAX_PARTICIPANTS {
+            return Err(ModelError::validation(
+                "A conversation must have between 2 and 10 participants.",
+            ));
+        }
+        if UsersModel::find_by_ids(&participant_ids, pool).await?.len() != participant_ids.len() {
+            return Err(ModelError::not_found("User not found."));
+        }
+
+        let mut tx = pool.begin().await?;
+        // Creates for the same users wait for each other, so only one inserts.
+        query!(
+            r#"
+            select 1 as "locked!"
+            from pg_advisory_xact_lock(hashtext($1))
+            "#,
+            participant_key(&participant_ids)
+        )
+        .fetch_one(&mut *tx)
+        .await?;
+        // Only the creator's own conversations can match.
+        let existing = query!(
+            r#"
+            select c.id
+            from channel_member own
+            inner join channel c on c.id = own.channel_id
+            where own.user_id = $2
+            and c.is_direct
+            and (
+                select array_agg(cm.user_id order by cm.user_id)
+                from channel_member cm
+                where cm.channel_id = c.id
+            ) = $1::int4[]
+            limit 1
+            "#,
+            &participant_ids,
+            user_id
+        )
+        .fetch_optional(&mut *tx)
+        .await?;
+        let id = match existing {
+            Some(row) => row.id,
+            None => {
+                let row = query!(
+                    r#"
+                    insert into channel (created_by, is_direct)
+                    values ($1, true)
+                    returning id
+                    "#,
+                    user_id
+                )
+                .fetch_one(&mut *tx)
+                .await?;
+                query!(
+                    r#"
+                    insert into channel_member (channel_id, user_id)
+                    select $1, unnest($2::int4[])
+                    "#,
+                    row.id,
+                    &participant_ids
+                )
+                .execute(&mut *tx)
+                .await?;
+                row.id
+            }
+        };
+        let row = Self::find_by_id(id, user_id, &mut *tx).await?;
+        audit
+            .record(
+                "createConversation",
+                Some(row.id),
+                None,
+                Some(json!(row)),
+                &mut tx,
+            )
+            .await?;
+        tx.commit().await?;
+
+        Ok(row)
+    }
+
+    pub async fn find_by_id(
+        id: i32,
+        user_id: i32,
+        executor: impl PgExecutor<'_>,
+    ) -> Result<ConversationModel, ModelError> {
+        query_as!(
+            ConversationModel,
+            r#"
+            select c.id,
+                array(
+                    select user_id
+                    from channel_member
+                    where channel_id = c.id
+                    order by user_id
+                ) as "participant_ids!",
+                coalesce(max(m.message_time), c.created_at) as "last_message_at!",
+                count(m.id) filter (
+                    where m.user_id is distinct from cm.user_id
+                    and (cm.last_read_at is null or m.message_time > cm.last_read_at)
+                ) as "unread_count!"
+            from channel c
+            inner join channel_member cm on cm.channel_id = c.id and cm.user_id = $2
+            left join message m on m.channel_id = c.id and m.deleted_at is null
+            where c.id = $1
+            and c.is_direct
+            group by c.id, cm.user_id, cm.last_read_at
+            "#,
+            id,
+            user_id
+        )
+        .fetch_optional(executor)
+        .await?
+        .ok_or_else(|| ModelError::not_found("Conversation not found."))
+    }
+
+    // List the user's conversations, most recently active first.
+    pub async fn find_by_user_id(
+        user_id: i32,
+        pool: &PgPool,
+    ) -> Result<Vec<ConversationModel>, ModelError> {
+        let rows = query_as!(
+            ConversationModel,
+            r#"
+            select c.id,
+                array(
+                    select user_id
+                    from channel_member
+                    where channel_id = c.id
+                    order by user_id
+                ) as "participant_ids!",
+                coalesce(max(m.message_time), c.created_at) as "last_message_at!",
+                count(m.id) filter (
+                    where m.user_id is distinct from cm.user_id
+                    and (cm.last_read_at is null or m.message_time > cm.last_read_at)
+                ) as "unread_count!"
+            from channel c
+            inner join channel_member cm on cm.channel_id = c.id and cm.user_id = $1
+            left join message m on m.channel_id = c.id and m.deleted_at is null
+            where c.is_direct
+            group by c.id, cm.user_id, cm.last_read_at
+            order by coalesce(max(m.message_time), c.created_at) desc, c.id desc
+            limit $2
+            "#,
+            user_id,
+            MAX_LIST_SIZE
+        )
+        .fetch_all(pool)
+        .await?;
+
+        Ok(rows)
+    }
+
+    // Mark every message posted so far as read by the user.
+    pub async fn mark_read(
+        id: i32,
+        user_id: i32,
+        audit: &AuditContext,
+        pool: &PgPool,
+    ) -> Result<ConversationModel, ModelError> {
+        let mut tx = pool.begin().await?;
+        let result = query!(
+            r#"
+            update channel_member cm
+            set last_read_at = now()
+            from channel c
+            where c.id = cm.channel_id
+            and c.is_direct
+            and cm.channel_id = $1
+            and cm.user_id = $2
+            "#,
+            id,
+            user_id
+        )
+        .execute(&mut *tx)
+        .await?;
+        if result.rows_affected() == 0 {
+            return Err(ModelError::not_found("Conversation not found."));
+        }
+        audit
+            .record("markConversationRead", Some(id), None, None, &mut tx)
+            .await?;
+        tx.commit().await?;
+
+        Self::find_by_id(id, user_id, pool).await
+    }
+}
+
+// Key of the lock taken while looking for or creating a conversation.
+fn participant_key(participant_ids: &[i32]) -> String {
+    let ids = participant_ids
+        .iter()
+        .map(i32::to_string)
+        .collect::<Vec<_>>()
+        .join(",");
+    format!("conversation:{}", ids)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::{channel::ChannelModel, message::MessageModel, users::DeletionPolicy};
+
+    async fn create_user(name: &str, pool: &PgPool) -> Result<i32> {
+        let user = UsersModel::create(
+            name.to_string(),
+            format!("{}@example.com", name),
+            "password".to_string(),
+            &AuditContext::default(),
+            pool,
+        )
+        .await?;
+        Ok(user.id)
+    }
+
+    #[sqlx::test]
+    async fn create(pool: PgPool) -> Result<()> {
+        let alice = create_user("alice", &pool).await?;
+        let bob = create_user("bob", &pool).await?;
+        let carol = create_user("carol", &pool).await?;
+
+        // Start a conversation and start it again from the other side.
+        let conversation =
+            ConversationModel::create(alice, vec![bob], &AuditContext::default(), &pool).await?;
+        assert_eq!(conversation.participant_ids, vec![alice, bob]);
+        let again =
+            ConversationModel::create(bob, vec![alice, bob], &AuditContext::default(), &pool)
+                .await?;
+        assert_eq!(again.id, conversation.id);
+        // A group with the same users plus one is another conversation.
+        let group =
+            ConversationModel::create(alice, vec![bob, carol], &AuditContext::default(), &pool)
+                .await?;
+        assert_ne!(group.id, conversation.id);
+        // Concurrent creates on separate connections make a single conversation.
+        let wide = sqlx::postgres::PgPoolOptions::new()
+            .max_connections(10)
+            .connect_with((*pool.connect_options()).clone())
+            .await?;
+        let audit = AuditContext::default();
+        let created = futures_util::future::try_join_all(
+            (0..10).map(|_| ConversationModel::create(bob, vec![carol], &audit, &wide)),
+        )
+        .await?;
+        assert!(created.iter().all(|c| c.id == created[0].id));
+
+        // Only participants can post, and the conversation cannot be joined.
+        let result = MessageModel::create(
+            carol,
+            conversation.id,
+            "hi".to_string(),
+            None,
+            &AuditContext::default(),
+            &pool,
+        )
+        .await;
+        assert!(matches!(result, Err(ModelError::Forbidden(_))));
+        let result =
+            ChannelModel::join(conversation.id, carol, &AuditContext::default(), &pool).await;
+        assert!(matches!(result, Err(ModelError::NotFound(_))));
+
+        // Talking to yourself or unknown users.
+        let result =
+            ConversationModel::create(alice, vec![alice], &AuditContext::default(), &pool).await;
+        assert!(matches!(result, Err(ModelError::Validation(_))));
+        let result =
+            ConversationModel::create(alice, vec![0], &AuditContext::default(), &pool).await;
+        assert!(matches!(result, Err(ModelError::NotFound(_))));
+        Ok(())
+    }
+
+    #[sqlx::test]
+    async fn unread_counts(pool: PgPool) -> Result<()> {
+        let alice = create_user("alice", &pool).await?;
+        let bob = create_user("bob", &pool).await?;
+        let carol = create_user("carol", &pool).await?;
+        let with_bob =
+            ConversationModel::create(alice, vec![bob], &AuditContext::default(), &pool).await?;
+        let with_carol =
+            ConversationModel::create(alice, vec![carol], &AuditContext::default(), &pool).await?;
+
+        // Messages from others are unread until marked read.
+        MessageModel::create(
+            bob,
+            with_bob.id,
+            "one".to_string(),
+            None,
+            &AuditContext::default(),
+            &pool,
+        )
+        .await?;
+        MessageModel::create(
+            bob,
+            with_bob.id,
+            "two".to_string(),
+            None,
+            &AuditContext::default(),
+            &pool,
+        )
+        .await?;
+        MessageModel::create(
+            alice,
+            with_bob.id,
+            "mine".to_string(),
+            None,
+            &AuditContext::default(),
+            &pool,
+        )
+        .await?;
+        MessageModel::create(
+            carol,
+            with_carol.id,
+            "hey".to_string(),
+            None,
+            &AuditContext::default(),
+            &pool,
+        )
+        .await?;
+
+        // The latest activity comes first.
+        let conversations = ConversationModel::find_by_user_id(alice, &pool).await?;
+        assert_eq!(
+            conversations
+                .iter()
+                .map(|c| (c.id, c.unread_count))
+                .collect::<Vec<_>>(),
+            vec![(with_carol.id, 1), (with_bob.id, 2)]
+        );
+        let read =
+            ConversationModel::mark_read(with_bob.id, alice, &AuditContext::default(), &pool)
+                .await?;
+        assert_eq!(read.unread_count, 0);
+        // Others see their own unread counts.
+        let conversation = ConversationModel::find_by_id(with_bob.id, bob, &pool).await?;
+        assert_eq!(conversation.unread_count, 1);
+        // Outsiders see nothing.
+        let result =
+            ConversationModel::mark_read(with_bob.id, carol, &AuditContext::default(), &pool).await;
+        assert!(matches!(result, Err(ModelError::NotFound(_))));
+
+        // Messages from deleted accounts stay unread too.
+        MessageModel::create(
+            carol,
+            with_carol.id,
+            "bye".to_string(),
+            None,
+            &AuditContext::default(),
+            &pool,
+        )
+        .await?;
+        UsersModel::delete_account(
+            carol,
+            "password".to_string(),
+            DeletionPolicy::Anonymize,
+            &AuditContext::default(),
+            &pool,
+        )
+        .await?;
+        let conversation = ConversationModel::find_by_id(with_carol.id, alice, &pool).await?;
+        assert_eq!(conversation.unread_count, 2);
+        Ok(())
+    }
+}
@@ -0,0 +1,289 @@
+use anyhow::Result;
+use sqlx::{query, query_as, PgConnection, PgPool};
+
+use super::error::ModelError;
+
+const MAX_TAG_LENGTH: usize = 64;
+pub const MAX_TRENDING_TAGS: i64 = 10;
+
+// How far back `TagModel::find_trending` counts tag usage.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TrendingWindow {
+    Hour,
+    Day,
+    Week,
+}
+
+impl TrendingWindow {
+    fn seconds(self) -> f64 {
+        match self {
+            TrendingWindow::Hour => 60.0 * 60.0,
+            TrendingWindow::Day => 24.0 * 60.0 * 60.0,
+            TrendingWindow::Week => 7.0 * 24.0 * 60.0 * 60.0,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, serde::Deserialize, serde::Serialize)]
+pub struct TagModel {
+    pub tag: String,
+    // Number of messages using the tag.
+    pub count: i64,
+}
+
+impl TagModel {
+    // Most used tags in the viewer's channels within the window.
+    pub async fn find_trending(
+        window: TrendingWindow,
+        viewer_id: i32,
+        pool: &PgPool,
+    ) -> Result<Vec<TagModel>, ModelError> {
+        let rows = query_as!(
+            TagModel,
+            r#"
+            select t.tag, count(*) as "count!"
+            from message_tag t
+            inner join message m on m.id = t.message_id
+            where m.deleted_at is null
+            and m.hidden_at is null
+            and m.message_time >= now() - make_interval(secs => $1)
+            and m.channel_id in (select channel_id from channel_member where user_id = $2)
+            group by t.tag
+            order by count(*) desc, t.tag
+            limit $3
+            "#,
+            window.seconds(),
+            viewer_id,
+            MAX_TRENDING_TAGS
+        )
+        .fetch_all(pool)
+        .await?;
+
+        Ok(rows)
+    }
+}
+
+// Replace the mentions and tags stored for the message with the ones in its body.
+// Mentions of unknown users are ignored.
+pub(super) async fn save(
+    message_id: i32,
+    message: &str,
+    conn: &mut PgConnection,
+) -> Result<(), ModelError> {
+    query!(
+        r#"
+        delete from message_mention
+        where message_id = $1
+        "#,
+        message_id
+    )
+    .execute(&mut *conn)
+    .await?;
+    query!(
+        r#"
+        delete from message_tag
+        where message_id = $1
+        "#,
+        message_id
+    )
+    .execute(&mut *conn)
+    .await?;
+
+    let names = extract(message, '@');
+    if !names.is_empty() {
+        query!(
+            r#"
+            insert into message_mention (message_id, user_id)
+            select $1, id
+            from users
+            where lower(name) = any($2)
+            "#,
+            message_id,
+            &names
+        )
+        .execute(&mut *conn)
+        .await?;
+    }
+    let tags = extract(message, '#')
+        .into_iter()
+        .filter(|tag| tag.chars().count() <= MAX_TAG_LENGTH)
+        .collect::<Vec<_>>();
+    if !tags.is_empty() {
+        query!(
+            r#"
+            insert into message_tag (message_id, tag)
+            select $1, unnest($2::text[])
+            "#,
+            message_id,
+            &tags
+        )
+        .execute(&mut *conn)
+        .await?;
+    }
+
+    Ok(())
+}
+
+// Find the distinct lowercase words following the sigil, e.g. `@name` or `#tag`.
+// A sigil inside a word, like in an email address, does not start an entity.
+fn extract(message: &str, sigil: char) -> Vec<String> {
+    let is_word = |c: char| c.is_alphanumeric() || c == '_';
+    let mut words: Vec<String> = vec![];
+    let mut chars = message.chars().peekable();
+    let mut previous: Option<char> = None;
+    while let Some(c) = chars.next() {
+        if c == sigil && previous.is_none_or(|p| !is_word(p) && p != sigil) {
+            let mut word = String::new();
+            while let Some(&next) = chars.peek().filter(|&&next| is_word(next)) {
+                word.push(next);
+                chars.next();
+            }
+            previous = Some(word.chars().last().unwrap_or(sigil));
+            let word = word.to_lowercase();
+            if !word.is_empty() && !words.contains(&word) {
+                words.push(word);
+            }
+        } else {
+            previous = Some(c);
+        }
+    }
+    words
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::{
+        audit::AuditContext,
+        channel::ChannelModel,
+        message::MessageModel,
+        pagination::PageArgs,
+        users::{Role, UsersModel},
+    };
+
+    #[test]
+    fn extract_entities() {
+        assert_eq!(
+            extract("@Alice and @bob, @alice again", '@'),
+            vec!["alice", "bob"]
+        );
+        assert_eq!(
+            extract("mail me at a@example.com", '@'),
+            Vec::<String>::new()
+        );
+        assert_eq!(
+            extract("#Rust #日本語 ##nope #a#b #", '#'),
+            vec!["rust", "日本語", "a"]
+        );
+    }
+
+    #[sqlx::test]
+    async fn mentions_and_tags(pool: PgPool) -> Result<()> {
+        // Create users.
+        let alice = UsersModel::create(
+            "Alice".to_string(),
+            "alice@example.com".to_string(),
+            "password".to_string(),
+            &AuditContext::default(),
+            &pool,
+        )
+        .await?;
+        let bob = UsersModel::create(
+            "bob".to_string(),
+            "bob@example.com".to_string(),
+            "password".to_string(),
+            &AuditContext::default(),
+            &pool,
+        )
+        .await?;
+        let channel = ChannelModel::create(
+            "test".to_string(),
+            alice.id,
+            &AuditContext::default(),
+            &pool,
+        )
+        .await?;
+        let row = MessageModel::create(
+            alice.id,
+            channel.id,
+            "@bob @nobody see #Rust".to_string(),
+            None,
+            &AuditContext::default(),
+            &pool,
+        )
+        .await?;
+        MessageModel::create(
+            alice.id,
+            channel.id,
+            "#rust #go".to_string(),
+            None,
+            &AuditContext::default(),
+            &pool,
+        )
+        .await?;
+
+        // Find by mention and tag.
+        let page = MessageModel::find_page_by_mentioned_user_id(
+            bob.id,
+            PageArgs::default(),
+            alice.id,
+            &pool,
+        )
+        .await?;
+        assert_eq!(
+            page.rows.iter().map(|m| m.id).collect::<Vec<_>>(),
+            vec![row.id]
+        );
+        let page =
+            MessageModel::find_page_by_tag("RUST", PageArgs::default(), alice.id, &pool).await?;
+        assert_eq!(page.rows.len(), 2);
+        // Only messages in the viewer's channels are returned.
+        let page =
+            MessageModel::find_page_by_tag("rust", PageArgs::default(), bob.id, &pool).await?;
+        assert!(page.rows.is_empty());
+
+        // Editing replaces the mentions and tags.
+        MessageModel::modify(
+            row.id,
+            alice.id,
+            Role::User,
+            "@Alice #go".to_string(),
+            &AuditContext::default(),
+            &pool,
+        )
+        .await?;
+        let page = MessageModel::find_page_by_mentioned_user_id(
+            bob.id,
+            PageArgs::default(),
+            alice.id,
+            &pool,
+        )
+        .await?;
+        assert!(page.rows.is_empty());
+        let page = MessageModel::find_page_by_mentioned_user_id(
+            alice.id,
+            PageArgs::default(),
+            alice.id,
+            &pool,
+        )
+        .await?;
+        assert_eq!(page.rows.len(), 1);
+
+        // Trending tags.
+        let tags = TagModel::find_trending(TrendingWindow::Day, alice.id, &pool).await?;
+        assert_eq!(
+            tags,
+            vec![
+                TagModel {
+                    tag: "go".to_string(),
+                    count: 2,
+                },
+                TagModel {
+                    tag: "rust".to_string(),
+                    count: 1,
+                },
+            ]
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,71 @@
+use thiserror::Error;
+
+// Errors returned by the models. Everything except `Internal` is caused by the
+// request and its message is safe to show to clients.
+#[derive(Debug, Error)]
+pub enum ModelError {
+    #[error("{0}")]
+    NotFound(String),
+    #[error("{0}")]
+    Unauthenticated(String),
+    #[error("{0}")]
+    Forbidden(String),
+    #[error("{0}")]
+    Validation(String),
+    #[error("{0}")]
+    Conflict(String),
+    #[error("{0}")]
+    Locked(String),
+    #[error(transparent)]
+    Internal(#[from] anyhow::Error),
+}
+
+impl ModelError {
+    pub fn not_found(message: &str) -> Self {
+        ModelError::NotFound(message.to_string())
+    }
+
+    pub fn unauthenticated(message: &str) -> Self {
+        ModelError::Unauthenticated(message.to_string())
+    }
+
+    pub fn forbidden(message: &str) -> Self {
+        ModelError::Forbidden(message.to_string())
+    }
+
+    pub fn validation(message: &str) -> Self {
+        ModelError::Validation(message.to_string())
+    }
+
+    pub fn conflict(message: &str) -> Self {
+        ModelError::Conflict(message.to_string())
+    }
+
+    pub fn locked(message: &str) -> Self {
+        ModelError::Locked(message.to_string())
+    }
+}
+
+impl From<sqlx::Error> for ModelError {
+    fn from(e: sqlx::Error) -> Self {
+        ModelError::Internal(e.into())
+    }
+}
+
+impl From<serde_json::Error> for ModelError {
+    fn from(e: serde_json::Error) -> Self {
+        ModelError::Internal(e.into())
+    }
+}
+
+impl From<jsonwebtoken::errors::Error> for ModelError {
+    fn from(e: jsonwebtoken::errors::Error) -> Self {
+        ModelError::Internal(e.into())
+    }
+}
+
+// Whether the error is a unique constraint violation, e.g. a duplicate email.
+pub fn is_unique_violation(e: &sqlx::Error) -> bool {
+    e.as_database_error()
+        .is_some_and(|e| e.is_unique_violation())
+}
@@ -0,0 +1,227 @@
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::{postgres::PgListener, query, query_as, PgPool};
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    OnceLock,
+};
+use tokio::sync::broadcast;
+
+use super::{error::ModelError, message::MessageModelResponse, notification::NotificationModel};
+
+const CHANNEL: &str = "message_events";
+const NOTIFICATION_CHANNEL: &str = "notification_events";
+const CAPACITY: usize = 1024;
+
+static SENDER: OnceLock<broadcast::Sender<MessageEvent>> = OnceLock::new();
+static NOTIFICATION_SENDER: OnceLock<broadcast::Sender<NotificationModel>> = OnceLock::new();
+static USE_PG_NOTIFY: AtomicBool = AtomicBool::new(false);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MessageEventKind {
+    Created,
+    Updated,
+    Deleted,
+    // The reactions of the message changed.
+    Reacted,
+}
+
+#[derive(Debug, Clone)]
+pub struct MessageEvent {
+    pub kind: MessageEventKind,
+    pub message: MessageModelResponse,
+    pub thread_root_id: i32,
+}
+
+// NOTIFY payloads are limited to 8000 bytes, so the message body is left out
+// and re-read by every listening instance.
+#[derive(Debug, Serialize, Deserialize)]
+struct Notification {
+    kind: MessageEventKind,
+    id: i32,
+    user_id: Option<i32>,
+    channel_id: i32,
+    parent_id: Option<i32>,
+    message_time: DateTime<Utc>,
+    edited_at: Option<DateTime<Utc>>,
+    deleted_at: Option<DateTime<Utc>>,
+    hidden_at: Option<DateTime<Utc>>,
+    thread_root_id: i32,
+}
+
+fn sender() -> &'static broadcast::Sender<MessageEvent> {
+    SENDER.get_or_init(|| broadcast::channel(CAPACITY).0)
+}
+
+// Receive every message event published from now on.
+pub fn subscribe() -> broadcast::Receiver<MessageEvent> {
+    sender().subscribe()
+}
+
+// Publish an event to subscribers.
+// With Postgres LISTEN/NOTIFY enabled the event goes through the database,
+// so subscribers on every server instance receive it.
+// Events are published after the change is committed, so failures are only logged:
+// reporting them would make clients retry a change that was saved.
+pub async fn publish(event: MessageEvent, pool: &PgPool) {
+    if let Err(e) = send_event(event, pool).await {
+        log::error!("Failed to publish message event: {:?}", e);
+    }
+}
+
+async fn send_event(event: MessageEvent, pool: &PgPool) -> Result<(), ModelError> {
+    if !USE_PG_NOTIFY.load(Ordering::Relaxed) {
+        // Sending only fails when nobody is subscribed.
+        let _ = sender().send(event);
+        return Ok(());
+    }
+
+    let payload = serde_json::to_string(&Notification {
+        kind: event.kind,
+        id: event.message.id,
+        user_id: event.message.user_id,
+        channel_id: event.message.channel_id,
+        parent_id: event.message.parent_id,
+        message_time: event.message.message_time,
+        edited_at: event.message.edited_at,
+        deleted_at: event.message.deleted_at,
+        hidden_at: event.message.hidden_at,
+        thread_root_id: event.thread_root_id,
+    })?;
+    query!("select pg_notify($1, $2)", CHANNEL, payload)
+        .execute(pool)
+        .await?;
+
+    Ok(())
+}
+
+fn notification_sender() -> &'static broadcast::Sender<NotificationModel> {
+    NOTIFICATION_SENDER.get_or_init(|| broadcast::channel(CAPACITY).0)
+}
+
+// Receive every notification created from now on.
+pub fn subscribe_notifications() -> broadcast::Receiver<NotificationModel> {
+    notification_sender().subscribe()
+}
+
+// Publish a new notification like a message event. Only its id is sent through
+// the database.
+pub async fn publish_notification(notification: NotificationModel, pool: &PgPool) {
+    if let Err(e) = send_notification(notification, pool).await {
+        log::error!("Failed to publish notification: {:?}", e);
+    }
+}
+
+async fn send_notification(
+    notification: NotificationModel,
+    pool: &PgPool,
+) -> Result<(), ModelError> {
+    if !USE_PG_NOTIFY.load(Ordering::Relaxed) {
+        let _ = notification_sender().send(notification);
+        return Ok(());
+    }
+
+    query!(
+        "select pg_notify($1, $2)",
+        NOTIFICATION_CHANNEL,
+        notification.id.to_string()
+    )
+    .execute(pool)
+    .await?;
+
+    Ok(())
+}
+
+// Switch publishing to Postgres LISTEN/NOTIFY and forward notifications
+// to local subscribers.
+pub async fn listen(pool: &PgPool) -> Result<(), ModelError> {
+    let mut listener = PgListener::connect_with(pool).await?;
+    listener.listen_all([CHANNEL, NOTIFICATION_CHANNEL]).await?;
+    USE_PG_NOTIFY.store(true, Ordering::Relaxed);
+
+    let pool = pool.clone();
+    tokio::spawn(async move {
+        loop {
+            let notification = match listener.recv().await {
+                Ok(notification) => notification,
+                Err(e) => {
+                    log::error!("Failed to receive message event: {}", e);
+                    continue;
+                }
+            };
+            if notification.channel() == NOTIFICATION_CHANNEL {
+                match notification_from_payload(notification.payload(), &pool).await {
+                    Ok(notification) => {
+                        let _ = notification_sender().send(notification);
+                    }
+                    Err(e) => log::error!("Failed to read notification: {}", e),
+                }
+                continue;
+            }
+            match from_notification(notification.payload(), &pool).await {
+                Ok(Some(event)) => {
+                    let _ = sender().send(event);
+                }
+                Ok(None) => {}
+                Err(e) => log::error!("Failed to read message event: {}", e),
+            }
+        }
+    });
+
+    Ok(())
+}
+
+async fn from_notification(
+    payload: &str,
+    pool: &PgPool,
+) -> Result<Option<MessageEvent>, ModelError> {
+    let notification = serde_json::from_str::<Notification>(payload)?;
+    let message = if notification.kind == MessageEventKind::Deleted {
+        MessageModelResponse {
+            id: notification.id,
+            user_id: notification.user_id,
+            channel_id: notification.channel_id,
+            message: String::new(),
+            parent_id: notification.parent_id,
+            message_time: notification.message_time,
+            edited_at: notification.edited_at,
+            deleted_at: notification.deleted_at,
+            hidden_at: notification.hidden_at,
+        }
+    } else {
+        // Skip the event if the message has been purged since.
+        match query_as!(
+            MessageModelResponse,
+            r#"
+            select id, user_id, channel_id, message, parent_id, message_time, edited_at, deleted_at, hidden_at
+            from message
+            where id = $1
+            "#,
+            notification.id
+        )
+        .fetch_optional(pool)
+        .await?
+        {
+            Some(message) => message,
+            None => return Ok(None),
+        }
+    };
+
+    Ok(Some(MessageEvent {
+        kind: notification.kind,
+        message,
+        thread_root_id: notification.thread_root_id,
+    }))
+}
+
+async fn notification_from_payload(
+    payload: &str,
+    pool: &PgPool,
+) -> Result<NotificationModel, ModelError> {
+    let id = payload
+        .parse()
+        .map_err(|_| anyhow::anyhow!("Invalid notification id: {}", payload))?;
+    NotificationModel::find_by_id(id, pool).await
+}
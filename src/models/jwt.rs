@@ -0,0 +1,300 @@
+use anyhow::{anyhow, bail, Context, Result};
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use jsonwebtoken::{
+    jwk::{
+        AlgorithmParameters, CommonParameters, EllipticCurve, Jwk, JwkSet, KeyAlgorithm,
+        OctetKeyPairParameters, OctetKeyPairType, PublicKeyUse, RSAKeyParameters, RSAKeyType,
+    },
+    Algorithm, DecodingKey, EncodingKey, Header, Validation,
+};
+use ring::{
+    rand::SystemRandom,
+    signature::{Ed25519KeyPair, KeyPair, RsaKeyPair},
+};
+use serde::{de::DeserializeOwned, Serialize};
+use sha2::{Digest, Sha256};
+use simple_asn1::{oid, ASN1Block};
+use std::{collections::HashMap, env, sync::OnceLock};
+
+use super::error::ModelError;
+
+static KEYS: OnceLock<JwtKeys> = OnceLock::new();
+
+// Keys for signing access tokens with RS256 or EdDSA. Tokens name their key in the
+// `kid` header, so several keys can be accepted at once while rotating.
+pub struct JwtKeys {
+    signing_kid: String,
+    signing_algorithm: Algorithm,
+    signing_key: EncodingKey,
+    verifying_keys: HashMap<String, (Algorithm, DecodingKey)>,
+    jwks: JwkSet,
+}
+
+impl JwtKeys {
+    // `JWT_PRIVATE_KEY` is the path of a PKCS#8 PEM file with the RSA or Ed25519 key to
+    // sign with. `JWT_PUBLIC_KEYS` is an optional comma separated list of PEM public
+    // keys to accept as well, e.g. the previous key while rotating.
+    // Without a private key startup fails, unless `JWT_TEMPORARY_KEY=true` asks for a
+    // throwaway key. That is only fit for development: tokens stop working on restart
+    // and are not accepted by other instances.
+    pub fn from_env() -> Result<Self> {
+        let private_key = match env::var("JWT_PRIVATE_KEY") {
+            Ok(path) => read(&path)?,
+            Err(_) if env::var("JWT_TEMPORARY_KEY").as_deref() == Ok("true") => {
+                log::warn!("JWT_PRIVATE_KEY is not set, signing tokens with a temporary key.");
+                return Self::generate();
+            }
+            Err(_) => bail!("JWT_PRIVATE_KEY is not set."),
+        };
+        let public_keys = env::var("JWT_PUBLIC_KEYS")
+            .unwrap_or_default()
+            .split(',')
+            .map(str::trim)
+            .filter(|path| !path.is_empty())
+            .map(read)
+            .collect::<Result<Vec<_>>>()?;
+        Self::from_pem(&private_key, &public_keys)
+    }
+
+    pub fn from_pem(private_key: &[u8], public_keys: &[Vec<u8>]) -> Result<Self> {
+        let (signing_algorithm, signing_key, signing_jwk) = parse_private_key(private_key)?;
+        let signing_kid = key_id(&signing_jwk);
+        let mut keys = Self {
+            signing_kid,
+            signing_algorithm,
+            signing_key,
+            verifying_keys: HashMap::new(),
+            jwks: JwkSet { keys: vec![] },
+        };
+        keys.add_verifying_key(signing_jwk)?;
+        for public_key in public_keys {
+            keys.add_verifying_key(parse_public_key(public_key)?)?;
+        }
+        Ok(keys)
+    }
+
+    pub fn generate() -> Result<Self> {
+        let document = Ed25519KeyPair::generate_pkcs8(&SystemRandom::new())
+            .map_err(|_| anyhow!("Failed to generate a key."))?;
+        let private_key = pem::encode(&pem::Pem::new("PRIVATE KEY", document.as_ref()));
+        Self::from_pem(private_key.as_bytes(), &[])
+    }
+
+    // The public keys, for other services to verify tokens with.
+    pub fn jwks(&self) -> &JwkSet {
+        &self.jwks
+    }
+
+    pub fn encode<T: Serialize>(&self, claims: &T) -> Result<String, ModelError> {
+        let header = Header {
+            kid: Some(self.signing_kid.clone()),
+            ..Header::new(self.signing_algorithm)
+        };
+        Ok(jsonwebtoken::encode(&header, claims, &self.signing_key)?)
+    }
+
+    pub fn decode<T: DeserializeOwned>(&self, token: &str) -> Result<T, ModelError> {
+        let invalid = || ModelError::unauthenticated("Invalid token.");
+        let header = jsonwebtoken::decode_header(token).map_err(|_| invalid())?;
+        let (algorithm, key) = header
+            .kid
+            .and_then(|kid| self.verifying_keys.get(&kid))
+            .ok_or_else(invalid)?;
+        let data = jsonwebtoken::decode::<T>(token, key, &Validation::new(*algorithm))
+            .map_err(|_| invalid())?;
+        Ok(data.claims)
+    }
+
+    fn add_verifying_key(&mut self, mut jwk: Jwk) -> Result<()> {
+        let kid = key_id(&jwk);
+        if self.verifying_keys.contains_key(&kid) {
+            return Ok(());
+        }
+        let algorithm = match jwk.algorithm {
+            AlgorithmParameters::RSA(_) => Algorithm::RS256,
+            _ => Algorithm::EdDSA,
+        };
+        jwk.common = CommonParameters {
+            public_key_use: Some(PublicKeyUse::Signature),
+            key_algorithm: Some(match algorithm {
+                Algorithm::RS256 => KeyAlgorithm::RS256,
+                _ => KeyAlgorithm::EdDSA,
+            }),
+            key_id: Some(kid.clone()),
+            ..Default::default()
+        };
+        let key = DecodingKey::from_jwk(&jwk)?;
+        self.verifying_keys.insert(kid, (algorithm, key));
+        self.jwks.keys.push(jwk);
+        Ok(())
+    }
+}
+
+// Set the keys at startup. Until then, or if it is never called, e.g. in tests,
+// a throwaway key is used.
+pub fn init(keys: JwtKeys) {
+    if KEYS.set(keys).is_err() {
+        panic!("JWT keys are already set.");
+    }
+}
+
+pub fn keys() -> &'static JwtKeys {
+    KEYS.get_or_init(|| JwtKeys::generate().expect("Failed to generate JWT keys."))
+}
+
+fn read(path: &str) -> Result<Vec<u8>> {
+    std::fs::read(path).with_context(|| format!("Failed to read {}", path))
+}
+
+fn parse_private_key(pem_key: &[u8]) -> Result<(Algorithm, EncodingKey, Jwk)> {
+    let document = pem::parse(pem_key)?;
+    if document.tag() != "PRIVATE KEY" {
+        bail!("Expected a PKCS#8 private key, got {}.", document.tag());
+    }
+    if let Ok(key_pair) = RsaKeyPair::from_pkcs8(document.contents()) {
+        let jwk = rsa_jwk(key_pair.public().as_ref())?;
+        return Ok((Algorithm::RS256, EncodingKey::from_rsa_pem(pem_key)?, jwk));
+    }
+    if let Ok(key_pair) = Ed25519KeyPair::from_pkcs8_maybe_unchecked(document.contents()) {
+        let jwk = ed25519_jwk(key_pair.public_key().as_ref());
+        return Ok((Algorithm::EdDSA, EncodingKey::from_ed_pem(pem_key)?, jwk));
+    }
+    bail!("Unsupported private key, expected RSA or Ed25519.")
+}
+
+// A SubjectPublicKeyInfo, as written by `openssl pkey -pubout`.
+fn parse_public_key(pem_key: &[u8]) -> Result<Jwk> {
+    let document = pem::parse(pem_key)?;
+    if document.tag() != "PUBLIC KEY" {
+        bail!("Expected a public key, got {}.", document.tag());
+    }
+    let blocks = simple_asn1::from_der(document.contents())?;
+    let (algorithm, key) = match blocks.as_slice() {
+        [ASN1Block::Sequence(_, fields)] => match fields.as_slice() {
+            [ASN1Block::Sequence(_, algorithm), ASN1Block::BitString(_, _, key)] => {
+                match algorithm.first() {
+                    Some(ASN1Block::ObjectIdentifier(_, algorithm)) => (algorithm.clone(), key),
+                    _ => bail!("Invalid public key."),
+                }
+            }
+            _ => bail!("Invalid public key."),
+        },
+        _ => bail!("Invalid public key."),
+    };
+    if algorithm == oid!(1, 2, 840, 113549, 1, 1, 1) {
+        rsa_jwk(key)
+    } else if algorithm == oid!(1, 3, 101, 112) {
+        Ok(ed25519_jwk(key))
+    } else {
+        bail!("Unsupported public key, expected RSA or Ed25519.")
+    }
+}
+
+// From a DER encoded RSAPublicKey (PKCS#1).
+fn rsa_jwk(der: &[u8]) -> Result<Jwk> {
+    let blocks = simple_asn1::from_der(der)?;
+    let (n, e) = match blocks.as_slice() {
+        [ASN1Block::Sequence(_, fields)] => match fields.as_slice() {
+            [ASN1Block::Integer(_, n), ASN1Block::Integer(_, e)] => (n, e),
+            _ => bail!("Invalid RSA public key."),
+        },
+        _ => bail!("Invalid RSA public key."),
+    };
+    Ok(Jwk {
+        common: CommonParameters::default(),
+        algorithm: AlgorithmParameters::RSA(RSAKeyParameters {
+            key_type: RSAKeyType::RSA,
+            n: URL_SAFE_NO_PAD.encode(n.to_bytes_be().1),
+            e: URL_SAFE_NO_PAD.encode(e.to_bytes_be().1),
+        }),
+    })
+}
+
+fn ed25519_jwk(public_key: &[u8]) -> Jwk {
+    Jwk {
+        common: CommonParameters::default(),
+        algorithm: AlgorithmParameters::OctetKeyPair(OctetKeyPairParameters {
+            key_type: OctetKeyPairType::OctetKeyPair,
+            curve: EllipticCurve::Ed25519,
+            x: URL_SAFE_NO_PAD.encode(public_key),
+        }),
+    }
+}
+
+// JWK thumbprint (RFC 7638), so the id of a key never changes.
+fn key_id(jwk: &Jwk) -> String {
+    let members = match &jwk.algorithm {
+        AlgorithmParameters::RSA(params) => {
+            format!(r#"{{"e":"{}","kty":"RSA","n":"{}"}}"#, params.e, params.n)
+        }
+        AlgorithmParameters::OctetKeyPair(params) => {
+            format!(r#"{{"crv":"Ed25519","kty":"OKP","x":"{}"}}"#, params.x)
+        }
+        _ => unreachable!("Only RSA and Ed25519 keys are loaded."),
+    };
+    URL_SAFE_NO_PAD.encode(Sha256::digest(members.as_bytes()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde::Deserialize;
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct TestClaims {
+        sub: String,
+        exp: usize,
+    }
+
+    // The SubjectPublicKeyInfo of an Ed25519 key is a fixed prefix and the key.
+    fn public_pem(keys: &JwtKeys) -> Vec<u8> {
+        let x = match &keys.jwks().keys[0].algorithm {
+            AlgorithmParameters::OctetKeyPair(params) => URL_SAFE_NO_PAD.decode(&params.x).unwrap(),
+            _ => unreachable!(),
+        };
+        let mut der = vec![
+            0x30, 0x2a, 0x30, 0x05, 0x06, 0x03, 0x2b, 0x65, 0x70, 0x03, 0x21, 0x00,
+        ];
+        der.extend(x);
+        pem::encode(&pem::Pem::new("PUBLIC KEY", der)).into_bytes()
+    }
+
+    #[test]
+    fn rotation() -> Result<()> {
+        let claims = TestClaims {
+            sub: "1".to_string(),
+            exp: 4102444800,
+        };
+        let old = JwtKeys::generate()?;
+        let new = JwtKeys::generate()?;
+        let token = old.encode(&claims)?;
+        assert_eq!(old.decode::<TestClaims>(&token)?, claims);
+        let header = jsonwebtoken::decode_header(&token)?;
+        assert_eq!(header.alg, Algorithm::EdDSA);
+        assert_eq!(
+            header.kid.as_ref(),
+            old.jwks().keys[0].common.key_id.as_ref()
+        );
+
+        // Tokens of unknown keys are rejected.
+        let result = new.decode::<TestClaims>(&token);
+        assert!(matches!(result, Err(ModelError::Unauthenticated(_))));
+
+        // A new key that still accepts the old one.
+        let private_key = pem::encode(&pem::Pem::new(
+            "PRIVATE KEY",
+            Ed25519KeyPair::generate_pkcs8(&SystemRandom::new())
+                .unwrap()
+                .as_ref(),
+        ));
+        let rotated = JwtKeys::from_pem(private_key.as_bytes(), &[public_pem(&old)])?;
+        assert_eq!(rotated.jwks().keys.len(), 2);
+        assert_eq!(rotated.decode::<TestClaims>(&token)?, claims);
+        let header = jsonwebtoken::decode_header(&rotated.encode(&claims)?)?;
+        assert_ne!(
+            header.kid.as_ref(),
+            old.jwks().keys[0].common.key_id.as_ref()
+        );
+        Ok(())
+    }
+}
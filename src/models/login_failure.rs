@@ -0,0 +1,260 @@
+use chrono::{DateTime, Duration, Utc};
+use serde_json::json;
+use sqlx::{query, PgPool};
+
+use super::{audit::AuditContext, error::ModelError};
+
+// Failures allowed before a lockout. IP addresses get more, since they can be shared.
+const ACCOUNT_FREE_FAILURES: i32 = 5;
+const IP_FREE_FAILURES: i32 = 20;
+// The lockout doubles with every further failure, up to the maximum.
+const BASE_LOCKOUT_SECONDS: i64 = 30;
+const MAX_LOCKOUT_SECONDS: i64 = 60 * 60;
+// Failures are forgotten after a day without any.
+const FAILURE_WINDOW_HOURS: i64 = 24;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, sqlx::Type)]
+#[sqlx(type_name = "login_failure_scope", rename_all = "snake_case")]
+pub enum LoginScope {
+    Account,
+    Ip,
+}
+
+pub struct LoginFailureModel;
+
+impl LoginFailureModel {
+    // Reject the attempt while the email or the IP address is locked.
+    pub async fn check(email: &str, ip: Option<&str>, pool: &PgPool) -> Result<(), ModelError> {
+        let row = query!(
+            r#"
+            select max(locked_until) as locked_until
+            from login_failure
+            where ((scope = $1 and subject = $2) or (scope = $3 and subject = $4))
+            and locked_until > now()
+            "#,
+            LoginScope::Account as LoginScope,
+            account_subject(email),
+            LoginScope::Ip as LoginScope,
+            ip
+        )
+        .fetch_one(pool)
+        .await?;
+        match row.locked_until {
+            Some(locked_until) => Err(locked_error(locked_until)),
+            None => Ok(()),
+        }
+    }
+
+    pub async fn record_failure(
+        email: &str,
+        ip: Option<&str>,
+        pool: &PgPool,
+    ) -> Result<(), ModelError> {
+        record(
+            LoginScope::Account,
+            &account_subject(email),
+            ACCOUNT_FREE_FAILURES,
+            pool,
+        )
+        .await?;
+        if let Some(ip) = ip {
+            record(LoginScope::Ip, ip, IP_FREE_FAILURES, pool).await?;
+        }
+        Ok(())
+    }
+
+    // A successful login clears the failures of the email, but not of the IP address,
+    // so an attacker cannot reset their own count with an account of their own.
+    pub async fn clear(email: &str, pool: &PgPool) -> Result<(), ModelError> {
+        clear(LoginScope::Account, &account_subject(email), pool).await
+    }
+
+    // Lift the lockout of a user's email. Returns whether it was locked.
+    pub async fn unlock_user(
+        user_id: i32,
+        audit: &AuditContext,
+        pool: &PgPool,
+    ) -> Result<bool, ModelError> {
+        let user = query!(
+            r#"
+            select email
+            from users
+            where id = $1
+            "#,
+            user_id
+        )
+        .fetch_optional(pool)
+        .await?
+        .ok_or_else(|| ModelError::not_found("User not found."))?;
+        let mut tx = pool.begin().await?;
+        let row = query!(
+            r#"
+            delete from login_failure
+            where scope = $1 and subject = $2
+            returning locked_until > now() as "locked!"
+            "#,
+            LoginScope::Account as LoginScope,
+            account_subject(&user.email)
+        )
+        .fetch_optional(&mut *tx)
+        .await?;
+        let unlocked = row.is_some_and(|row| row.locked);
+        let after = json!({ "unlocked": unlocked });
+        audit
+            .record("unlockAccount", Some(user_id), None, Some(after), &mut tx)
+            .await?;
+        tx.commit().await?;
+
+        Ok(unlocked)
+    }
+}
+
+async fn record(
+    scope: LoginScope,
+    subject: &str,
+    free_failures: i32,
+    pool: &PgPool,
+) -> Result<(), ModelError> {
+    let row = query!(
+        r#"
+        insert into login_failure (scope, subject, failures, last_failed_at)
+        values ($1, $2, 1, now())
+        on conflict (scope, subject) do update
+        set failures = case
+                when login_failure.last_failed_at > now() - make_interval(hours => $3)
+                then login_failure.failures + 1
+                else 1
+            end,
+            last_failed_at = now()
+        returning failures
+        "#,
+        scope as LoginScope,
+        subject,
+        FAILURE_WINDOW_HOURS as i32
+    )
+    .fetch_one(pool)
+    .await?;
+
+    if let Some(lockout) = lockout(row.failures, free_failures) {
+        query!(
+            r#"
+            update login_failure
+            set locked_until = now() + make_interval(secs => $3)
+            where scope = $1 and subject = $2
+            "#,
+            scope as LoginScope,
+            subject,
+            lockout.num_seconds() as f64
+        )
+        .execute(pool)
+        .await?;
+    }
+    Ok(())
+}
+
+async fn clear(scope: LoginScope, subject: &str, pool: &PgPool) -> Result<(), ModelError> {
+    query!(
+        r#"
+        delete from login_failure
+        where scope = $1 and subject = $2
+        "#,
+        scope as LoginScope,
+        subject
+    )
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+// Emails are compared case-insensitively so the lockout cannot be dodged by changing case.
+fn account_subject(email: &str) -> String {
+    email.trim().to_lowercase()
+}
+
+fn lockout(failures: i32, free_failures: i32) -> Option<Duration> {
+    let extra = failures - free_failures;
+    if extra < 0 {
+        return None;
+    }
+    let seconds = BASE_LOCKOUT_SECONDS
+        .saturating_mul(1 << extra.min(32))
+        .min(MAX_LOCKOUT_SECONDS);
+    Some(Duration::seconds(seconds))
+}
+
+fn locked_error(locked_until: DateTime<Utc>) -> ModelError {
+    let seconds = (locked_until - Utc::now()).num_seconds().max(1);
+    ModelError::Locked(format!(
+        "Too many failed login attempts. Try again in {} seconds.",
+        seconds
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::users::UsersModel;
+    use anyhow::Result;
+
+    #[test]
+    fn exponential_lockout() {
+        assert_eq!(lockout(4, 5), None);
+        assert_eq!(lockout(5, 5), Some(Duration::seconds(30)));
+        assert_eq!(lockout(6, 5), Some(Duration::seconds(60)));
+        assert_eq!(lockout(8, 5), Some(Duration::seconds(240)));
+        assert_eq!(
+            lockout(100, 5),
+            Some(Duration::seconds(MAX_LOCKOUT_SECONDS))
+        );
+    }
+
+    #[sqlx::test]
+    async fn lock_by_email_and_ip(pool: PgPool) -> Result<()> {
+        let ip = Some("127.0.0.1");
+        for _ in 1..ACCOUNT_FREE_FAILURES {
+            LoginFailureModel::record_failure("nobody@example.com", ip, &pool).await?;
+        }
+        LoginFailureModel::check("nobody@example.com", ip, &pool).await?;
+
+        // Locked whether or not the account exists, ignoring case.
+        LoginFailureModel::record_failure("Nobody@example.com", ip, &pool).await?;
+        let result = LoginFailureModel::check("nobody@example.com", None, &pool).await;
+        assert!(matches!(result, Err(ModelError::Locked(_))));
+
+        // The IP address is not locked yet, so other emails can still log in.
+        LoginFailureModel::check("other@example.com", ip, &pool).await?;
+        for _ in ACCOUNT_FREE_FAILURES..IP_FREE_FAILURES {
+            LoginFailureModel::record_failure("other@example.com", ip, &pool).await?;
+        }
+        let result = LoginFailureModel::check("third@example.com", ip, &pool).await;
+        assert!(matches!(result, Err(ModelError::Locked(_))));
+        LoginFailureModel::check("third@example.com", Some("127.0.0.2"), &pool).await?;
+        Ok(())
+    }
+
+    #[sqlx::test]
+    async fn unlock_user(pool: PgPool) -> Result<()> {
+        let user = UsersModel::create(
+            "test".to_string(),
+            "test@example.com".to_string(),
+            "password".to_string(),
+            &AuditContext::default(),
+            &pool,
+        )
+        .await?;
+        assert!(!LoginFailureModel::unlock_user(user.id, &AuditContext::default(), &pool).await?);
+        for _ in 0..ACCOUNT_FREE_FAILURES {
+            LoginFailureModel::record_failure("test@example.com", None, &pool).await?;
+        }
+        assert!(LoginFailureModel::check("test@example.com", None, &pool)
+            .await
+            .is_err());
+
+        assert!(LoginFailureModel::unlock_user(user.id, &AuditContext::default(), &pool).await?);
+        LoginFailureModel::check("test@example.com", None, &pool).await?;
+        let result =
+            LoginFailureModel::unlock_user(user.id + 1, &AuditContext::default(), &pool).await;
+        assert!(matches!(result, Err(ModelError::NotFound(_))));
+        Ok(())
+    }
+}
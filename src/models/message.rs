@@ -1,14 +1,27 @@
-use anyhow::{Error, Result};
+use anyhow::Result;
 use chrono::{DateTime, Utc};
-use jsonwebtoken::{decode, DecodingKey, Validation};
-use sqlx::{query, query_as, PgPool};
+use serde_json::json;
+use sqlx::{query, query_as, PgConnection, PgPool};
+use std::collections::HashMap;
 
-use super::users::Claims;
+use super::{
+    audit::AuditContext,
+    channel::ChannelModel,
+    entity,
+    error::ModelError,
+    events::{self, MessageEvent, MessageEventKind},
+    notification,
+    pagination::{Page, PageArgs, MAX_LIST_SIZE},
+    users::Role,
+};
 
 #[derive(Debug, serde::Deserialize, serde::Serialize)]
 pub struct MessageModel {
     pub id: i32,
-    pub user_id: i32,
+    // None once the author's account is deleted.
+    pub user_id: Option<i32>,
+    #[serde(rename = "channelId")]
+    pub channel_id: i32,
     pub message: String,
     pub parent_id: Option<i32>,
     #[serde(rename = "messageTime")]
@@ -17,139 +30,289 @@ pub struct MessageModel {
     pub created_at: DateTime<Utc>,
     #[serde(rename = "updatedAt")]
     pub updated_at: DateTime<Utc>,
+    #[serde(rename = "editedAt")]
+    pub edited_at: Option<DateTime<Utc>>,
+    #[serde(rename = "deletedAt")]
+    pub deleted_at: Option<DateTime<Utc>>,
+    #[serde(rename = "hiddenAt")]
+    pub hidden_at: Option<DateTime<Utc>>,
 }
 
-#[derive(Debug, serde::Deserialize, serde::Serialize)]
+#[derive(Debug, Clone, serde::Deserialize, serde::Serialize)]
 pub struct MessageModelResponse {
     pub id: i32,
-    pub user_id: i32,
+    // None once the author's account is deleted.
+    pub user_id: Option<i32>,
+    #[serde(rename = "channelId")]
+    pub channel_id: i32,
     pub message: String,
     pub parent_id: Option<i32>,
     #[serde(rename = "messageTime")]
     pub message_time: DateTime<Utc>,
+    #[serde(rename = "editedAt")]
+    pub edited_at: Option<DateTime<Utc>>,
+    #[serde(rename = "deletedAt")]
+    pub deleted_at: Option<DateTime<Utc>>,
+    #[serde(rename = "hiddenAt")]
+    pub hidden_at: Option<DateTime<Utc>>,
 }
 
 impl MessageModel {
+    // The author is always the authenticated user, who must belong to the channel.
     pub async fn create(
         user_id: i32,
+        channel_id: i32,
         message: String,
         parent_id: Option<i32>,
+        audit: &AuditContext,
         pool: &PgPool,
-        token: String,
-    ) -> Result<MessageModelResponse, Error> {
-        verify_token(token)?;
-        // Check if parent_id exists.
-        if parent_id.is_some()
-            && query!(
+    ) -> Result<MessageModelResponse, ModelError> {
+        if !ChannelModel::is_member(channel_id, user_id, pool).await? {
+            return Err(ModelError::forbidden("Not a member of the channel."));
+        }
+        // Check if parent_id exists in the same channel.
+        if let Some(parent_id) = parent_id {
+            let parent = query!(
                 r#"
-            select id
-            from message
-            where id = $1
-            "#,
+                select channel_id
+                from message
+                where id = $1
+                and deleted_at is null
+                "#,
                 parent_id
             )
-            .fetch_one(pool)
-            .await
-            .is_err()
-        {
-            return Err(Error::msg("Parent message not found."));
+            .fetch_optional(pool)
+            .await?;
+            match parent {
+                None => return Err(ModelError::not_found("Parent message not found.")),
+                Some(parent) if parent.channel_id != channel_id => {
+                    return Err(ModelError::validation(
+                        "Parent message is in another channel.",
+                    ))
+                }
+                Some(_) => {}
+            }
         }
 
+        let mut tx = pool.begin().await?;
         let row = query_as!(
             MessageModelResponse,
             r#"
-            insert into message (user_id, message, parent_id, message_time)
-            values ($1, $2, $3, now())
-            returning id, user_id, message, parent_id, message_time
+            insert into message (user_id, channel_id, message, parent_id, message_time)
+            values ($1, $2, $3, $4, now())
+            returning id, user_id, channel_id, message, parent_id, message_time, edited_at, deleted_at, hidden_at
             "#,
             user_id,
+            channel_id,
             message,
             parent_id,
         )
-        .fetch_one(pool)
+        .fetch_one(&mut *tx)
         .await?;
+        entity::save(row.id, &row.message, &mut tx).await?;
+        let notifications = notification::create_for_message(row.id, &mut tx).await?;
+        audit
+            .record(
+                "createMessage",
+                Some(row.id),
+                None,
+                Some(json!(row)),
+                &mut tx,
+            )
+            .await?;
+        tx.commit().await?;
+
+        for notification in notifications {
+            events::publish_notification(notification, pool).await;
+        }
+        announce(MessageEventKind::Created, row.clone(), pool).await;
 
         Ok(row)
     }
 
+    // Moderators can edit any message, which is recorded as a moderator action.
     pub async fn modify(
         id: i32,
+        user_id: i32,
+        role: Role,
         message: String,
+        audit: &AuditContext,
         pool: &PgPool,
-        token: String,
-    ) -> Result<MessageModelResponse, Error> {
-        verify_token(token)?;
-        // Check if message exists.
-        if query!(
+    ) -> Result<MessageModelResponse, ModelError> {
+        // Check if message exists and belongs to the user.
+        let moderated = check_owner(id, user_id, role, pool).await?;
+
+        let mut tx = pool.begin().await?;
+        if moderated {
+            record_moderator_action(id, user_id, ModeratorActionKind::EditMessage, &mut tx).await?;
+        }
+        // Keep the current body as a revision, with who wrote it and when, before
+        // replacing it.
+        let before = lock(id, &mut tx).await?;
+        query!(
             r#"
-            select id
+            insert into message_revision (message_id, message, editor_id, edited_at)
+            select id, message,
+                case when edited_at is null then user_id else editor_id end,
+                coalesce(edited_at, message_time)
             from message
             where id = $1
             "#,
             id
         )
-        .fetch_one(pool)
-        .await
-        .is_err()
-        {
-            return Err(Error::msg("Message not found."));
-        }
-
+        .execute(&mut *tx)
+        .await?;
         let row = query_as!(
             MessageModelResponse,
             r#"
             update message
-            set message = $1, updated_at = now()
+            set message = $1, editor_id = $3, updated_at = now(), edited_at = now()
             where id = $2
-            returning id, user_id, message, parent_id, message_time
+            returning id, user_id, channel_id, message, parent_id, message_time, edited_at, deleted_at, hidden_at
             "#,
             message,
-            id
+            id,
+            user_id
         )
-        .fetch_one(pool)
+        .fetch_one(&mut *tx)
         .await?;
+        // Keep mentions and tags in sync with the new body.
+        entity::save(row.id, &row.message, &mut tx).await?;
+        let notifications = notification::create_for_message(row.id, &mut tx).await?;
+        audit
+            .record(
+                "modifyMessage",
+                Some(id),
+                Some(json!(before)),
+                Some(json!(row)),
+                &mut tx,
+            )
+            .await?;
+        tx.commit().await?;
+
+        for notification in notifications {
+            events::publish_notification(notification, pool).await;
+        }
+        announce(MessageEventKind::Updated, row.clone(), pool).await;
 
         Ok(row)
     }
 
-    pub async fn delete(id: i32, pool: &PgPool, token: String) -> Result<i32, Error> {
-        verify_token(token)?;
-        // Check if message exists.
-        if query!(
+    // Moderators can delete any message, which is recorded as a moderator action.
+    pub async fn delete(
+        id: i32,
+        user_id: i32,
+        role: Role,
+        audit: &AuditContext,
+        pool: &PgPool,
+    ) -> Result<i32, ModelError> {
+        // Check if message exists and belongs to the user.
+        let moderated = check_owner(id, user_id, role, pool).await?;
+
+        let mut tx = pool.begin().await?;
+        if moderated {
+            record_moderator_action(id, user_id, ModeratorActionKind::DeleteMessage, &mut tx)
+                .await?;
+        }
+        let before = lock(id, &mut tx).await?;
+        // Keep a tombstone so replies stay attached to the thread.
+        let row = query_as!(
+            MessageModelResponse,
             r#"
-            select id
-            from message
+            update message
+            set deleted_at = now()
             where id = $1
+            returning id, user_id, channel_id, message, parent_id, message_time, edited_at, deleted_at, hidden_at
             "#,
             id
         )
-        .fetch_one(pool)
-        .await
-        .is_err()
-        {
-            return Err(Error::msg("Message not found."));
-        }
+        .fetch_one(&mut *tx)
+        .await?;
+        audit
+            .record(
+                "deleteMessage",
+                Some(id),
+                Some(json!(before)),
+                Some(json!(row)),
+                &mut tx,
+            )
+            .await?;
+        tx.commit().await?;
+
+        let id = row.id;
+        announce(MessageEventKind::Deleted, row, pool).await;
+
+        Ok(id)
+    }
+
+    // Permanently remove the message and all of its replies.
+    // Returns the ids of the removed messages.
+    pub async fn purge(
+        id: i32,
+        audit: &AuditContext,
+        pool: &PgPool,
+    ) -> Result<Vec<i32>, ModelError> {
+        let mut tx = pool.begin().await?;
+        let before = lock(id, &mut tx).await?;
+        let thread_root_id = thread_root_id(id, pool).await?;
 
-        let row = query!(
+        let rows = query_as!(
+            MessageModelResponse,
             r#"
+            with recursive thread as (
+                select id
+                from message
+                where id = $1
+                union all
+                select m.id
+                from message m
+                inner join thread t on m.parent_id = t.id
+            )
             delete from message
-            where id = $1
-            returning id
+            where id in (select id from thread)
+            returning id, user_id, channel_id, message, parent_id, message_time, edited_at, deleted_at, hidden_at
             "#,
             id
         )
-        .fetch_one(pool)
+        .fetch_all(&mut *tx)
         .await?;
+        let ids: Vec<i32> = rows.iter().map(|row| row.id).collect();
+        let after = json!({ "purgedIds": ids });
+        audit
+            .record(
+                "purgeMessage",
+                Some(id),
+                Some(json!(before)),
+                Some(after),
+                &mut tx,
+            )
+            .await?;
+        tx.commit().await?;
+
+        // Soft deleted messages have already been announced.
+        for row in rows.into_iter().filter(|row| row.deleted_at.is_none()) {
+            events::publish(
+                MessageEvent {
+                    kind: MessageEventKind::Deleted,
+                    message: row,
+                    thread_root_id,
+                },
+                pool,
+            )
+            .await;
+        }
 
-        Ok(row.id)
+        Ok(ids)
     }
 
+    // Only messages in the viewer's channels are returned.
     pub async fn find_by_user_id_and_time_range(
         user_id: i32,
         start_time: Option<DateTime<Utc>>,
         end_time: Option<DateTime<Utc>>,
+        viewer_id: i32,
         pool: &PgPool,
-    ) -> Result<Vec<MessageModelResponse>, Error> {
+    ) -> Result<Vec<MessageModelResponse>, ModelError> {
         // Check if user_id valid.
         if query!(
             r#"
@@ -163,7 +326,7 @@ impl MessageModel {
         .await
         .is_err()
         {
-            return Err(Error::msg("User not found."));
+            return Err(ModelError::not_found("User not found."));
         }
 
         let rows = if start_time.is_some() && end_time.is_some() {
@@ -171,14 +334,21 @@ impl MessageModel {
             query_as!(
                 MessageModelResponse,
                 r#"
-                select id, user_id, message, parent_id, message_time
+                select id, user_id, channel_id, message, parent_id, message_time, edited_at, deleted_at, hidden_at
                 from message
                 where user_id = $1
                 and message_time between $2 and $3
+                and deleted_at is null
+                and channel_id in (select channel_id from channel_member where user_id = $5)
+                and (hidden_at is null or is_moderator($5))
+                order by message_time, id
+                limit $4
                 "#,
                 user_id,
                 start_time,
-                end_time
+                end_time,
+                MAX_LIST_SIZE,
+                viewer_id
             )
             .fetch_all(pool)
             .await?
@@ -187,13 +357,20 @@ impl MessageModel {
             query_as!(
                 MessageModelResponse,
                 r#"
-                select id, user_id, message, parent_id, message_time
+                select id, user_id, channel_id, message, parent_id, message_time, edited_at, deleted_at, hidden_at
                 from message
                 where user_id = $1
                 and message_time >= $2
+                and deleted_at is null
+                and channel_id in (select channel_id from channel_member where user_id = $4)
+                and (hidden_at is null or is_moderator($4))
+                order by message_time, id
+                limit $3
                 "#,
                 user_id,
-                start_time
+                start_time,
+                MAX_LIST_SIZE,
+                viewer_id
             )
             .fetch_all(pool)
             .await?
@@ -202,13 +379,20 @@ impl MessageModel {
             query_as!(
                 MessageModelResponse,
                 r#"
-                select id, user_id, message, parent_id, message_time
+                select id, user_id, channel_id, message, parent_id, message_time, edited_at, deleted_at, hidden_at
                 from message
                 where user_id = $1
                 and message_time <= $2
+                and deleted_at is null
+                and channel_id in (select channel_id from channel_member where user_id = $4)
+                and (hidden_at is null or is_moderator($4))
+                order by message_time, id
+                limit $3
                 "#,
                 user_id,
-                end_time
+                end_time,
+                MAX_LIST_SIZE,
+                viewer_id
             )
             .fetch_all(pool)
             .await?
@@ -217,11 +401,18 @@ impl MessageModel {
             query_as!(
                 MessageModelResponse,
                 r#"
-                select id, user_id, message, parent_id, message_time
+                select id, user_id, channel_id, message, parent_id, message_time, edited_at, deleted_at, hidden_at
                 from message
                 where user_id = $1
+                and deleted_at is null
+                and channel_id in (select channel_id from channel_member where user_id = $3)
+                and (hidden_at is null or is_moderator($3))
+                order by message_time, id
+                limit $2
                 "#,
-                user_id
+                user_id,
+                MAX_LIST_SIZE,
+                viewer_id
             )
             .fetch_all(pool)
             .await?
@@ -233,216 +424,1229 @@ impl MessageModel {
     // Find messages and child messages by id.
     pub async fn find_messages_by_id(
         id: i32,
+        viewer_id: i32,
         pool: &PgPool,
-    ) -> Result<Vec<MessageModelResponse>, Error> {
-        // Check if message exists.
-        if query!(
-            r#"
-            select id
-            from message
-            where id = $1
-            "#,
-            id
-        )
-        .fetch_one(pool)
-        .await
-        .is_err()
-        {
-            return Err(Error::msg("Message not found."));
-        }
+    ) -> Result<Vec<MessageModelResponse>, ModelError> {
+        // Check if message exists in one of the viewer's channels.
+        // Replies are always in the channel of their parent.
+        check_visible(id, viewer_id, pool).await?;
 
         let rows = query_as!(
             MessageModelResponse,
             r#"
             with recursive cte as (
-                select id, user_id, message, parent_id, message_time
+                select id, user_id, channel_id, message, parent_id, message_time, edited_at, deleted_at, hidden_at
                 from message
                 where id = $1
                 union all
-                select m.id, m.user_id, m.message, m.parent_id, m.message_time
+                select m.id, m.user_id, m.channel_id, m.message, m.parent_id, m.message_time, m.edited_at, m.deleted_at, m.hidden_at
                 from message m
                 inner join cte on cte.id = m.parent_id
             )
-            select id as "id!", user_id as "user_id!", message as "message!", parent_id, message_time as "message_time!"
+            select id as "id!", user_id, channel_id as "channel_id!", message as "message!", parent_id, message_time as "message_time!", edited_at, deleted_at, hidden_at
             from cte
+            order by message_time, id
+            limit $2
             "#,
-            id
+            id,
+            MAX_LIST_SIZE
         ).fetch_all(pool).await?;
 
         Ok(rows)
     }
-}
-
-fn verify_token(token: String) -> Result<i32, Error> {
-    let encoding_key = std::env::var("ENCODING_KEY").expect("Failed to get encoding key.");
-    let token_data = decode::<Claims>(
-        &token,
-        &DecodingKey::from_secret(encoding_key.as_bytes()),
-        &Validation::default(),
-    )?;
-    Ok(token_data.claims.sub.parse::<i32>()?)
-}
-
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use crate::models::users::UsersModel;
 
-    #[sqlx::test]
-    async fn create(pool: PgPool) -> Result<()> {
-        let user_id = 1;
-        let message = "test message";
-        let parent_id = None;
-        let email = "test@example.com";
-        let password = "password";
-        // Create user.
-        UsersModel::create(
-            "test".to_string(),
-            email.to_string(),
-            password.to_string(),
-            &pool,
-        )
-        .await?;
-        // Login.
-        let token = UsersModel::login(email.to_string(), password.to_string(), &pool).await?;
-        let dummy_token = "dummy token";
-        // Create message with invalid token.
-        let row = MessageModel::create(
-            user_id,
-            message.to_string(),
-            parent_id,
-            &pool,
-            dummy_token.to_string(),
+    // Find a page of the user's messages ordered by (message_time, id).
+    pub async fn find_page_by_user_id_and_time_range(
+        user_id: i32,
+        start_time: Option<DateTime<Utc>>,
+        end_time: Option<DateTime<Utc>>,
+        args: PageArgs,
+        viewer_id: i32,
+        pool: &PgPool,
+    ) -> Result<Page<MessageModelResponse>, ModelError> {
+        // Check if user_id valid.
+        if query!(
+            r#"
+            select id
+            from users
+            where id = $1
+            "#,
+            user_id
         )
-        .await;
-        assert!(row.is_err());
-        // Create message.
-        let row =
-            MessageModel::create(user_id, message.to_string(), parent_id, &pool, token).await?;
+        .fetch_one(pool)
+        .await
+        .is_err()
+        {
+            return Err(ModelError::not_found("User not found."));
+        }
 
-        assert_eq!(row.user_id, user_id);
-        assert_eq!(row.message, message);
-        assert_eq!(row.parent_id, parent_id);
+        let rows = if args.is_backward() {
+            query_as!(
+                MessageModelResponse,
+                r#"
+                select id, user_id, channel_id, message, parent_id, message_time, edited_at, deleted_at, hidden_at
+                from message
+                where user_id = $1
+                and deleted_at is null
+                and ($2::timestamptz is null or message_time >= $2)
+                and ($3::timestamptz is null or message_time <= $3)
+                and ($4::timestamptz is null or (message_time, id) > ($4, $5))
+                and ($6::timestamptz is null or (message_time, id) < ($6, $7))
+                and channel_id in (select channel_id from channel_member where user_id = $9)
+                and (hidden_at is null or is_moderator($9))
+                order by message_time desc, id desc
+                limit $8
+                "#,
+                user_id,
+                start_time,
+                end_time,
+                args.after_time(),
+                args.after_id(),
+                args.before_time(),
+                args.before_id(),
+                args.limit(),
+                viewer_id
+            )
+            .fetch_all(pool)
+            .await?
+        } else {
+            query_as!(
+                MessageModelResponse,
+                r#"
+                select id, user_id, channel_id, message, parent_id, message_time, edited_at, deleted_at, hidden_at
+                from message
+                where user_id = $1
+                and deleted_at is null
+                and ($2::timestamptz is null or message_time >= $2)
+                and ($3::timestamptz is null or message_time <= $3)
+                and ($4::timestamptz is null or (message_time, id) > ($4, $5))
+                and ($6::timestamptz is null or (message_time, id) < ($6, $7))
+                and channel_id in (select channel_id from channel_member where user_id = $9)
+                and (hidden_at is null or is_moderator($9))
+                order by message_time, id
+                limit $8
+                "#,
+                user_id,
+                start_time,
+                end_time,
+                args.after_time(),
+                args.after_id(),
+                args.before_time(),
+                args.before_id(),
+                args.limit(),
+                viewer_id
+            )
+            .fetch_all(pool)
+            .await?
+        };
 
-        Ok(())
+        Ok(Page::new(rows, &args))
     }
 
-    #[sqlx::test]
-    async fn modify(pool: PgPool) -> Result<()> {
-        let user_id = 1;
-        let message = "test message";
-        let email = "test@example.com";
-        let password = "password";
-        // Create user.
-        UsersModel::create(
-            "test".to_string(),
-            email.to_string(),
-            password.to_string(),
-            &pool,
-        )
-        .await?;
-        // Create message.
-        query!(
-            r#"
-            insert into message (user_id, message, message_time)
-            values ($1, $2, now())
-            "#,
-            user_id,
-            message.to_string(),
-        )
-        .execute(&pool)
-        .await?;
-        let modified_message = "modified message";
-        // Login.
-        let token = UsersModel::login(email.to_string(), password.to_string(), &pool).await?;
-        let dummy_token = "dummy token";
-        // Modify message with invalid token.
-        let row = MessageModel::modify(
-            1,
-            modified_message.to_string(),
-            &pool,
-            dummy_token.to_string(),
-        )
-        .await;
-        assert!(row.is_err());
-        // Modify message.
-        let row = MessageModel::modify(1, modified_message.to_string(), &pool, token).await?;
-        assert_eq!(row.message, modified_message);
-        Ok(())
-    }
+    // Find a page of the message and its child messages ordered by (message_time, id).
+    pub async fn find_message_page_by_id(
+        id: i32,
+        args: PageArgs,
+        viewer_id: i32,
+        pool: &PgPool,
+    ) -> Result<Page<MessageModelResponse>, ModelError> {
+        // Check if message exists in one of the viewer's channels.
+        // Replies are always in the channel of their parent.
+        check_visible(id, viewer_id, pool).await?;
 
-    #[sqlx::test]
-    async fn delete(pool: PgPool) -> Result<()> {
-        let user_id = 1;
-        let message = "test message";
-        let email = "test@example.com";
-        let password = "password";
-        // Create user.
-        UsersModel::create(
-            "test".to_string(),
-            email.to_string(),
-            password.to_string(),
-            &pool,
-        )
-        .await?;
-        // Create message.
-        query!(
-            r#"
-            insert into message (user_id, message, message_time)
-            values ($1, $2, now())
-            "#,
-            user_id,
-            message.to_string(),
-        )
-        .execute(&pool)
-        .await?;
-        // Login.
-        let token = UsersModel::login(email.to_string(), password.to_string(), &pool).await?;
-        let dummy_token = "dummy token";
-        // Delete message with invalid token.
-        let row = MessageModel::delete(user_id, &pool, dummy_token.to_string()).await;
-        assert!(row.is_err());
-        // Delete message.
-        MessageModel::delete(user_id, &pool, token).await?;
-        // Check if message deleted.
-        let row = query_as!(
-            MessageModel,
-            r#"
-            select *
-            from message
-            "#
-        )
-        .fetch_all(&pool)
-        .await?;
-        assert_eq!(row.len(), 0);
-        Ok(())
+        let rows = if args.is_backward() {
+            query_as!(
+                MessageModelResponse,
+                r#"
+                with recursive cte as (
+                    select id, user_id, channel_id, message, parent_id, message_time, edited_at, deleted_at, hidden_at
+                    from message
+                    where id = $1
+                    union all
+                    select m.id, m.user_id, m.channel_id, m.message, m.parent_id, m.message_time, m.edited_at, m.deleted_at, m.hidden_at
+                    from message m
+                    inner join cte on cte.id = m.parent_id
+                )
+                select id as "id!", user_id, channel_id as "channel_id!", message as "message!", parent_id, message_time as "message_time!", edited_at, deleted_at, hidden_at
+                from cte
+                where ($2::timestamptz is null or (message_time, id) > ($2, $3))
+                and ($4::timestamptz is null or (message_time, id) < ($4, $5))
+                order by message_time desc, id desc
+                limit $6
+                "#,
+                id,
+                args.after_time(),
+                args.after_id(),
+                args.before_time(),
+                args.before_id(),
+                args.limit()
+            )
+            .fetch_all(pool)
+            .await?
+        } else {
+            query_as!(
+                MessageModelResponse,
+                r#"
+                with recursive cte as (
+                    select id, user_id, channel_id, message, parent_id, message_time, edited_at, deleted_at, hidden_at
+                    from message
+                    where id = $1
+                    union all
+                    select m.id, m.user_id, m.channel_id, m.message, m.parent_id, m.message_time, m.edited_at, m.deleted_at, m.hidden_at
+                    from message m
+                    inner join cte on cte.id = m.parent_id
+                )
+                select id as "id!", user_id, channel_id as "channel_id!", message as "message!", parent_id, message_time as "message_time!", edited_at, deleted_at, hidden_at
+                from cte
+                where ($2::timestamptz is null or (message_time, id) > ($2, $3))
+                and ($4::timestamptz is null or (message_time, id) < ($4, $5))
+                order by message_time, id
+                limit $6
+                "#,
+                id,
+                args.after_time(),
+                args.after_id(),
+                args.before_time(),
+                args.before_id(),
+                args.limit()
+            )
+            .fetch_all(pool)
+            .await?
+        };
+
+        Ok(Page::new(rows, &args))
     }
 
-    #[sqlx::test]
-    async fn find_by_user_id_and_time_range(pool: PgPool) -> Result<()> {
-        let user_id = 1;
-        let message = "test message".to_string();
-        // Create user.
-        query!(
-            r#"
-            insert into users (id, name, password, email)
-            values ($1, $2, $3, $4)
-            "#,
-            user_id,
-            "test",
-            "test",
-            "test@example.com"
-        )
-        .execute(&pool)
-        .await?;
-        // Create message.
-        let message_time = chrono::Utc::now();
-        query!(
+    // Find a page of the channel's messages ordered by (message_time, id).
+    pub async fn find_page_by_channel_id(
+        channel_id: i32,
+        args: PageArgs,
+        viewer_id: i32,
+        pool: &PgPool,
+    ) -> Result<Page<MessageModelResponse>, ModelError> {
+        // Channels the viewer is not a member of are hidden.
+        if !ChannelModel::is_member(channel_id, viewer_id, pool).await? {
+            return Err(ModelError::not_found("Channel not found."));
+        }
+
+        let rows = if args.is_backward() {
+            query_as!(
+                MessageModelResponse,
+                r#"
+                select id, user_id, channel_id, message, parent_id, message_time, edited_at, deleted_at, hidden_at
+                from message
+                where channel_id = $1
+                and deleted_at is null
+                and ($2::timestamptz is null or (message_time, id) > ($2, $3))
+                and ($4::timestamptz is null or (message_time, id) < ($4, $5))
+                and (hidden_at is null or is_moderator($7))
+                order by message_time desc, id desc
+                limit $6
+                "#,
+                channel_id,
+                args.after_time(),
+                args.after_id(),
+                args.before_time(),
+                args.before_id(),
+                args.limit(),
+                viewer_id
+            )
+            .fetch_all(pool)
+            .await?
+        } else {
+            query_as!(
+                MessageModelResponse,
+                r#"
+                select id, user_id, channel_id, message, parent_id, message_time, edited_at, deleted_at, hidden_at
+                from message
+                where channel_id = $1
+                and deleted_at is null
+                and ($2::timestamptz is null or (message_time, id) > ($2, $3))
+                and ($4::timestamptz is null or (message_time, id) < ($4, $5))
+                and (hidden_at is null or is_moderator($7))
+                order by message_time, id
+                limit $6
+                "#,
+                channel_id,
+                args.after_time(),
+                args.after_id(),
+                args.before_time(),
+                args.before_id(),
+                args.limit(),
+                viewer_id
+            )
+            .fetch_all(pool)
+            .await?
+        };
+
+        Ok(Page::new(rows, &args))
+    }
+
+    // Find a page of messages mentioning the user ordered by (message_time, id).
+    pub async fn find_page_by_mentioned_user_id(
+        user_id: i32,
+        args: PageArgs,
+        viewer_id: i32,
+        pool: &PgPool,
+    ) -> Result<Page<MessageModelResponse>, ModelError> {
+        let rows = if args.is_backward() {
+            query_as!(
+                MessageModelResponse,
+                r#"
+                select id, user_id, channel_id, message, parent_id, message_time, edited_at, deleted_at, hidden_at
+                from message
+                where id in (select message_id from message_mention where user_id = $1)
+                and deleted_at is null
+                and ($2::timestamptz is null or (message_time, id) > ($2, $3))
+                and ($4::timestamptz is null or (message_time, id) < ($4, $5))
+                and channel_id in (select channel_id from channel_member where user_id = $7)
+                and (hidden_at is null or is_moderator($7))
+                order by message_time desc, id desc
+                limit $6
+                "#,
+                user_id,
+                args.after_time(),
+                args.after_id(),
+                args.before_time(),
+                args.before_id(),
+                args.limit(),
+                viewer_id
+            )
+            .fetch_all(pool)
+            .await?
+        } else {
+            query_as!(
+                MessageModelResponse,
+                r#"
+                select id, user_id, channel_id, message, parent_id, message_time, edited_at, deleted_at, hidden_at
+                from message
+                where id in (select message_id from message_mention where user_id = $1)
+                and deleted_at is null
+                and ($2::timestamptz is null or (message_time, id) > ($2, $3))
+                and ($4::timestamptz is null or (message_time, id) < ($4, $5))
+                and channel_id in (select channel_id from channel_member where user_id = $7)
+                and (hidden_at is null or is_moderator($7))
+                order by message_time, id
+                limit $6
+                "#,
+                user_id,
+                args.after_time(),
+                args.after_id(),
+                args.before_time(),
+                args.before_id(),
+                args.limit(),
+                viewer_id
+            )
+            .fetch_all(pool)
+            .await?
+        };
+
+        Ok(Page::new(rows, &args))
+    }
+
+    // Find a page of messages using the tag ordered by (message_time, id).
+    pub async fn find_page_by_tag(
+        tag: &str,
+        args: PageArgs,
+        viewer_id: i32,
+        pool: &PgPool,
+    ) -> Result<Page<MessageModelResponse>, ModelError> {
+        let rows = if args.is_backward() {
+            query_as!(
+                MessageModelResponse,
+                r#"
+                select id, user_id, channel_id, message, parent_id, message_time, edited_at, deleted_at, hidden_at
+                from message
+                where id in (select message_id from message_tag where tag = lower($1))
+                and deleted_at is null
+                and ($2::timestamptz is null or (message_time, id) > ($2, $3))
+                and ($4::timestamptz is null or (message_time, id) < ($4, $5))
+                and channel_id in (select channel_id from channel_member where user_id = $7)
+                and (hidden_at is null or is_moderator($7))
+                order by message_time desc, id desc
+                limit $6
+                "#,
+                tag,
+                args.after_time(),
+                args.after_id(),
+                args.before_time(),
+                args.before_id(),
+                args.limit(),
+                viewer_id
+            )
+            .fetch_all(pool)
+            .await?
+        } else {
+            query_as!(
+                MessageModelResponse,
+                r#"
+                select id, user_id, channel_id, message, parent_id, message_time, edited_at, deleted_at, hidden_at
+                from message
+                where id in (select message_id from message_tag where tag = lower($1))
+                and deleted_at is null
+                and ($2::timestamptz is null or (message_time, id) > ($2, $3))
+                and ($4::timestamptz is null or (message_time, id) < ($4, $5))
+                and channel_id in (select channel_id from channel_member where user_id = $7)
+                and (hidden_at is null or is_moderator($7))
+                order by message_time, id
+                limit $6
+                "#,
+                tag,
+                args.after_time(),
+                args.after_id(),
+                args.before_time(),
+                args.before_id(),
+                args.limit(),
+                viewer_id
+            )
+            .fetch_all(pool)
+            .await?
+        };
+
+        Ok(Page::new(rows, &args))
+    }
+
+    pub async fn find_by_ids(
+        ids: &[i32],
+        pool: &PgPool,
+    ) -> Result<Vec<MessageModelResponse>, ModelError> {
+        let rows = query_as!(
+            MessageModelResponse,
+            r#"
+            select id, user_id, channel_id, message, parent_id, message_time, edited_at, deleted_at, hidden_at
+            from message
+            where id = any($1)
+            "#,
+            ids
+        )
+        .fetch_all(pool)
+        .await?;
+
+        Ok(rows)
+    }
+
+    // Find messages by id, leaving out those outside the viewer's channels.
+    pub async fn find_by_ids_for_viewer(
+        ids: &[i32],
+        viewer_id: i32,
+        pool: &PgPool,
+    ) -> Result<Vec<MessageModelResponse>, ModelError> {
+        let rows = query_as!(
+            MessageModelResponse,
+            r#"
+            select m.id, m.user_id, m.channel_id, m.message, m.parent_id, m.message_time, m.edited_at, m.deleted_at, m.hidden_at
+            from message m
+            inner join channel_member cm on cm.channel_id = m.channel_id
+            where m.id = any($1)
+            and cm.user_id = $2
+            "#,
+            ids,
+            viewer_id
+        )
+        .fetch_all(pool)
+        .await?;
+
+        Ok(rows)
+    }
+
+    // Count the direct replies of each message.
+    pub async fn count_replies(
+        ids: &[i32],
+        pool: &PgPool,
+    ) -> Result<HashMap<i32, i64>, ModelError> {
+        let rows = query!(
+            r#"
+            select parent_id as "parent_id!", count(*) as "count!"
+            from message
+            where parent_id = any($1)
+            group by parent_id
+            "#,
+            ids
+        )
+        .fetch_all(pool)
+        .await?;
+
+        Ok(rows.into_iter().map(|r| (r.parent_id, r.count)).collect())
+    }
+
+    // Find the top-level message of each message's thread.
+    pub async fn find_thread_root_ids(
+        ids: &[i32],
+        pool: &PgPool,
+    ) -> Result<HashMap<i32, i32>, ModelError> {
+        let rows = query!(
+            r#"
+            with recursive ancestors as (
+                select id as start_id, id, parent_id, 0 as depth
+                from message
+                where id = any($1)
+                union all
+                select a.start_id, m.id, m.parent_id, a.depth + 1
+                from message m
+                inner join ancestors a on a.parent_id = m.id
+            )
+            select distinct on (start_id) start_id as "start_id!", id as "id!"
+            from ancestors
+            order by start_id, depth desc
+            "#,
+            ids
+        )
+        .fetch_all(pool)
+        .await?;
+
+        Ok(rows.into_iter().map(|r| (r.start_id, r.id)).collect())
+    }
+
+    // Find a page of direct replies for each of the given messages.
+    // Only forward paging is supported.
+    pub async fn find_reply_pages(
+        parent_ids: &[i32],
+        args: PageArgs,
+        pool: &PgPool,
+    ) -> Result<HashMap<i32, Page<MessageModelResponse>>, ModelError> {
+        let rows = query_as!(
+            MessageModelResponse,
+            r#"
+            select id as "id!", user_id, channel_id as "channel_id!", message as "message!", parent_id, message_time as "message_time!", edited_at, deleted_at, hidden_at
+            from (
+                select id, user_id, channel_id, message, parent_id, message_time, edited_at, deleted_at, hidden_at,
+                    row_number() over (partition by parent_id order by message_time, id) as position
+                from message
+                where parent_id = any($1)
+                and ($2::timestamptz is null or (message_time, id) > ($2, $3))
+            ) replies
+            where position <= $4
+            order by parent_id, message_time, id
+            "#,
+            parent_ids,
+            args.after_time(),
+            args.after_id(),
+            args.limit()
+        )
+        .fetch_all(pool)
+        .await?;
+
+        let mut replies: HashMap<i32, Vec<MessageModelResponse>> =
+            parent_ids.iter().map(|id| (*id, Vec::new())).collect();
+        for row in rows {
+            if let Some(parent_id) = row.parent_id {
+                replies.entry(parent_id).or_default().push(row);
+            }
+        }
+
+        Ok(replies
+            .into_iter()
+            .map(|(id, rows)| (id, Page::new(rows, &args)))
+            .collect())
+    }
+}
+
+// Find the top-level message of the thread the given message belongs to.
+// If a parent has been removed the oldest reachable ancestor is used.
+pub(super) async fn thread_root_id(id: i32, pool: &PgPool) -> Result<i32, ModelError> {
+    let row = query!(
+        r#"
+        with recursive ancestors as (
+            select id, parent_id, 0 as depth
+            from message
+            where id = $1
+            union all
+            select m.id, m.parent_id, a.depth + 1
+            from message m
+            inner join ancestors a on a.parent_id = m.id
+        )
+        select id as "id!"
+        from ancestors
+        order by depth desc
+        limit 1
+        "#,
+        id
+    )
+    .fetch_one(pool)
+    .await?;
+
+    Ok(row.id)
+}
+
+// Tell subscribers about a committed change. Like publishing, this only logs failures.
+pub(super) async fn announce(kind: MessageEventKind, message: MessageModelResponse, pool: &PgPool) {
+    match thread_root_id(message.id, pool).await {
+        Ok(thread_root_id) => {
+            events::publish(
+                MessageEvent {
+                    kind,
+                    message,
+                    thread_root_id,
+                },
+                pool,
+            )
+            .await
+        }
+        Err(e) => log::error!("Failed to publish message event: {:?}", e),
+    }
+}
+
+// Erase the messages of a user whose account is being deleted. Replied messages are
+// kept as tombstones, like deleted ones, so their replies stay in the thread, and the
+// rest are removed. Returns the deletions to announce once committed.
+pub(super) async fn erase_by_user(
+    user_id: i32,
+    conn: &mut PgConnection,
+) -> Result<Vec<MessageEvent>, ModelError> {
+    // Messages deleted before have already been announced.
+    let thread_root_ids: HashMap<i32, i32> = query!(
+        r#"
+        with recursive ancestors as (
+            select id as message_id, id, parent_id
+            from message
+            where user_id = $1 and deleted_at is null
+            union all
+            select a.message_id, m.id, m.parent_id
+            from message m
+            inner join ancestors a on a.parent_id = m.id
+        )
+        select message_id as "message_id!", id as "thread_root_id!"
+        from ancestors
+        where parent_id is null
+        "#,
+        user_id
+    )
+    .fetch_all(&mut *conn)
+    .await?
+    .into_iter()
+    .map(|row| (row.message_id, row.thread_root_id))
+    .collect();
+
+    query!(
+        r#"
+        delete from message_revision
+        where message_id in (select id from message where user_id = $1)
+        "#,
+        user_id
+    )
+    .execute(&mut *conn)
+    .await?;
+    query!(
+        r#"
+        delete from message_mention
+        where message_id in (select id from message where user_id = $1)
+        "#,
+        user_id
+    )
+    .execute(&mut *conn)
+    .await?;
+    query!(
+        r#"
+        delete from message_tag
+        where message_id in (select id from message where user_id = $1)
+        "#,
+        user_id
+    )
+    .execute(&mut *conn)
+    .await?;
+    let rows = query_as!(
+        MessageModelResponse,
+        r#"
+        update message
+        set message = '', deleted_at = coalesce(deleted_at, now())
+        where user_id = $1
+        returning id, user_id, channel_id, message, parent_id, message_time, edited_at, deleted_at, hidden_at
+        "#,
+        user_id
+    )
+    .fetch_all(&mut *conn)
+    .await?;
+    query!(
+        r#"
+        delete from message m
+        where m.user_id = $1
+        and not exists (select 1 from message r where r.parent_id = m.id)
+        "#,
+        user_id
+    )
+    .execute(&mut *conn)
+    .await?;
+
+    Ok(rows
+        .into_iter()
+        .filter_map(|row| {
+            thread_root_ids
+                .get(&row.id)
+                .map(|&thread_root_id| MessageEvent {
+                    kind: MessageEventKind::Deleted,
+                    // The author goes with the account.
+                    message: MessageModelResponse {
+                        user_id: None,
+                        ..row
+                    },
+                    thread_root_id,
+                })
+        })
+        .collect())
+}
+
+// Lock the message for a change, or report it as missing.
+async fn lock(id: i32, conn: &mut PgConnection) -> Result<MessageModelResponse, ModelError> {
+    query_as!(
+        MessageModelResponse,
+        r#"
+        select id, user_id, channel_id, message, parent_id, message_time, edited_at, deleted_at, hidden_at
+        from message
+        where id = $1
+        for update
+        "#,
+        id
+    )
+    .fetch_optional(conn)
+    .await?
+    .ok_or_else(|| ModelError::not_found("Message not found."))
+}
+
+// Check that the message exists in one of the channels the viewer belongs to.
+// Messages in other channels and hidden messages are reported as missing,
+// except that moderators see hidden messages.
+async fn check_visible(id: i32, viewer_id: i32, pool: &PgPool) -> Result<(), ModelError> {
+    let row = query!(
+        r#"
+        select m.id
+        from message m
+        where m.id = $1
+        and m.channel_id in (select channel_id from channel_member where user_id = $2)
+        and (m.hidden_at is null or is_moderator($2))
+        "#,
+        id,
+        viewer_id
+    )
+    .fetch_optional(pool)
+    .await?;
+
+    match row {
+        None => Err(ModelError::not_found("Message not found.")),
+        Some(_) => Ok(()),
+    }
+}
+
+// Check that the message exists, is not deleted and was posted by the given user.
+// Moderators pass for other users' messages, which is reported by returning true.
+async fn check_owner(id: i32, user_id: i32, role: Role, pool: &PgPool) -> Result<bool, ModelError> {
+    let row = query!(
+        r#"
+        select user_id
+        from message
+        where id = $1
+        and deleted_at is null
+        "#,
+        id
+    )
+    .fetch_optional(pool)
+    .await?;
+
+    match row {
+        None => Err(ModelError::not_found("Message not found.")),
+        Some(row) if row.user_id == Some(user_id) => Ok(false),
+        Some(_) if role >= Role::Moderator => Ok(true),
+        Some(_) => Err(ModelError::forbidden("Forbidden.")),
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, sqlx::Type)]
+#[sqlx(type_name = "moderator_action_kind", rename_all = "snake_case")]
+enum ModeratorActionKind {
+    EditMessage,
+    DeleteMessage,
+}
+
+async fn record_moderator_action(
+    message_id: i32,
+    moderator_id: i32,
+    kind: ModeratorActionKind,
+    conn: &mut PgConnection,
+) -> Result<(), ModelError> {
+    query!(
+        r#"
+        insert into moderator_action (moderator_id, kind, message_id)
+        values ($1, $2, $3)
+        "#,
+        moderator_id,
+        kind as ModeratorActionKind,
+        message_id
+    )
+    .execute(conn)
+    .await?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::{pagination::Cursor, users::UsersModel};
+
+    // Create a channel with the user as its only member.
+    async fn create_channel(user_id: i32, pool: &PgPool) -> Result<i32> {
+        let channel =
+            ChannelModel::create("test".to_string(), user_id, &AuditContext::default(), pool)
+                .await?;
+        Ok(channel.id)
+    }
+
+    #[sqlx::test]
+    async fn create(pool: PgPool) -> Result<()> {
+        let message = "test message";
+        let parent_id = None;
+        // Create user.
+        let user = UsersModel::create(
+            "test".to_string(),
+            "test@example.com".to_string(),
+            "password".to_string(),
+            &AuditContext::default(),
+            &pool,
+        )
+        .await?;
+        let channel_id = create_channel(user.id, &pool).await?;
+        // Create message.
+        let row = MessageModel::create(
+            user.id,
+            channel_id,
+            message.to_string(),
+            parent_id,
+            &AuditContext::default(),
+            &pool,
+        )
+        .await?;
+
+        assert_eq!(row.user_id, Some(user.id));
+        assert_eq!(row.message, message);
+        assert_eq!(row.parent_id, parent_id);
+
+        Ok(())
+    }
+
+    #[sqlx::test]
+    async fn modify(pool: PgPool) -> Result<()> {
+        let user_id = 1;
+        let message = "test message";
+        // Create user.
+        UsersModel::create(
+            "test".to_string(),
+            "test@example.com".to_string(),
+            "password".to_string(),
+            &AuditContext::default(),
+            &pool,
+        )
+        .await?;
+        let channel_id = create_channel(user_id, &pool).await?;
+        // Create message.
+        query!(
+            r#"
+            insert into message (user_id, channel_id, message, message_time)
+            values ($1, $2, $3, now())
+            "#,
+            user_id,
+            channel_id,
+            message.to_string(),
+        )
+        .execute(&pool)
+        .await?;
+        let modified_message = "modified message";
+        // Modify message.
+        let row = MessageModel::modify(
+            1,
+            user_id,
+            Role::User,
+            modified_message.to_string(),
+            &AuditContext::default(),
+            &pool,
+        )
+        .await?;
+        assert_eq!(row.message, modified_message);
+        Ok(())
+    }
+
+    #[sqlx::test]
+    async fn delete(pool: PgPool) -> Result<()> {
+        let user_id = 1;
+        let message = "test message";
+        // Create user.
+        UsersModel::create(
+            "test".to_string(),
+            "test@example.com".to_string(),
+            "password".to_string(),
+            &AuditContext::default(),
+            &pool,
+        )
+        .await?;
+        let channel_id = create_channel(user_id, &pool).await?;
+        // Create message.
+        query!(
+            r#"
+            insert into message (user_id, channel_id, message, message_time)
+            values ($1, $2, $3, now())
+            "#,
+            user_id,
+            channel_id,
+            message.to_string(),
+        )
+        .execute(&pool)
+        .await?;
+        // Delete message.
+        MessageModel::delete(1, user_id, Role::User, &AuditContext::default(), &pool).await?;
+        // Check if message left as a tombstone.
+        let row = query_as!(
+            MessageModel,
+            r#"
+            select id, user_id, channel_id, message, message_time, created_at, updated_at, parent_id, edited_at, deleted_at, hidden_at
+            from message
+            "#
+        )
+        .fetch_all(&pool)
+        .await?;
+        assert_eq!(row.len(), 1);
+        assert!(row[0].deleted_at.is_some());
+        // Delete and modify deleted message.
+        let result =
+            MessageModel::delete(1, user_id, Role::User, &AuditContext::default(), &pool).await;
+        assert_eq!(result.unwrap_err().to_string(), "Message not found.");
+        let result = MessageModel::modify(
+            1,
+            user_id,
+            Role::User,
+            "modified".to_string(),
+            &AuditContext::default(),
+            &pool,
+        )
+        .await;
+        assert_eq!(result.unwrap_err().to_string(), "Message not found.");
+        Ok(())
+    }
+
+    #[sqlx::test]
+    async fn modify_by_other_user(pool: PgPool) -> Result<()> {
+        let message = "test message";
+        // Create users.
+        let owner = UsersModel::create(
+            "owner".to_string(),
+            "owner@example.com".to_string(),
+            "password".to_string(),
+            &AuditContext::default(),
+            &pool,
+        )
+        .await?;
+        let other = UsersModel::create(
+            "other".to_string(),
+            "other@example.com".to_string(),
+            "password".to_string(),
+            &AuditContext::default(),
+            &pool,
+        )
+        .await?;
+        let channel_id = create_channel(owner.id, &pool).await?;
+        // Create message as owner.
+        let row = MessageModel::create(
+            owner.id,
+            channel_id,
+            message.to_string(),
+            None,
+            &AuditContext::default(),
+            &pool,
+        )
+        .await?;
+        // Modify message as other user.
+        let result = MessageModel::modify(
+            row.id,
+            other.id,
+            Role::User,
+            "hijacked".to_string(),
+            &AuditContext::default(),
+            &pool,
+        )
+        .await;
+        assert_eq!(result.unwrap_err().to_string(), "Forbidden.");
+        // Check if message unchanged.
+        let stored = query!(
+            r#"
+            select message
+            from message
+            where id = $1
+            "#,
+            row.id
+        )
+        .fetch_one(&pool)
+        .await?;
+        assert_eq!(stored.message, message);
+        Ok(())
+    }
+
+    #[sqlx::test]
+    async fn delete_by_other_user(pool: PgPool) -> Result<()> {
+        let message = "test message";
+        // Create users.
+        let owner = UsersModel::create(
+            "owner".to_string(),
+            "owner@example.com".to_string(),
+            "password".to_string(),
+            &AuditContext::default(),
+            &pool,
+        )
+        .await?;
+        let other = UsersModel::create(
+            "other".to_string(),
+            "other@example.com".to_string(),
+            "password".to_string(),
+            &AuditContext::default(),
+            &pool,
+        )
+        .await?;
+        let channel_id = create_channel(owner.id, &pool).await?;
+        // Create message as owner.
+        let row = MessageModel::create(
+            owner.id,
+            channel_id,
+            message.to_string(),
+            None,
+            &AuditContext::default(),
+            &pool,
+        )
+        .await?;
+        // Delete message as other user.
+        let result = MessageModel::delete(
+            row.id,
+            other.id,
+            Role::User,
+            &AuditContext::default(),
+            &pool,
+        )
+        .await;
+        assert_eq!(result.unwrap_err().to_string(), "Forbidden.");
+        // Check if message still exists.
+        let rows = query!(
+            r#"
+            select id
+            from message
+            "#
+        )
+        .fetch_all(&pool)
+        .await?;
+        assert_eq!(rows.len(), 1);
+        Ok(())
+    }
+
+    #[sqlx::test]
+    async fn modify_and_delete_by_moderator(pool: PgPool) -> Result<()> {
+        // Create users.
+        let owner = UsersModel::create(
+            "owner".to_string(),
+            "owner@example.com".to_string(),
+            "password".to_string(),
+            &AuditContext::default(),
+            &pool,
+        )
+        .await?;
+        let moderator = UsersModel::create(
+            "moderator".to_string(),
+            "moderator@example.com".to_string(),
+            "password".to_string(),
+            &AuditContext::default(),
+            &pool,
+        )
+        .await?;
+        let channel_id = create_channel(owner.id, &pool).await?;
+        let row = MessageModel::create(
+            owner.id,
+            channel_id,
+            "spam".to_string(),
+            None,
+            &AuditContext::default(),
+            &pool,
+        )
+        .await?;
+
+        // Moderators can edit and delete other users' messages.
+        let modified = MessageModel::modify(
+            row.id,
+            moderator.id,
+            Role::Moderator,
+            "[removed]".to_string(),
+            &AuditContext::default(),
+            &pool,
+        )
+        .await?;
+        assert_eq!(modified.message, "[removed]");
+        MessageModel::delete(
+            row.id,
+            moderator.id,
+            Role::Admin,
+            &AuditContext::default(),
+            &pool,
+        )
+        .await?;
+        // Both are recorded as moderator actions, and the edit revision names the owner
+        // who wrote the replaced body.
+        let actions = query!(
+            r#"
+            select moderator_id, kind::text as "kind!"
+            from moderator_action
+            where message_id = $1
+            order by id
+            "#,
+            row.id
+        )
+        .fetch_all(&pool)
+        .await?;
+        assert_eq!(
+            actions
+                .iter()
+                .map(|a| (a.moderator_id, a.kind.as_str()))
+                .collect::<Vec<_>>(),
+            vec![
+                (Some(moderator.id), "edit_message"),
+                (Some(moderator.id), "delete_message")
+            ]
+        );
+        let revision = query!(
+            r#"
+            select editor_id
+            from message_revision
+            where message_id = $1
+            "#,
+            row.id
+        )
+        .fetch_one(&pool)
+        .await?;
+        assert_eq!(revision.editor_id, Some(owner.id));
+
+        // Owners' own edits are not moderator actions.
+        let row = MessageModel::create(
+            owner.id,
+            channel_id,
+            "mine".to_string(),
+            None,
+            &AuditContext::default(),
+            &pool,
+        )
+        .await?;
+        MessageModel::modify(
+            row.id,
+            owner.id,
+            Role::Moderator,
+            "edited".to_string(),
+            &AuditContext::default(),
+            &pool,
+        )
+        .await?;
+        let count = query!(
+            r#"
+            select count(*) as "count!"
+            from moderator_action
+            where message_id = $1
+            "#,
+            row.id
+        )
+        .fetch_one(&pool)
+        .await?;
+        assert_eq!(count.count, 0);
+        Ok(())
+    }
+
+    #[sqlx::test]
+    async fn publish_events(pool: PgPool) -> Result<()> {
+        let message = "event test message";
+        // Create user.
+        let user = UsersModel::create(
+            "test".to_string(),
+            "test@example.com".to_string(),
+            "password".to_string(),
+            &AuditContext::default(),
+            &pool,
+        )
+        .await?;
+        let channel_id = create_channel(user.id, &pool).await?;
+        let mut receiver = events::subscribe();
+        // Create, reply to, modify and delete messages.
+        let root = MessageModel::create(
+            user.id,
+            channel_id,
+            message.to_string(),
+            None,
+            &AuditContext::default(),
+            &pool,
+        )
+        .await?;
+        let reply = MessageModel::create(
+            user.id,
+            channel_id,
+            message.to_string(),
+            Some(root.id),
+            &AuditContext::default(),
+            &pool,
+        )
+        .await?;
+        MessageModel::modify(
+            reply.id,
+            user.id,
+            Role::User,
+            message.to_string(),
+            &AuditContext::default(),
+            &pool,
+        )
+        .await?;
+        MessageModel::delete(
+            reply.id,
+            user.id,
+            Role::User,
+            &AuditContext::default(),
+            &pool,
+        )
+        .await?;
+
+        // Other tests publish to the same channel, so only look at this test's messages.
+        let mut received = Vec::new();
+        while received.len() < 4 {
+            let event =
+                tokio::time::timeout(std::time::Duration::from_secs(5), receiver.recv()).await??;
+            if event.message.message == message {
+                received.push((event.kind, event.message.id, event.thread_root_id));
+            }
+        }
+        assert_eq!(
+            received,
+            vec![
+                (MessageEventKind::Created, root.id, root.id),
+                (MessageEventKind::Created, reply.id, root.id),
+                (MessageEventKind::Updated, reply.id, root.id),
+                (MessageEventKind::Deleted, reply.id, root.id),
+            ]
+        );
+        Ok(())
+    }
+
+    #[sqlx::test]
+    async fn find_by_user_id_and_time_range(pool: PgPool) -> Result<()> {
+        let user_id = 1;
+        let message = "test message".to_string();
+        // Create user.
+        query!(
+            r#"
+            insert into users (id, name, password, email)
+            values ($1, $2, $3, $4)
+            "#,
+            user_id,
+            "test",
+            "test",
+            "test@example.com"
+        )
+        .execute(&pool)
+        .await?;
+        let channel_id = create_channel(user_id, &pool).await?;
+        // Create message.
+        let message_time = chrono::Utc::now();
+        query!(
             r#"
-            insert into message (user_id, message, message_time)
-            values ($1, $2, $3)
+            insert into message (user_id, channel_id, message, message_time)
+            values ($1, $2, $3, $4)
             "#,
             user_id,
+            channel_id,
             message.clone(),
             message_time
         )
@@ -450,23 +1654,36 @@ mod tests {
         .await?;
         // Find message.
         // Find by user_id.
-        let rows = MessageModel::find_by_user_id_and_time_range(user_id, None, None, &pool).await?;
-        assert_eq!(rows.len(), 1);
-        // Find by user_id and start_time.
         let rows =
-            MessageModel::find_by_user_id_and_time_range(user_id, Some(message_time), None, &pool)
+            MessageModel::find_by_user_id_and_time_range(user_id, None, None, user_id, &pool)
                 .await?;
         assert_eq!(rows.len(), 1);
+        // Find by user_id and start_time.
+        let rows = MessageModel::find_by_user_id_and_time_range(
+            user_id,
+            Some(message_time),
+            None,
+            user_id,
+            &pool,
+        )
+        .await?;
+        assert_eq!(rows.len(), 1);
         // Find by user_id and end_time.
-        let rows =
-            MessageModel::find_by_user_id_and_time_range(user_id, None, Some(message_time), &pool)
-                .await?;
+        let rows = MessageModel::find_by_user_id_and_time_range(
+            user_id,
+            None,
+            Some(message_time),
+            user_id,
+            &pool,
+        )
+        .await?;
         assert_eq!(rows.len(), 1);
         // Find by user_id and start_time and end_time.
         let rows = MessageModel::find_by_user_id_and_time_range(
             user_id,
             Some(message_time),
             Some(message_time),
+            user_id,
             &pool,
         )
         .await?;
@@ -491,13 +1708,15 @@ mod tests {
         )
         .execute(&pool)
         .await?;
+        let channel_id = create_channel(user_id, &pool).await?;
         // Create message1.
         query!(
             r#"
-            insert into message (user_id, message, message_time)
-            values ($1, $2, now())
+            insert into message (user_id, channel_id, message, message_time)
+            values ($1, $2, $3, now())
             "#,
             user_id,
+            channel_id,
             message.clone(),
         )
         .execute(&pool)
@@ -505,10 +1724,11 @@ mod tests {
         // Create message2.
         query!(
             r#"
-            insert into message (user_id, message, parent_id, message_time)
-            values ($1, $2, $3, now())
+            insert into message (user_id, channel_id, message, parent_id, message_time)
+            values ($1, $2, $3, $4, now())
             "#,
             user_id,
+            channel_id,
             message.clone(),
             1,
         )
@@ -516,8 +1736,598 @@ mod tests {
         .await?;
 
         // Find thread.
-        let rows = MessageModel::find_messages_by_id(user_id, &pool).await?;
+        let rows = MessageModel::find_messages_by_id(user_id, user_id, &pool).await?;
+        assert_eq!(rows.len(), 2);
+        Ok(())
+    }
+
+    #[sqlx::test]
+    async fn find_page_by_user_id_and_time_range(pool: PgPool) -> Result<()> {
+        let user_id = 1;
+        // Create user.
+        query!(
+            r#"
+            insert into users (id, name, password, email)
+            values ($1, $2, $3, $4)
+            "#,
+            user_id,
+            "test",
+            "test",
+            "test@example.com"
+        )
+        .execute(&pool)
+        .await?;
+        let channel_id = create_channel(user_id, &pool).await?;
+        // Create 5 messages sharing the same message_time.
+        query!(
+            r#"
+            insert into message (user_id, channel_id, message, message_time)
+            select $1, $2, 'test message', now()
+            from generate_series(1, 5)
+            "#,
+            user_id,
+            channel_id
+        )
+        .execute(&pool)
+        .await?;
+
+        // Page forward.
+        let args = PageArgs::new(None, None, Some(2), None)?;
+        let page = MessageModel::find_page_by_user_id_and_time_range(
+            user_id, None, None, args, user_id, &pool,
+        )
+        .await?;
+        assert_eq!(
+            page.rows.iter().map(|r| r.id).collect::<Vec<_>>(),
+            vec![1, 2]
+        );
+        assert!(page.has_next_page);
+        let last = &page.rows[1];
+        let args = PageArgs::new(
+            Some(Cursor::new(last.message_time, last.id)),
+            None,
+            Some(5),
+            None,
+        )?;
+        let page = MessageModel::find_page_by_user_id_and_time_range(
+            user_id, None, None, args, user_id, &pool,
+        )
+        .await?;
+        assert_eq!(
+            page.rows.iter().map(|r| r.id).collect::<Vec<_>>(),
+            vec![3, 4, 5]
+        );
+        assert!(!page.has_next_page);
+
+        // Page backward.
+        let args = PageArgs::new(None, None, None, Some(2))?;
+        let page = MessageModel::find_page_by_user_id_and_time_range(
+            user_id, None, None, args, user_id, &pool,
+        )
+        .await?;
+        assert_eq!(
+            page.rows.iter().map(|r| r.id).collect::<Vec<_>>(),
+            vec![4, 5]
+        );
+        assert!(page.has_previous_page);
+        let first = &page.rows[0];
+        let args = PageArgs::new(
+            None,
+            Some(Cursor::new(first.message_time, first.id)),
+            None,
+            Some(5),
+        )?;
+        let page = MessageModel::find_page_by_user_id_and_time_range(
+            user_id, None, None, args, user_id, &pool,
+        )
+        .await?;
+        assert_eq!(
+            page.rows.iter().map(|r| r.id).collect::<Vec<_>>(),
+            vec![1, 2, 3]
+        );
+        assert!(!page.has_previous_page);
+
+        // Both first and last.
+        assert!(PageArgs::new(None, None, Some(1), Some(1)).is_err());
+        Ok(())
+    }
+
+    #[sqlx::test]
+    async fn find_message_page_by_id(pool: PgPool) -> Result<()> {
+        // Create user.
+        let user = UsersModel::create(
+            "test".to_string(),
+            "test@example.com".to_string(),
+            "password".to_string(),
+            &AuditContext::default(),
+            &pool,
+        )
+        .await?;
+        let channel_id = create_channel(user.id, &pool).await?;
+        // Create a thread of 3 messages and an unrelated message.
+        let root = MessageModel::create(
+            user.id,
+            channel_id,
+            "root".to_string(),
+            None,
+            &AuditContext::default(),
+            &pool,
+        )
+        .await?;
+        let reply = MessageModel::create(
+            user.id,
+            channel_id,
+            "reply".to_string(),
+            Some(root.id),
+            &AuditContext::default(),
+            &pool,
+        )
+        .await?;
+        MessageModel::create(
+            user.id,
+            channel_id,
+            "other".to_string(),
+            None,
+            &AuditContext::default(),
+            &pool,
+        )
+        .await?;
+        let nested = MessageModel::create(
+            user.id,
+            channel_id,
+            "nested".to_string(),
+            Some(reply.id),
+            &AuditContext::default(),
+            &pool,
+        )
+        .await?;
+
+        // Find thread.
+        let args = PageArgs::new(None, None, Some(2), None)?;
+        let page = MessageModel::find_message_page_by_id(root.id, args, user.id, &pool).await?;
+        assert_eq!(
+            page.rows.iter().map(|r| r.id).collect::<Vec<_>>(),
+            vec![root.id, reply.id]
+        );
+        assert!(page.has_next_page);
+        let args = PageArgs::new(
+            Some(Cursor::new(reply.message_time, reply.id)),
+            None,
+            Some(2),
+            None,
+        )?;
+        let page = MessageModel::find_message_page_by_id(root.id, args, user.id, &pool).await?;
+        assert_eq!(
+            page.rows.iter().map(|r| r.id).collect::<Vec<_>>(),
+            vec![nested.id]
+        );
+        assert!(!page.has_next_page);
+        Ok(())
+    }
+
+    #[sqlx::test]
+    async fn find_by_user_id_and_time_range_limit(pool: PgPool) -> Result<()> {
+        let user_id = 1;
+        // Create user.
+        query!(
+            r#"
+            insert into users (id, name, password, email)
+            values ($1, $2, $3, $4)
+            "#,
+            user_id,
+            "test",
+            "test",
+            "test@example.com"
+        )
+        .execute(&pool)
+        .await?;
+        let channel_id = create_channel(user_id, &pool).await?;
+        // Create more messages than the list limit.
+        query!(
+            r#"
+            insert into message (user_id, channel_id, message, message_time)
+            select $1, $2, 'test message', now()
+            from generate_series(1, $3)
+            "#,
+            user_id,
+            channel_id,
+            MAX_LIST_SIZE as i32 + 1
+        )
+        .execute(&pool)
+        .await?;
+        let rows =
+            MessageModel::find_by_user_id_and_time_range(user_id, None, None, user_id, &pool)
+                .await?;
+        assert_eq!(rows.len() as i64, MAX_LIST_SIZE);
+        Ok(())
+    }
+
+    #[sqlx::test]
+    async fn find_by_ids(pool: PgPool) -> Result<()> {
+        // Create user.
+        let user = UsersModel::create(
+            "test".to_string(),
+            "test@example.com".to_string(),
+            "password".to_string(),
+            &AuditContext::default(),
+            &pool,
+        )
+        .await?;
+        let channel_id = create_channel(user.id, &pool).await?;
+        // Create messages.
+        let first = MessageModel::create(
+            user.id,
+            channel_id,
+            "first".to_string(),
+            None,
+            &AuditContext::default(),
+            &pool,
+        )
+        .await?;
+        let second = MessageModel::create(
+            user.id,
+            channel_id,
+            "second".to_string(),
+            None,
+            &AuditContext::default(),
+            &pool,
+        )
+        .await?;
+
+        let rows = MessageModel::find_by_ids(&[first.id, second.id, 0], &pool).await?;
+        assert_eq!(rows.len(), 2);
+        Ok(())
+    }
+
+    #[sqlx::test]
+    async fn thread_structure(pool: PgPool) -> Result<()> {
+        // Create user.
+        let user = UsersModel::create(
+            "test".to_string(),
+            "test@example.com".to_string(),
+            "password".to_string(),
+            &AuditContext::default(),
+            &pool,
+        )
+        .await?;
+        let channel_id = create_channel(user.id, &pool).await?;
+        // Create two threads.
+        let root = MessageModel::create(
+            user.id,
+            channel_id,
+            "root".to_string(),
+            None,
+            &AuditContext::default(),
+            &pool,
+        )
+        .await?;
+        let first = MessageModel::create(
+            user.id,
+            channel_id,
+            "first".to_string(),
+            Some(root.id),
+            &AuditContext::default(),
+            &pool,
+        )
+        .await?;
+        let second = MessageModel::create(
+            user.id,
+            channel_id,
+            "second".to_string(),
+            Some(root.id),
+            &AuditContext::default(),
+            &pool,
+        )
+        .await?;
+        let nested = MessageModel::create(
+            user.id,
+            channel_id,
+            "nested".to_string(),
+            Some(first.id),
+            &AuditContext::default(),
+            &pool,
+        )
+        .await?;
+        let other = MessageModel::create(
+            user.id,
+            channel_id,
+            "other".to_string(),
+            None,
+            &AuditContext::default(),
+            &pool,
+        )
+        .await?;
+
+        // Count replies.
+        let counts = MessageModel::count_replies(&[root.id, first.id, other.id], &pool).await?;
+        assert_eq!(counts.get(&root.id), Some(&2));
+        assert_eq!(counts.get(&first.id), Some(&1));
+        assert_eq!(counts.get(&other.id), None);
+
+        // Find thread roots.
+        let roots =
+            MessageModel::find_thread_root_ids(&[root.id, nested.id, other.id], &pool).await?;
+        assert_eq!(roots.get(&root.id), Some(&root.id));
+        assert_eq!(roots.get(&nested.id), Some(&root.id));
+        assert_eq!(roots.get(&other.id), Some(&other.id));
+
+        // Find reply pages.
+        let args = PageArgs::new(None, None, Some(1), None)?;
+        let pages =
+            MessageModel::find_reply_pages(&[root.id, first.id, other.id], args, &pool).await?;
+        assert_eq!(pages[&root.id].rows[0].id, first.id);
+        assert!(pages[&root.id].has_next_page);
+        assert_eq!(pages[&first.id].rows[0].id, nested.id);
+        assert!(!pages[&first.id].has_next_page);
+        assert!(pages[&other.id].rows.is_empty());
+        let args = PageArgs::new(
+            Some(Cursor::new(first.message_time, first.id)),
+            None,
+            Some(1),
+            None,
+        )?;
+        let pages = MessageModel::find_reply_pages(&[root.id], args, &pool).await?;
+        assert_eq!(pages[&root.id].rows[0].id, second.id);
+        assert!(!pages[&root.id].has_next_page);
+        Ok(())
+    }
+
+    #[sqlx::test]
+    async fn deleted_messages_in_listings(pool: PgPool) -> Result<()> {
+        // Create user.
+        let user = UsersModel::create(
+            "test".to_string(),
+            "test@example.com".to_string(),
+            "password".to_string(),
+            &AuditContext::default(),
+            &pool,
+        )
+        .await?;
+        let channel_id = create_channel(user.id, &pool).await?;
+        // Create a thread and delete the middle message.
+        let root = MessageModel::create(
+            user.id,
+            channel_id,
+            "root".to_string(),
+            None,
+            &AuditContext::default(),
+            &pool,
+        )
+        .await?;
+        let reply = MessageModel::create(
+            user.id,
+            channel_id,
+            "reply".to_string(),
+            Some(root.id),
+            &AuditContext::default(),
+            &pool,
+        )
+        .await?;
+        MessageModel::create(
+            user.id,
+            channel_id,
+            "nested".to_string(),
+            Some(reply.id),
+            &AuditContext::default(),
+            &pool,
+        )
+        .await?;
+        MessageModel::delete(
+            reply.id,
+            user.id,
+            Role::User,
+            &AuditContext::default(),
+            &pool,
+        )
+        .await?;
+
+        // User listings skip deleted messages.
+        let rows =
+            MessageModel::find_by_user_id_and_time_range(user.id, None, None, user.id, &pool)
+                .await?;
         assert_eq!(rows.len(), 2);
+        let page = MessageModel::find_page_by_user_id_and_time_range(
+            user.id,
+            None,
+            None,
+            PageArgs::default(),
+            user.id,
+            &pool,
+        )
+        .await?;
+        assert_eq!(page.rows.len(), 2);
+        // Threads keep the deleted message as a tombstone.
+        let rows = MessageModel::find_messages_by_id(root.id, user.id, &pool).await?;
+        assert_eq!(rows.len(), 3);
+        assert!(rows
+            .iter()
+            .any(|r| r.id == reply.id && r.deleted_at.is_some()));
+        // Replying to a deleted message fails.
+        let result = MessageModel::create(
+            user.id,
+            channel_id,
+            "late".to_string(),
+            Some(reply.id),
+            &AuditContext::default(),
+            &pool,
+        )
+        .await;
+        assert_eq!(result.unwrap_err().to_string(), "Parent message not found.");
+        Ok(())
+    }
+
+    #[sqlx::test]
+    async fn purge(pool: PgPool) -> Result<()> {
+        // Create user.
+        let user = UsersModel::create(
+            "test".to_string(),
+            "test@example.com".to_string(),
+            "password".to_string(),
+            &AuditContext::default(),
+            &pool,
+        )
+        .await?;
+        let channel_id = create_channel(user.id, &pool).await?;
+        // Create a thread.
+        let root = MessageModel::create(
+            user.id,
+            channel_id,
+            "root".to_string(),
+            None,
+            &AuditContext::default(),
+            &pool,
+        )
+        .await?;
+        let reply = MessageModel::create(
+            user.id,
+            channel_id,
+            "reply".to_string(),
+            Some(root.id),
+            &AuditContext::default(),
+            &pool,
+        )
+        .await?;
+        let nested = MessageModel::create(
+            user.id,
+            channel_id,
+            "nested".to_string(),
+            Some(reply.id),
+            &AuditContext::default(),
+            &pool,
+        )
+        .await?;
+        MessageModel::delete(
+            reply.id,
+            user.id,
+            Role::User,
+            &AuditContext::default(),
+            &pool,
+        )
+        .await?;
+
+        // Purge the deleted reply with its replies.
+        let mut ids = MessageModel::purge(reply.id, &AuditContext::default(), &pool).await?;
+        ids.sort();
+        assert_eq!(ids, vec![reply.id, nested.id]);
+        let rows = MessageModel::find_messages_by_id(root.id, user.id, &pool).await?;
+        assert_eq!(rows.len(), 1);
+        // Purge missing message.
+        let result = MessageModel::purge(reply.id, &AuditContext::default(), &pool).await;
+        assert_eq!(result.unwrap_err().to_string(), "Message not found.");
+        Ok(())
+    }
+
+    #[sqlx::test]
+    async fn parent_foreign_key(pool: PgPool) -> Result<()> {
+        // Create user.
+        let user = UsersModel::create(
+            "test".to_string(),
+            "test@example.com".to_string(),
+            "password".to_string(),
+            &AuditContext::default(),
+            &pool,
+        )
+        .await?;
+        let channel_id = create_channel(user.id, &pool).await?;
+        // Insert message with missing parent.
+        let result = query!(
+            r#"
+            insert into message (user_id, channel_id, message, parent_id, message_time)
+            values ($1, $2, $3, $4, now())
+            "#,
+            user.id,
+            channel_id,
+            "orphan",
+            1000
+        )
+        .execute(&pool)
+        .await;
+        assert!(result.is_err());
+        Ok(())
+    }
+
+    #[sqlx::test]
+    async fn channel_membership(pool: PgPool) -> Result<()> {
+        // Create users.
+        let owner = UsersModel::create(
+            "owner".to_string(),
+            "owner@example.com".to_string(),
+            "password".to_string(),
+            &AuditContext::default(),
+            &pool,
+        )
+        .await?;
+        let other = UsersModel::create(
+            "other".to_string(),
+            "other@example.com".to_string(),
+            "password".to_string(),
+            &AuditContext::default(),
+            &pool,
+        )
+        .await?;
+        let channel_id = create_channel(owner.id, &pool).await?;
+        let root = MessageModel::create(
+            owner.id,
+            channel_id,
+            "root".to_string(),
+            None,
+            &AuditContext::default(),
+            &pool,
+        )
+        .await?;
+
+        // Non-members can neither post nor read.
+        let result = MessageModel::create(
+            other.id,
+            channel_id,
+            "intruder".to_string(),
+            None,
+            &AuditContext::default(),
+            &pool,
+        )
+        .await;
+        assert!(matches!(result, Err(ModelError::Forbidden(_))));
+        let rows =
+            MessageModel::find_by_user_id_and_time_range(owner.id, None, None, other.id, &pool)
+                .await?;
+        assert!(rows.is_empty());
+        let result = MessageModel::find_messages_by_id(root.id, other.id, &pool).await;
+        assert!(matches!(result, Err(ModelError::NotFound(_))));
+        let result =
+            MessageModel::find_page_by_channel_id(channel_id, PageArgs::default(), other.id, &pool)
+                .await;
+        assert!(matches!(result, Err(ModelError::NotFound(_))));
+
+        // Members can.
+        ChannelModel::join(channel_id, other.id, &AuditContext::default(), &pool).await?;
+        let rows = MessageModel::find_messages_by_id(root.id, other.id, &pool).await?;
+        assert_eq!(rows.len(), 1);
+        let page =
+            MessageModel::find_page_by_channel_id(channel_id, PageArgs::default(), other.id, &pool)
+                .await?;
+        assert_eq!(
+            page.rows.iter().map(|m| m.id).collect::<Vec<_>>(),
+            vec![root.id]
+        );
+
+        // Replies stay in the channel of their parent.
+        let elsewhere = ChannelModel::create(
+            "elsewhere".to_string(),
+            other.id,
+            &AuditContext::default(),
+            &pool,
+        )
+        .await?;
+        let result = MessageModel::create(
+            other.id,
+            elsewhere.id,
+            "reply".to_string(),
+            Some(root.id),
+            &AuditContext::default(),
+            &pool,
+        )
+        .await;
+        assert!(matches!(result, Err(ModelError::Validation(_))));
         Ok(())
     }
 }
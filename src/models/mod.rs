@@ -1,2 +1,20 @@
+pub mod audit;
+pub mod channel;
+pub mod conversation;
+pub mod entity;
+pub mod error;
+pub mod events;
+pub mod jwt;
+pub mod login_failure;
+pub mod notification;
+pub mod oidc;
+pub mod pagination;
+pub mod reaction;
+pub mod report;
+pub mod revision;
+pub mod search;
+pub mod session;
+pub mod token;
+pub mod totp;
 pub mod users;
-pub mod message;
\ No newline at end of file
+pub mod message;
@@ -0,0 +1,326 @@
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use serde_json::json;
+use sqlx::{query, query_as, PgConnection, PgPool};
+
+use super::{
+    audit::AuditContext,
+    error::ModelError,
+    pagination::{Page, PageArgs, MAX_LIST_SIZE},
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Deserialize, serde::Serialize, sqlx::Type)]
+#[sqlx(type_name = "notification_kind", rename_all = "lowercase")]
+pub enum NotificationKind {
+    Reply,
+    Mention,
+}
+
+#[derive(Debug, Clone, serde::Deserialize, serde::Serialize)]
+pub struct NotificationModel {
+    pub id: i32,
+    pub user_id: i32,
+    pub kind: NotificationKind,
+    pub message_id: i32,
+    pub actor_id: i32,
+    #[serde(rename = "createdAt")]
+    pub created_at: DateTime<Utc>,
+    #[serde(rename = "readAt")]
+    pub read_at: Option<DateTime<Utc>>,
+}
+
+impl NotificationModel {
+    pub async fn find_by_id(id: i32, pool: &PgPool) -> Result<NotificationModel, ModelError> {
+        query_as!(
+            NotificationModel,
+            r#"
+            select id, user_id, kind as "kind: NotificationKind", message_id, actor_id, created_at, read_at
+            from notification
+            where id = $1
+            "#,
+            id
+        )
+        .fetch_optional(pool)
+        .await?
+        .ok_or_else(|| ModelError::not_found("Notification not found."))
+    }
+
+    // Find a page of the user's notifications, newest first.
+    // Notifications of deleted or hidden messages, and of channels the user left, are left out.
+    // Only forward paging is supported.
+    pub async fn find_page_by_user_id(
+        user_id: i32,
+        unread_only: bool,
+        args: PageArgs,
+        pool: &PgPool,
+    ) -> Result<Page<NotificationModel>, ModelError> {
+        let rows = query_as!(
+            NotificationModel,
+            r#"
+            select n.id, n.user_id, n.kind as "kind: NotificationKind", n.message_id, n.actor_id, n.created_at, n.read_at
+            from notification n
+            inner join message m on m.id = n.message_id
+            inner join channel_member cm on cm.channel_id = m.channel_id and cm.user_id = n.user_id
+            where n.user_id = $1
+            and m.deleted_at is null
+            and m.hidden_at is null
+            and (not $2 or n.read_at is null)
+            and ($3::timestamptz is null or (n.created_at, n.id) < ($3, $4))
+            order by n.created_at desc, n.id desc
+            limit $5
+            "#,
+            user_id,
+            unread_only,
+            args.after_time(),
+            args.after_id(),
+            args.limit()
+        )
+        .fetch_all(pool)
+        .await?;
+
+        Ok(Page::new(rows, &args))
+    }
+
+    pub async fn count_unread(user_id: i32, pool: &PgPool) -> Result<i64, ModelError> {
+        let row = query!(
+            r#"
+            select count(*) as "count!"
+            from notification n
+            inner join message m on m.id = n.message_id
+            inner join channel_member cm on cm.channel_id = m.channel_id and cm.user_id = n.user_id
+            where n.user_id = $1
+            and n.read_at is null
+            and m.deleted_at is null
+            and m.hidden_at is null
+            "#,
+            user_id
+        )
+        .fetch_one(pool)
+        .await?;
+
+        Ok(row.count)
+    }
+
+    // Mark the user's notifications as read. Ids of other users' notifications are ignored.
+    pub async fn mark_read(
+        ids: &[i32],
+        user_id: i32,
+        audit: &AuditContext,
+        pool: &PgPool,
+    ) -> Result<Vec<NotificationModel>, ModelError> {
+        if ids.len() > MAX_LIST_SIZE as usize {
+            return Err(ModelError::validation("Too many notifications."));
+        }
+        let mut tx = pool.begin().await?;
+        let rows = query_as!(
+            NotificationModel,
+            r#"
+            update notification
+            set read_at = coalesce(read_at, now())
+            where id = any($1)
+            and user_id = $2
+            returning id, user_id, kind as "kind: NotificationKind", message_id, actor_id, created_at, read_at
+            "#,
+            ids,
+            user_id
+        )
+        .fetch_all(&mut *tx)
+        .await?;
+        let after = json!({ "ids": rows.iter().map(|row| row.id).collect::<Vec<_>>() });
+        audit
+            .record("markNotificationsRead", None, None, Some(after), &mut tx)
+            .await?;
+        tx.commit().await?;
+
+        Ok(rows)
+    }
+}
+
+// Notify the author of the parent message and the mentioned users of a new or
+// edited message, unless they wrote it or cannot read its channel.
+// Users are notified once per message, so edits only notify newly mentioned users.
+// Mentions must already be saved for the message.
+pub(super) async fn create_for_message(
+    message_id: i32,
+    conn: &mut PgConnection,
+) -> Result<Vec<NotificationModel>, ModelError> {
+    let rows = query_as!(
+        NotificationModel,
+        r#"
+        insert into notification (user_id, kind, message_id, actor_id)
+        select recipients.user_id, recipients.kind, m.id, m.user_id
+        from message m
+        cross join lateral (
+            select p.user_id, 'reply'::notification_kind as kind
+            from message p
+            where p.id = m.parent_id
+            and p.deleted_at is null
+            union all
+            select mm.user_id, 'mention'::notification_kind
+            from message_mention mm
+            where mm.message_id = m.id
+        ) recipients
+        where m.id = $1
+        and recipients.user_id <> m.user_id
+        and exists (
+            select 1
+            from channel_member cm
+            where cm.channel_id = m.channel_id
+            and cm.user_id = recipients.user_id
+        )
+        on conflict do nothing
+        returning id, user_id, kind as "kind: NotificationKind", message_id, actor_id, created_at, read_at
+        "#,
+        message_id
+    )
+    .fetch_all(&mut *conn)
+    .await?;
+
+    Ok(rows)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::{
+        channel::ChannelModel,
+        message::MessageModel,
+        users::{Role, UsersModel},
+    };
+
+    #[sqlx::test]
+    async fn notifications(pool: PgPool) -> Result<()> {
+        // Create users.
+        let alice = UsersModel::create(
+            "alice".to_string(),
+            "alice@example.com".to_string(),
+            "password".to_string(),
+            &AuditContext::default(),
+            &pool,
+        )
+        .await?;
+        let bob = UsersModel::create(
+            "bob".to_string(),
+            "bob@example.com".to_string(),
+            "password".to_string(),
+            &AuditContext::default(),
+            &pool,
+        )
+        .await?;
+        let carol = UsersModel::create(
+            "carol".to_string(),
+            "carol@example.com".to_string(),
+            "password".to_string(),
+            &AuditContext::default(),
+            &pool,
+        )
+        .await?;
+        let channel = ChannelModel::create(
+            "test".to_string(),
+            alice.id,
+            &AuditContext::default(),
+            &pool,
+        )
+        .await?;
+        ChannelModel::join(channel.id, bob.id, &AuditContext::default(), &pool).await?;
+
+        // Reply to alice and mention her, yourself and carol, who is not a member.
+        let root = MessageModel::create(
+            alice.id,
+            channel.id,
+            "root".to_string(),
+            None,
+            &AuditContext::default(),
+            &pool,
+        )
+        .await?;
+        let reply = MessageModel::create(
+            bob.id,
+            channel.id,
+            "@alice @bob @carol".to_string(),
+            Some(root.id),
+            &AuditContext::default(),
+            &pool,
+        )
+        .await?;
+        let page =
+            NotificationModel::find_page_by_user_id(alice.id, false, PageArgs::default(), &pool)
+                .await?;
+        assert_eq!(
+            page.rows
+                .iter()
+                .map(|n| (n.kind, n.message_id, n.actor_id))
+                .collect::<Vec<_>>(),
+            vec![
+                (NotificationKind::Mention, reply.id, bob.id),
+                (NotificationKind::Reply, reply.id, bob.id),
+            ]
+        );
+        assert_eq!(NotificationModel::count_unread(bob.id, &pool).await?, 0);
+        assert_eq!(NotificationModel::count_unread(carol.id, &pool).await?, 0);
+
+        // Edits only notify newly mentioned users.
+        MessageModel::modify(
+            reply.id,
+            bob.id,
+            Role::User,
+            "@alice again".to_string(),
+            &AuditContext::default(),
+            &pool,
+        )
+        .await?;
+        assert_eq!(NotificationModel::count_unread(alice.id, &pool).await?, 2);
+
+        // Mark one as read, ignoring other users' notifications.
+        let ids = vec![page.rows[0].id];
+        let rows =
+            NotificationModel::mark_read(&ids, bob.id, &AuditContext::default(), &pool).await?;
+        assert!(rows.is_empty());
+        let rows =
+            NotificationModel::mark_read(&ids, alice.id, &AuditContext::default(), &pool).await?;
+        assert!(rows[0].read_at.is_some());
+        assert_eq!(NotificationModel::count_unread(alice.id, &pool).await?, 1);
+        let page =
+            NotificationModel::find_page_by_user_id(alice.id, true, PageArgs::default(), &pool)
+                .await?;
+        assert_eq!(page.rows[0].kind, NotificationKind::Reply);
+
+        // Notifications of deleted messages are hidden.
+        MessageModel::delete(
+            reply.id,
+            bob.id,
+            Role::User,
+            &AuditContext::default(),
+            &pool,
+        )
+        .await?;
+        assert_eq!(NotificationModel::count_unread(alice.id, &pool).await?, 0);
+
+        // And so are those of channels the user left, whose messages she can no longer load.
+        let other =
+            ChannelModel::create("other".to_string(), bob.id, &AuditContext::default(), &pool)
+                .await?;
+        ChannelModel::join(other.id, alice.id, &AuditContext::default(), &pool).await?;
+        let mention = MessageModel::create(
+            bob.id,
+            other.id,
+            "@alice".to_string(),
+            None,
+            &AuditContext::default(),
+            &pool,
+        )
+        .await?;
+        assert_eq!(NotificationModel::count_unread(alice.id, &pool).await?, 1);
+        let rows = MessageModel::find_by_ids_for_viewer(&[mention.id], alice.id, &pool).await?;
+        assert_eq!(rows.len(), 1);
+        ChannelModel::leave(other.id, alice.id, &AuditContext::default(), &pool).await?;
+        assert_eq!(NotificationModel::count_unread(alice.id, &pool).await?, 0);
+        let page =
+            NotificationModel::find_page_by_user_id(alice.id, false, PageArgs::default(), &pool)
+                .await?;
+        assert!(page.rows.iter().all(|n| n.message_id != mention.id));
+        let rows = MessageModel::find_by_ids_for_viewer(&[mention.id], alice.id, &pool).await?;
+        assert!(rows.is_empty());
+        Ok(())
+    }
+}
@@ -0,0 +1,323 @@
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use sqlx::{query, PgPool};
+
+use super::{
+    channel,
+    error::{is_unique_violation, ModelError},
+    session::{generate_token, hash_token},
+    users::hash_password,
+};
+
+pub const LOGIN_TTL_MINUTES: i64 = 10;
+const MAX_NAME_LENGTH: usize = 255;
+
+// A login sent to the provider. The state comes back with the user, and the verifier
+// and nonce are checked against the provider's responses.
+#[derive(Debug, Clone)]
+pub struct PendingLogin {
+    pub state: String,
+    pub code_verifier: String,
+    pub nonce: String,
+}
+
+impl PendingLogin {
+    // The PKCE S256 challenge sent with the authorization request.
+    pub fn code_challenge(&self) -> String {
+        URL_SAFE_NO_PAD.encode(Sha256::digest(self.code_verifier.as_bytes()))
+    }
+}
+
+// An account at the provider, from a verified ID token.
+#[derive(Debug, Clone)]
+pub struct ExternalIdentity {
+    pub issuer: String,
+    pub subject: String,
+    pub email: Option<String>,
+    pub email_verified: bool,
+    pub name: Option<String>,
+}
+
+// How an identity was matched to a user.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum IdentityLink {
+    // Logged in with before.
+    Existing,
+    // Linked to the user with the same email.
+    Linked,
+    // A new user was created for it.
+    Created,
+}
+
+pub struct OidcModel;
+
+impl OidcModel {
+    pub async fn start_login(pool: &PgPool) -> Result<PendingLogin, ModelError> {
+        let login = PendingLogin {
+            state: generate_token(),
+            code_verifier: generate_token(),
+            nonce: generate_token(),
+        };
+        query!(
+            r#"
+            delete from oidc_login
+            where expires_at <= now()
+            "#
+        )
+        .execute(pool)
+        .await?;
+        query!(
+            r#"
+            insert into oidc_login (state_hash, code_verifier, nonce, expires_at)
+            values ($1, $2, $3, now() + make_interval(mins => $4))
+            "#,
+            hash_token(&login.state),
+            login.code_verifier,
+            login.nonce,
+            LOGIN_TTL_MINUTES as i32
+        )
+        .execute(pool)
+        .await?;
+        Ok(login)
+    }
+
+    // Each login can only be finished once.
+    pub async fn finish_login(state: &str, pool: &PgPool) -> Result<PendingLogin, ModelError> {
+        let row = query!(
+            r#"
+            delete from oidc_login
+            where state_hash = $1
+            returning code_verifier, nonce, expires_at > now() as "active!"
+            "#,
+            hash_token(state)
+        )
+        .fetch_optional(pool)
+        .await?
+        .filter(|row| row.active)
+        .ok_or_else(|| ModelError::unauthenticated("Invalid or expired login."))?;
+        Ok(PendingLogin {
+            state: state.to_string(),
+            code_verifier: row.code_verifier,
+            nonce: row.nonce,
+        })
+    }
+
+    // Find the user of an identity. Identities seen for the first time need an email
+    // verified by the provider. They are linked to the user with the same email if the
+    // user verified it too, or get a new user otherwise.
+    pub async fn find_or_create_user(
+        identity: &ExternalIdentity,
+        pool: &PgPool,
+    ) -> Result<(i32, IdentityLink), ModelError> {
+        let row = query!(
+            r#"
+            select user_id
+            from user_identity
+            where issuer = $1 and subject = $2
+            "#,
+            identity.issuer,
+            identity.subject
+        )
+        .fetch_optional(pool)
+        .await?;
+        if let Some(row) = row {
+            return Ok((row.user_id, IdentityLink::Existing));
+        }
+
+        let email = identity
+            .email
+            .as_deref()
+            .map(str::trim)
+            .filter(|email| !email.is_empty())
+            .ok_or_else(|| ModelError::validation("The identity provider shared no email."))?;
+        // Otherwise anyone could claim an account, or keep its owner from signing up, by
+        // entering its email at a provider.
+        if !identity.email_verified {
+            return Err(ModelError::forbidden(
+                "The identity provider has not verified the email.",
+            ));
+        }
+        let mut tx = pool.begin().await?;
+        let user = query!(
+            r#"
+            select id, email_verified_at is not null as "email_verified!"
+            from users
+            where lower(email) = lower($1)
+            "#,
+            email
+        )
+        .fetch_optional(&mut *tx)
+        .await?;
+        let (user_id, link) = match user {
+            // Otherwise someone could register the email first, and keep access with
+            // their password once its owner logs in through the provider.
+            Some(user) if !user.email_verified => {
+                return Err(ModelError::conflict(
+                    "Email already registered and not verified. Verify it before logging in through the identity provider.",
+                ))
+            }
+            Some(user) => (user.id, IdentityLink::Linked),
+            None => {
+                // The password is random, so it can only be set with a password reset.
+                let row = query!(
+                    r#"
+                    insert into users (name, email, password, email_verified_at)
+                    values ($1, $2, $3, now())
+                    returning id
+                    "#,
+                    user_name(identity, email),
+                    email,
+                    hash_password(&generate_token())
+                )
+                .fetch_one(&mut *tx)
+                .await
+                .map_err(conflict)?;
+                channel::join_default(row.id, &mut tx).await?;
+                (row.id, IdentityLink::Created)
+            }
+        };
+        query!(
+            r#"
+            insert into user_identity (user_id, issuer, subject)
+            values ($1, $2, $3)
+            "#,
+            user_id,
+            identity.issuer,
+            identity.subject
+        )
+        .execute(&mut *tx)
+        .await
+        .map_err(conflict)?;
+        tx.commit().await?;
+
+        Ok((user_id, link))
+    }
+}
+
+// The name from the provider, or the start of the email.
+fn user_name(identity: &ExternalIdentity, email: &str) -> String {
+    let name = identity
+        .name
+        .as_deref()
+        .map(str::trim)
+        .filter(|name| !name.is_empty())
+        .unwrap_or_else(|| email.split('@').next().unwrap_or(email));
+    name.chars().take(MAX_NAME_LENGTH).collect()
+}
+
+// Another login for the same identity or email got there first.
+fn conflict(e: sqlx::Error) -> ModelError {
+    if is_unique_violation(&e) {
+        ModelError::conflict("Account changed during the login. Try again.")
+    } else {
+        e.into()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::{audit::AuditContext, token::UserTokenModel, users::UsersModel};
+    use anyhow::Result;
+
+    fn identity(subject: &str, email: &str, email_verified: bool) -> ExternalIdentity {
+        ExternalIdentity {
+            issuer: "https://idp.example.com".to_string(),
+            subject: subject.to_string(),
+            email: Some(email.to_string()),
+            email_verified,
+            name: None,
+        }
+    }
+
+    #[sqlx::test]
+    async fn pending_login(pool: PgPool) -> Result<()> {
+        let login = OidcModel::start_login(&pool).await?;
+        // RFC 7636, appendix B.
+        let example = PendingLogin {
+            code_verifier: "dBjftJeZ4CVP-mB92K27uhbUJU1p1r_wW1gFWFOEjXk".to_string(),
+            ..login.clone()
+        };
+        assert_eq!(
+            example.code_challenge(),
+            "E9Melhoa2OwvFrEMTJguCHaoeK1t8URWbuGJSstw-cM"
+        );
+
+        let finished = OidcModel::finish_login(&login.state, &pool).await?;
+        assert_eq!(finished.code_verifier, login.code_verifier);
+        assert_eq!(finished.nonce, login.nonce);
+        let result = OidcModel::finish_login(&login.state, &pool).await;
+        assert!(matches!(result, Err(ModelError::Unauthenticated(_))));
+        Ok(())
+    }
+
+    #[sqlx::test]
+    async fn find_or_create_user(pool: PgPool) -> Result<()> {
+        let user = UsersModel::create(
+            "test".to_string(),
+            "test@example.com".to_string(),
+            "password".to_string(),
+            &AuditContext::default(),
+            &pool,
+        )
+        .await?;
+
+        // An unverified email is not enough to take over an account.
+        let result =
+            OidcModel::find_or_create_user(&identity("1", "Test@example.com", false), &pool).await;
+        assert!(matches!(result, Err(ModelError::Forbidden(_))));
+        // Nor to link to an account that never verified it, which could be someone else's.
+        let result =
+            OidcModel::find_or_create_user(&identity("1", "Test@example.com", true), &pool).await;
+        assert!(matches!(result, Err(ModelError::Conflict(_))));
+        let issued = UserTokenModel::issue_email_verification(user.id, &pool).await?;
+        UserTokenModel::verify_email(&issued.token, &AuditContext::default(), &pool).await?;
+        let linked =
+            OidcModel::find_or_create_user(&identity("1", "Test@example.com", true), &pool).await?;
+        assert_eq!(linked, (user.id, IdentityLink::Linked));
+        let existing =
+            OidcModel::find_or_create_user(&identity("1", "changed@example.com", false), &pool)
+                .await?;
+        assert_eq!(existing, (user.id, IdentityLink::Existing));
+
+        let (created, link) =
+            OidcModel::find_or_create_user(&identity("2", "new@example.com", true), &pool).await?;
+        assert_eq!(link, IdentityLink::Created);
+        let row = query!(
+            r#"
+            select name, email_verified_at is not null as "verified!"
+            from users
+            where id = $1
+            "#,
+            created
+        )
+        .fetch_one(&pool)
+        .await?;
+        assert_eq!(row.name, "new");
+        assert!(row.verified);
+
+        // Nor to take an email for a new user.
+        let result =
+            OidcModel::find_or_create_user(&identity("3", "unverified@example.com", false), &pool)
+                .await;
+        assert!(matches!(result, Err(ModelError::Forbidden(_))));
+        let row = query!(
+            r#"
+            select count(*) as "count!"
+            from users
+            where email = 'unverified@example.com'
+            "#
+        )
+        .fetch_one(&pool)
+        .await?;
+        assert_eq!(row.count, 0);
+
+        let mut no_email = identity("4", "", true);
+        no_email.email = None;
+        let result = OidcModel::find_or_create_user(&no_email, &pool).await;
+        assert!(matches!(result, Err(ModelError::Validation(_))));
+        Ok(())
+    }
+}
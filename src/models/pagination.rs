@@ -0,0 +1,106 @@
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+
+use super::error::ModelError;
+
+pub const DEFAULT_PAGE_SIZE: usize = 20;
+pub const MAX_PAGE_SIZE: usize = 100;
+// Hard limit for the non-paginated list queries.
+pub const MAX_LIST_SIZE: i64 = 500;
+
+// Stable position of a row ordered by (time, id).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Cursor {
+    pub time: DateTime<Utc>,
+    pub id: i32,
+}
+
+impl Cursor {
+    pub fn new(time: DateTime<Utc>, id: i32) -> Self {
+        Cursor { time, id }
+    }
+}
+
+// Relay style arguments: `first`/`after` page forward, `last`/`before` page backward.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct PageArgs {
+    pub after: Option<Cursor>,
+    pub before: Option<Cursor>,
+    pub first: Option<usize>,
+    pub last: Option<usize>,
+}
+
+impl PageArgs {
+    pub fn new(
+        after: Option<Cursor>,
+        before: Option<Cursor>,
+        first: Option<usize>,
+        last: Option<usize>,
+    ) -> Result<Self, ModelError> {
+        if first.is_some() && last.is_some() {
+            return Err(ModelError::validation("Cannot use both first and last."));
+        }
+        Ok(PageArgs {
+            after,
+            before,
+            first,
+            last,
+        })
+    }
+
+    pub fn is_backward(&self) -> bool {
+        self.last.is_some()
+    }
+
+    pub fn size(&self) -> usize {
+        self.first
+            .or(self.last)
+            .unwrap_or(DEFAULT_PAGE_SIZE)
+            .min(MAX_PAGE_SIZE)
+    }
+
+    // One extra row is fetched to know whether another page exists.
+    pub fn limit(&self) -> i64 {
+        self.size() as i64 + 1
+    }
+
+    pub fn after_time(&self) -> Option<DateTime<Utc>> {
+        self.after.map(|c| c.time)
+    }
+
+    pub fn after_id(&self) -> Option<i32> {
+        self.after.map(|c| c.id)
+    }
+
+    pub fn before_time(&self) -> Option<DateTime<Utc>> {
+        self.before.map(|c| c.time)
+    }
+
+    pub fn before_id(&self) -> Option<i32> {
+        self.before.map(|c| c.id)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Page<T> {
+    pub rows: Vec<T>,
+    pub has_previous_page: bool,
+    pub has_next_page: bool,
+}
+
+impl<T> Page<T> {
+    // Build a page from rows fetched with `args.limit()`.
+    // Backward pages are fetched in descending order and put back in ascending order.
+    pub fn new(mut rows: Vec<T>, args: &PageArgs) -> Self {
+        let has_more = rows.len() > args.size();
+        rows.truncate(args.size());
+        if args.is_backward() {
+            rows.reverse();
+        }
+        Page {
+            rows,
+            has_previous_page: args.is_backward() && has_more,
+            has_next_page: !args.is_backward() && has_more,
+        }
+    }
+}
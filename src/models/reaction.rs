@@ -0,0 +1,342 @@
+use anyhow::Result;
+use serde_json::json;
+use sqlx::{query, query_as, PgPool};
+use std::collections::HashMap;
+
+use super::{
+    audit::AuditContext,
+    error::ModelError,
+    events::MessageEventKind,
+    message::{announce, MessageModelResponse},
+};
+
+// Long enough for emoji built from several code points, e.g. families and flags.
+const MAX_EMOJI_LENGTH: usize = 16;
+
+// The reactions of a message with the same emoji, counted together.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Deserialize, serde::Serialize)]
+pub struct ReactionModel {
+    pub emoji: String,
+    pub count: i64,
+    // Whether the viewer is one of the users who reacted.
+    pub reacted: bool,
+}
+
+impl ReactionModel {
+    // Reacting twice with the same emoji is a no-op.
+    pub async fn add(
+        message_id: i32,
+        user_id: i32,
+        emoji: String,
+        audit: &AuditContext,
+        pool: &PgPool,
+    ) -> Result<MessageModelResponse, ModelError> {
+        let emoji = emoji.trim().to_string();
+        if emoji.is_empty()
+            || emoji.chars().count() > MAX_EMOJI_LENGTH
+            || emoji
+                .chars()
+                .any(|c| c.is_whitespace() || c.is_ascii_alphabetic())
+        {
+            return Err(ModelError::validation("Invalid emoji."));
+        }
+        let message = find_message(message_id, user_id, pool).await?;
+
+        let mut tx = pool.begin().await?;
+        let result = query!(
+            r#"
+            insert into message_reaction (message_id, user_id, emoji)
+            values ($1, $2, $3)
+            on conflict do nothing
+            "#,
+            message_id,
+            user_id,
+            emoji
+        )
+        .execute(&mut *tx)
+        .await?;
+        let after = json!({ "emoji": emoji });
+        audit
+            .record("addReaction", Some(message_id), None, Some(after), &mut tx)
+            .await?;
+        tx.commit().await?;
+        if result.rows_affected() > 0 {
+            // Subscribers of the message re-read its reactions.
+            announce(MessageEventKind::Reacted, message.clone(), pool).await;
+        }
+
+        Ok(message)
+    }
+
+    pub async fn remove(
+        message_id: i32,
+        user_id: i32,
+        emoji: String,
+        audit: &AuditContext,
+        pool: &PgPool,
+    ) -> Result<MessageModelResponse, ModelError> {
+        let message = find_message(message_id, user_id, pool).await?;
+
+        let mut tx = pool.begin().await?;
+        let result = query!(
+            r#"
+            delete from message_reaction
+            where message_id = $1 and user_id = $2 and emoji = $3
+            "#,
+            message_id,
+            user_id,
+            emoji.trim()
+        )
+        .execute(&mut *tx)
+        .await?;
+        if result.rows_affected() == 0 {
+            return Err(ModelError::not_found("Reaction not found."));
+        }
+        let before = json!({ "emoji": emoji });
+        audit
+            .record(
+                "removeReaction",
+                Some(message_id),
+                Some(before),
+                None,
+                &mut tx,
+            )
+            .await?;
+        tx.commit().await?;
+        announce(MessageEventKind::Reacted, message.clone(), pool).await;
+
+        Ok(message)
+    }
+
+    // Count the reactions of each message, in the order each emoji was first used.
+    pub async fn find_by_message_ids(
+        message_ids: &[i32],
+        viewer_id: i32,
+        pool: &PgPool,
+    ) -> Result<HashMap<i32, Vec<ReactionModel>>, ModelError> {
+        let rows = query!(
+            r#"
+            select message_id,
+                emoji,
+                count(*) as "count!",
+                bool_or(user_id = $2) as "reacted!"
+            from message_reaction
+            where message_id = any($1)
+            group by message_id, emoji
+            order by message_id, min(created_at), emoji
+            "#,
+            message_ids,
+            viewer_id
+        )
+        .fetch_all(pool)
+        .await?;
+
+        let mut reactions: HashMap<i32, Vec<ReactionModel>> = HashMap::new();
+        for row in rows {
+            reactions
+                .entry(row.message_id)
+                .or_default()
+                .push(ReactionModel {
+                    emoji: row.emoji,
+                    count: row.count,
+                    reacted: row.reacted,
+                });
+        }
+        Ok(reactions)
+    }
+}
+
+// Only messages in the user's channels that are not deleted or hidden can be reacted to.
+async fn find_message(
+    id: i32,
+    user_id: i32,
+    pool: &PgPool,
+) -> Result<MessageModelResponse, ModelError> {
+    query_as!(
+        MessageModelResponse,
+        r#"
+        select m.id, m.user_id, m.channel_id, m.message, m.parent_id, m.message_time, m.edited_at, m.deleted_at, m.hidden_at
+        from message m
+        inner join channel_member cm on cm.channel_id = m.channel_id
+        where m.id = $1
+        and m.deleted_at is null
+        and m.hidden_at is null
+        and cm.user_id = $2
+        "#,
+        id,
+        user_id
+    )
+    .fetch_optional(pool)
+    .await?
+    .ok_or_else(|| ModelError::not_found("Message not found."))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::{
+        channel::ChannelModel,
+        message::MessageModel,
+        users::{Role, UsersModel},
+    };
+
+    #[sqlx::test]
+    async fn reactions(pool: PgPool) -> Result<()> {
+        // Create users.
+        let owner = UsersModel::create(
+            "owner".to_string(),
+            "owner@example.com".to_string(),
+            "password".to_string(),
+            &AuditContext::default(),
+            &pool,
+        )
+        .await?;
+        let other = UsersModel::create(
+            "other".to_string(),
+            "other@example.com".to_string(),
+            "password".to_string(),
+            &AuditContext::default(),
+            &pool,
+        )
+        .await?;
+        let channel = ChannelModel::create(
+            "test".to_string(),
+            owner.id,
+            &AuditContext::default(),
+            &pool,
+        )
+        .await?;
+        let first = MessageModel::create(
+            owner.id,
+            channel.id,
+            "first".to_string(),
+            None,
+            &AuditContext::default(),
+            &pool,
+        )
+        .await?;
+        let second = MessageModel::create(
+            owner.id,
+            channel.id,
+            "second".to_string(),
+            None,
+            &AuditContext::default(),
+            &pool,
+        )
+        .await?;
+
+        // Non-members cannot react.
+        let result = ReactionModel::add(
+            first.id,
+            other.id,
+            "👍".to_string(),
+            &AuditContext::default(),
+            &pool,
+        )
+        .await;
+        assert!(matches!(result, Err(ModelError::NotFound(_))));
+        ChannelModel::join(channel.id, other.id, &AuditContext::default(), &pool).await?;
+
+        // React twice with the same emoji, and with another one.
+        ReactionModel::add(
+            first.id,
+            owner.id,
+            "👍".to_string(),
+            &AuditContext::default(),
+            &pool,
+        )
+        .await?;
+        ReactionModel::add(
+            first.id,
+            owner.id,
+            "👍".to_string(),
+            &AuditContext::default(),
+            &pool,
+        )
+        .await?;
+        ReactionModel::add(
+            first.id,
+            other.id,
+            "👍".to_string(),
+            &AuditContext::default(),
+            &pool,
+        )
+        .await?;
+        ReactionModel::add(
+            first.id,
+            other.id,
+            "🎉".to_string(),
+            &AuditContext::default(),
+            &pool,
+        )
+        .await?;
+        let reactions =
+            ReactionModel::find_by_message_ids(&[first.id, second.id], owner.id, &pool).await?;
+        assert_eq!(
+            reactions[&first.id],
+            vec![
+                ReactionModel {
+                    emoji: "👍".to_string(),
+                    count: 2,
+                    reacted: true,
+                },
+                ReactionModel {
+                    emoji: "🎉".to_string(),
+                    count: 1,
+                    reacted: false,
+                },
+            ]
+        );
+        assert!(!reactions.contains_key(&second.id));
+
+        // Remove.
+        ReactionModel::remove(
+            first.id,
+            other.id,
+            "🎉".to_string(),
+            &AuditContext::default(),
+            &pool,
+        )
+        .await?;
+        let result = ReactionModel::remove(
+            first.id,
+            other.id,
+            "🎉".to_string(),
+            &AuditContext::default(),
+            &pool,
+        )
+        .await;
+        assert!(matches!(result, Err(ModelError::NotFound(_))));
+        let reactions = ReactionModel::find_by_message_ids(&[first.id], other.id, &pool).await?;
+        assert_eq!(reactions[&first.id].len(), 1);
+
+        // Text and deleted messages.
+        let result = ReactionModel::add(
+            second.id,
+            owner.id,
+            "ok".to_string(),
+            &AuditContext::default(),
+            &pool,
+        )
+        .await;
+        assert!(matches!(result, Err(ModelError::Validation(_))));
+        MessageModel::delete(
+            second.id,
+            owner.id,
+            Role::User,
+            &AuditContext::default(),
+            &pool,
+        )
+        .await?;
+        let result = ReactionModel::add(
+            second.id,
+            owner.id,
+            "👍".to_string(),
+            &AuditContext::default(),
+            &pool,
+        )
+        .await;
+        assert!(matches!(result, Err(ModelError::NotFound(_))));
+        Ok(())
+    }
+}
@@ -0,0 +1,513 @@
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use serde_json::json;
+use sqlx::{query, query_as, PgExecutor, PgPool};
+
+use super::{
+    audit::AuditContext,
+    error::{is_unique_violation, ModelError},
+    events::MessageEventKind,
+    message::{announce, MessageModelResponse},
+    pagination::MAX_LIST_SIZE,
+    users::Role,
+};
+
+const MAX_REASON_LENGTH: usize = 1000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Deserialize, serde::Serialize, sqlx::Type)]
+#[sqlx(type_name = "report_action", rename_all = "snake_case")]
+pub enum ReportAction {
+    Dismiss,
+    HideMessage,
+    // Also hides the message.
+    SuspendAuthor,
+}
+
+#[derive(Debug, Clone, serde::Deserialize, serde::Serialize)]
+pub struct ReportModel {
+    pub id: i32,
+    pub message_id: i32,
+    pub reporter_id: i32,
+    pub reason: String,
+    #[serde(rename = "createdAt")]
+    pub created_at: DateTime<Utc>,
+    #[serde(rename = "resolvedAt")]
+    pub resolved_at: Option<DateTime<Utc>>,
+    #[serde(rename = "resolvedBy")]
+    pub resolved_by: Option<i32>,
+    pub action: Option<ReportAction>,
+}
+
+impl ReportModel {
+    // Report a message the user can read. Each user can report a message once.
+    pub async fn create(
+        message_id: i32,
+        reporter_id: i32,
+        reason: String,
+        audit: &AuditContext,
+        pool: &PgPool,
+    ) -> Result<ReportModel, ModelError> {
+        let reason = reason.trim().to_string();
+        if reason.is_empty() || reason.chars().count() > MAX_REASON_LENGTH {
+            return Err(ModelError::validation(
+                "Reason must be between 1 and 1000 characters.",
+            ));
+        }
+        let message = query!(
+            r#"
+            select m.user_id
+            from message m
+            inner join channel_member cm on cm.channel_id = m.channel_id
+            where m.id = $1
+            and m.deleted_at is null
+            and m.hidden_at is null
+            and cm.user_id = $2
+            "#,
+            message_id,
+            reporter_id
+        )
+        .fetch_optional(pool)
+        .await?
+        .ok_or_else(|| ModelError::not_found("Message not found."))?;
+        if message.user_id == Some(reporter_id) {
+            return Err(ModelError::validation("Cannot report your own message."));
+        }
+
+        let mut tx = pool.begin().await?;
+        let row = query_as!(
+            ReportModel,
+            r#"
+            insert into message_report (message_id, reporter_id, reason)
+            values ($1, $2, $3)
+            returning id, message_id, reporter_id, reason, created_at, resolved_at, resolved_by,
+                action as "action: ReportAction"
+            "#,
+            message_id,
+            reporter_id,
+            reason
+        )
+        .fetch_one(&mut *tx)
+        .await
+        .map_err(|e| {
+            if is_unique_violation(&e) {
+                ModelError::conflict("Message already reported.")
+            } else {
+                e.into()
+            }
+        })?;
+        audit
+            .record(
+                "reportMessage",
+                Some(row.id),
+                None,
+                Some(json!(row)),
+                &mut tx,
+            )
+            .await?;
+        tx.commit().await?;
+
+        Ok(row)
+    }
+
+    // Open reports, oldest first.
+    pub async fn find_open(pool: &PgPool) -> Result<Vec<ReportModel>, ModelError> {
+        let rows = query_as!(
+            ReportModel,
+            r#"
+            select id, message_id, reporter_id, reason, created_at, resolved_at, resolved_by,
+                action as "action: ReportAction"
+            from message_report
+            where resolved_at is null
+            order by created_at, id
+            limit $1
+            "#,
+            MAX_LIST_SIZE
+        )
+        .fetch_all(pool)
+        .await?;
+
+        Ok(rows)
+    }
+
+    // Resolve the report and every other open report of the same message.
+    pub async fn resolve(
+        id: i32,
+        moderator_id: i32,
+        action: ReportAction,
+        audit: &AuditContext,
+        pool: &PgPool,
+    ) -> Result<ReportModel, ModelError> {
+        let mut tx = pool.begin().await?;
+        let report = query!(
+            r#"
+            select r.message_id, r.resolved_at, m.user_id as author_id,
+                u.role as "author_role?: Role"
+            from message_report r
+            inner join message m on m.id = r.message_id
+            left join users u on u.id = m.user_id
+            where r.id = $1
+            for update of r
+            "#,
+            id
+        )
+        .fetch_optional(&mut *tx)
+        .await?
+        .ok_or_else(|| ModelError::not_found("Report not found."))?;
+        if report.resolved_at.is_some() {
+            return Err(ModelError::conflict("Report already resolved."));
+        }
+        let before = ReportModel::find_by_id(id, &mut *tx).await?;
+
+        if action == ReportAction::SuspendAuthor {
+            match report.author_role {
+                None => return Err(ModelError::validation("Author account deleted.")),
+                Some(role) if role >= Role::Moderator => {
+                    return Err(ModelError::forbidden("Cannot suspend a moderator."));
+                }
+                Some(_) => {}
+            }
+            // Suspended users are logged out everywhere.
+            query!(
+                r#"
+                update users
+                set suspended_at = coalesce(suspended_at, now())
+                where id = $1
+                "#,
+                report.author_id
+            )
+            .execute(&mut *tx)
+            .await?;
+            query!(
+                r#"
+                update user_session
+                set revoked_at = now()
+                where user_id = $1 and revoked_at is null
+                "#,
+                report.author_id
+            )
+            .execute(&mut *tx)
+            .await?;
+        }
+        let hidden = if action == ReportAction::Dismiss {
+            None
+        } else {
+            query_as!(
+                MessageModelResponse,
+                r#"
+                update message
+                set hidden_at = coalesce(hidden_at, now())
+                where id = $1
+                returning id, user_id, channel_id, message, parent_id, message_time, edited_at, deleted_at, hidden_at
+                "#,
+                report.message_id
+            )
+            .fetch_optional(&mut *tx)
+            .await?
+        };
+        query!(
+            r#"
+            update message_report
+            set resolved_at = now(), resolved_by = $2, action = $3
+            where message_id = $1
+            and resolved_at is null
+            "#,
+            report.message_id,
+            moderator_id,
+            action as ReportAction
+        )
+        .execute(&mut *tx)
+        .await?;
+        let row = ReportModel::find_by_id(id, &mut *tx).await?;
+        audit
+            .record(
+                "resolveReport",
+                Some(id),
+                Some(json!(before)),
+                Some(json!(row)),
+                &mut tx,
+            )
+            .await?;
+        tx.commit().await?;
+
+        // Subscribers replace the message like an edit.
+        if let Some(message) = hidden {
+            announce(MessageEventKind::Updated, message, pool).await;
+        }
+
+        Ok(row)
+    }
+
+    // The whole thread of the reported message, oldest first. Moderators review it
+    // whether or not they belong to its channel, so it is only loaded through a report.
+    pub async fn find_thread(
+        id: i32,
+        pool: &PgPool,
+    ) -> Result<Vec<MessageModelResponse>, ModelError> {
+        let rows = query_as!(
+            MessageModelResponse,
+            r#"
+            with recursive ancestors as (
+                select m.id, m.parent_id
+                from message_report r
+                inner join message m on m.id = r.message_id
+                where r.id = $1
+                union all
+                select m.id, m.parent_id
+                from message m
+                inner join ancestors a on a.parent_id = m.id
+            ),
+            thread as (
+                select id, user_id, channel_id, message, parent_id, message_time, edited_at, deleted_at, hidden_at
+                from message
+                where id = (select id from ancestors where parent_id is null)
+                union all
+                select m.id, m.user_id, m.channel_id, m.message, m.parent_id, m.message_time, m.edited_at, m.deleted_at, m.hidden_at
+                from message m
+                inner join thread t on t.id = m.parent_id
+            )
+            select id as "id!", user_id, channel_id as "channel_id!", message as "message!", parent_id, message_time as "message_time!", edited_at, deleted_at, hidden_at
+            from thread
+            order by message_time, id
+            limit $2
+            "#,
+            id,
+            MAX_LIST_SIZE
+        )
+        .fetch_all(pool)
+        .await?;
+
+        Ok(rows)
+    }
+
+    pub async fn find_by_id(
+        id: i32,
+        executor: impl PgExecutor<'_>,
+    ) -> Result<ReportModel, ModelError> {
+        query_as!(
+            ReportModel,
+            r#"
+            select id, message_id, reporter_id, reason, created_at, resolved_at, resolved_by,
+                action as "action: ReportAction"
+            from message_report
+            where id = $1
+            "#,
+            id
+        )
+        .fetch_optional(executor)
+        .await?
+        .ok_or_else(|| ModelError::not_found("Report not found."))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::{
+        audit::ClientInfo,
+        channel::ChannelModel,
+        message::MessageModel,
+        pagination::PageArgs,
+        users::{verify_token, UsersModel},
+    };
+
+    async fn create_user(name: &str, pool: &PgPool) -> Result<i32> {
+        let user = UsersModel::create(
+            name.to_string(),
+            format!("{}@example.com", name),
+            "password".to_string(),
+            &AuditContext::default(),
+            pool,
+        )
+        .await?;
+        Ok(user.id)
+    }
+
+    #[sqlx::test]
+    async fn report_and_hide(pool: PgPool) -> Result<()> {
+        let author = create_user("author", &pool).await?;
+        let reporter = create_user("reporter", &pool).await?;
+        let moderator = create_user("moderator", &pool).await?;
+        UsersModel::set_role(moderator, Role::Moderator, &AuditContext::default(), &pool).await?;
+        let channel =
+            ChannelModel::create("test".to_string(), author, &AuditContext::default(), &pool)
+                .await?;
+        ChannelModel::join(channel.id, reporter, &AuditContext::default(), &pool).await?;
+        let message = MessageModel::create(
+            author,
+            channel.id,
+            "spam".to_string(),
+            None,
+            &AuditContext::default(),
+            &pool,
+        )
+        .await?;
+
+        // Report twice, your own message and with an empty reason.
+        let report = ReportModel::create(
+            message.id,
+            reporter,
+            "Spam.".to_string(),
+            &AuditContext::default(),
+            &pool,
+        )
+        .await?;
+        let result = ReportModel::create(
+            message.id,
+            reporter,
+            "Spam.".to_string(),
+            &AuditContext::default(),
+            &pool,
+        )
+        .await;
+        assert!(matches!(result, Err(ModelError::Conflict(_))));
+        let result = ReportModel::create(
+            message.id,
+            author,
+            "Spam.".to_string(),
+            &AuditContext::default(),
+            &pool,
+        )
+        .await;
+        assert!(matches!(result, Err(ModelError::Validation(_))));
+        let result = ReportModel::create(
+            message.id,
+            reporter,
+            " ".to_string(),
+            &AuditContext::default(),
+            &pool,
+        )
+        .await;
+        assert!(matches!(result, Err(ModelError::Validation(_))));
+        let open = ReportModel::find_open(&pool).await?;
+        assert_eq!(
+            open.iter().map(|r| r.id).collect::<Vec<_>>(),
+            vec![report.id]
+        );
+
+        // Hide the message.
+        let resolved = ReportModel::resolve(
+            report.id,
+            moderator,
+            ReportAction::HideMessage,
+            &AuditContext::default(),
+            &pool,
+        )
+        .await?;
+        assert_eq!(resolved.action, Some(ReportAction::HideMessage));
+        assert_eq!(resolved.resolved_by, Some(moderator));
+        assert!(ReportModel::find_open(&pool).await?.is_empty());
+        let result = ReportModel::resolve(
+            report.id,
+            moderator,
+            ReportAction::Dismiss,
+            &AuditContext::default(),
+            &pool,
+        )
+        .await;
+        assert!(matches!(result, Err(ModelError::Conflict(_))));
+
+        // Hidden messages disappear from reads except for moderators, who still need to
+        // belong to the channel. Reported threads are shown to them in any case.
+        let page =
+            MessageModel::find_page_by_channel_id(channel.id, PageArgs::default(), author, &pool)
+                .await?;
+        assert!(page.rows.is_empty());
+        let result = MessageModel::find_messages_by_id(message.id, reporter, &pool).await;
+        assert!(matches!(result, Err(ModelError::NotFound(_))));
+        let result = MessageModel::find_messages_by_id(message.id, moderator, &pool).await;
+        assert!(matches!(result, Err(ModelError::NotFound(_))));
+        let rows = ReportModel::find_thread(report.id, &pool).await?;
+        assert_eq!(rows.len(), 1);
+        assert!(rows[0].hidden_at.is_some());
+        ChannelModel::join(channel.id, moderator, &AuditContext::default(), &pool).await?;
+        let rows = MessageModel::find_messages_by_id(message.id, moderator, &pool).await?;
+        assert!(rows[0].hidden_at.is_some());
+        Ok(())
+    }
+
+    #[sqlx::test]
+    async fn suspend_author(pool: PgPool) -> Result<()> {
+        let author = create_user("author", &pool).await?;
+        let reporter = create_user("reporter", &pool).await?;
+        let moderator = create_user("moderator", &pool).await?;
+        UsersModel::set_role(moderator, Role::Moderator, &AuditContext::default(), &pool).await?;
+        let channel =
+            ChannelModel::create("test".to_string(), author, &AuditContext::default(), &pool)
+                .await?;
+        ChannelModel::join(channel.id, reporter, &AuditContext::default(), &pool).await?;
+        ChannelModel::join(channel.id, moderator, &AuditContext::default(), &pool).await?;
+        let tokens = UsersModel::login(
+            "author@example.com".to_string(),
+            "password".to_string(),
+            &ClientInfo::default(),
+            &pool,
+        )
+        .await?
+        .unwrap_tokens();
+
+        // Moderators cannot be suspended.
+        let message = MessageModel::create(
+            moderator,
+            channel.id,
+            "hi".to_string(),
+            None,
+            &AuditContext::default(),
+            &pool,
+        )
+        .await?;
+        let report = ReportModel::create(
+            message.id,
+            reporter,
+            "Rude.".to_string(),
+            &AuditContext::default(),
+            &pool,
+        )
+        .await?;
+        let result = ReportModel::resolve(
+            report.id,
+            moderator,
+            ReportAction::SuspendAuthor,
+            &AuditContext::default(),
+            &pool,
+        )
+        .await;
+        assert!(matches!(result, Err(ModelError::Forbidden(_))));
+
+        // Suspended users are logged out and cannot log in again.
+        let message = MessageModel::create(
+            author,
+            channel.id,
+            "spam".to_string(),
+            None,
+            &AuditContext::default(),
+            &pool,
+        )
+        .await?;
+        let report = ReportModel::create(
+            message.id,
+            reporter,
+            "Spam.".to_string(),
+            &AuditContext::default(),
+            &pool,
+        )
+        .await?;
+        ReportModel::resolve(
+            report.id,
+            moderator,
+            ReportAction::SuspendAuthor,
+            &AuditContext::default(),
+            &pool,
+        )
+        .await?;
+        assert!(verify_token(&tokens.access_token, &pool).await.is_err());
+        let result = UsersModel::login(
+            "author@example.com".to_string(),
+            "password".to_string(),
+            &ClientInfo::default(),
+            &pool,
+        )
+        .await;
+        assert!(matches!(result, Err(ModelError::Forbidden(_))));
+        Ok(())
+    }
+}
@@ -0,0 +1,185 @@
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use sqlx::{query, query_as, PgPool};
+
+use super::{error::ModelError, pagination::MAX_LIST_SIZE};
+
+// A prior body of a message, written by `editor_id` at `edited_at`.
+#[derive(Debug, Clone, serde::Deserialize, serde::Serialize)]
+pub struct MessageRevisionModel {
+    pub id: i32,
+    pub message_id: i32,
+    pub message: String,
+    // None once the editor's account is deleted.
+    pub editor_id: Option<i32>,
+    #[serde(rename = "editedAt")]
+    pub edited_at: DateTime<Utc>,
+}
+
+impl MessageRevisionModel {
+    // Find the prior bodies of the message, oldest first.
+    pub async fn find_by_message_id(
+        message_id: i32,
+        viewer_id: i32,
+        pool: &PgPool,
+    ) -> Result<Vec<MessageRevisionModel>, ModelError> {
+        // Check if message exists in one of the viewer's channels.
+        if query!(
+            r#"
+            select m.id
+            from message m
+            inner join channel_member cm on cm.channel_id = m.channel_id
+            where m.id = $1
+            and m.deleted_at is null
+            and (m.hidden_at is null or is_moderator($2))
+            and cm.user_id = $2
+            "#,
+            message_id,
+            viewer_id
+        )
+        .fetch_one(pool)
+        .await
+        .is_err()
+        {
+            return Err(ModelError::not_found("Message not found."));
+        }
+
+        let rows = query_as!(
+            MessageRevisionModel,
+            r#"
+            select id, message_id, message, editor_id, edited_at
+            from message_revision
+            where message_id = $1
+            order by edited_at, id
+            limit $2
+            "#,
+            message_id,
+            MAX_LIST_SIZE
+        )
+        .fetch_all(pool)
+        .await?;
+
+        Ok(rows)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::{
+        audit::AuditContext,
+        channel::ChannelModel,
+        message::MessageModel,
+        users::{Role, UsersModel},
+    };
+
+    #[sqlx::test]
+    async fn find_by_message_id(pool: PgPool) -> Result<()> {
+        // Create user.
+        let user = UsersModel::create(
+            "test".to_string(),
+            "test@example.com".to_string(),
+            "password".to_string(),
+            &AuditContext::default(),
+            &pool,
+        )
+        .await?;
+        let channel =
+            ChannelModel::create("test".to_string(), user.id, &AuditContext::default(), &pool)
+                .await?;
+        // Create and edit message twice.
+        let row = MessageModel::create(
+            user.id,
+            channel.id,
+            "first".to_string(),
+            None,
+            &AuditContext::default(),
+            &pool,
+        )
+        .await?;
+        assert!(row.edited_at.is_none());
+        let second = MessageModel::modify(
+            row.id,
+            user.id,
+            Role::User,
+            "second".to_string(),
+            &AuditContext::default(),
+            &pool,
+        )
+        .await?;
+        let row = MessageModel::modify(
+            row.id,
+            user.id,
+            Role::User,
+            "third".to_string(),
+            &AuditContext::default(),
+            &pool,
+        )
+        .await?;
+        assert!(row.edited_at.is_some());
+
+        // Find revisions.
+        let revisions = MessageRevisionModel::find_by_message_id(row.id, user.id, &pool).await?;
+        assert_eq!(
+            revisions
+                .iter()
+                .map(|r| r.message.as_str())
+                .collect::<Vec<_>>(),
+            vec!["first", "second"]
+        );
+        assert!(revisions.iter().all(|r| r.editor_id == Some(user.id)));
+        assert_eq!(revisions[0].edited_at, row.message_time);
+        assert_eq!(revisions[1].edited_at, second.edited_at.unwrap());
+
+        // A moderator's edit keeps the owner's body under the owner.
+        let moderator = UsersModel::create(
+            "moderator".to_string(),
+            "moderator@example.com".to_string(),
+            "password".to_string(),
+            &AuditContext::default(),
+            &pool,
+        )
+        .await?;
+        MessageModel::modify(
+            row.id,
+            moderator.id,
+            Role::Moderator,
+            "fourth".to_string(),
+            &AuditContext::default(),
+            &pool,
+        )
+        .await?;
+        MessageModel::modify(
+            row.id,
+            user.id,
+            Role::User,
+            "fifth".to_string(),
+            &AuditContext::default(),
+            &pool,
+        )
+        .await?;
+        let revisions = MessageRevisionModel::find_by_message_id(row.id, user.id, &pool).await?;
+        assert_eq!(
+            revisions
+                .iter()
+                .map(|r| (r.message.as_str(), r.editor_id))
+                .collect::<Vec<_>>(),
+            vec![
+                ("first", Some(user.id)),
+                ("second", Some(user.id)),
+                ("third", Some(user.id)),
+                ("fourth", Some(moderator.id)),
+            ]
+        );
+        assert_eq!(revisions[2].edited_at, row.edited_at.unwrap());
+
+        // Find revisions of deleted message.
+        MessageModel::delete(row.id, user.id, Role::User, &AuditContext::default(), &pool).await?;
+        assert!(
+            MessageRevisionModel::find_by_message_id(row.id, user.id, &pool)
+                .await
+                .is_err()
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,490 @@
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use sqlx::{query_as, PgPool};
+
+use super::{
+    error::ModelError,
+    message::MessageModelResponse,
+    pagination::{Page, PageArgs},
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SearchLanguage {
+    English,
+    Japanese,
+}
+
+// Stable position of a search result ordered by (rank desc, id desc).
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SearchCursor {
+    pub rank: f32,
+    pub id: i32,
+}
+
+// ts_headline wraps hits in these, and they are replaced with <mark> tags once the
+// rest of the headline is escaped. They are removed from messages first.
+const START_SEL: char = '\u{1}';
+const STOP_SEL: char = '\u{2}';
+
+#[derive(Debug, Clone)]
+pub struct MessageSearchResult {
+    pub message: MessageModelResponse,
+    pub rank: f32,
+    // The matching part of the message as HTML, with hits wrapped in <mark> tags.
+    pub headline: String,
+}
+
+struct SearchRow {
+    id: i32,
+    user_id: Option<i32>,
+    channel_id: i32,
+    message: String,
+    parent_id: Option<i32>,
+    message_time: DateTime<Utc>,
+    edited_at: Option<DateTime<Utc>>,
+    deleted_at: Option<DateTime<Utc>>,
+    hidden_at: Option<DateTime<Utc>>,
+    rank: f32,
+    headline: String,
+}
+
+pub struct SearchModel;
+
+impl SearchModel {
+    // Find messages in the viewer's channels matching the query, most relevant first.
+    #[allow(clippy::too_many_arguments)]
+    pub async fn search_messages(
+        query: String,
+        language: SearchLanguage,
+        user_id: Option<i32>,
+        start_time: Option<DateTime<Utc>>,
+        end_time: Option<DateTime<Utc>>,
+        after: Option<SearchCursor>,
+        first: Option<usize>,
+        viewer_id: i32,
+        pool: &PgPool,
+    ) -> Result<Page<MessageSearchResult>, ModelError> {
+        if query.trim().is_empty() {
+            return Err(ModelError::validation("Query must not be empty."));
+        }
+        let args = PageArgs::new(None, None, first, None)?;
+
+        let rows = match language {
+            SearchLanguage::English => {
+                let mut rows = query_as!(
+                    SearchRow,
+                    r#"
+                    select id as "id!", user_id, channel_id as "channel_id!", message as "message!", parent_id, message_time as "message_time!", edited_at, deleted_at, hidden_at,
+                        rank as "rank!",
+                        ts_headline('english', translate(message, chr(1) || chr(2), ''), websearch_to_tsquery('english', $1), 'StartSel=' || chr(1) || ', StopSel=' || chr(2)) as "headline!"
+                    from (
+                        select m.id, m.user_id, m.channel_id, m.message, m.parent_id, m.message_time, m.edited_at, m.deleted_at, m.hidden_at,
+                            ts_rank(m.search_english, q) as rank
+                        from message m, websearch_to_tsquery('english', $1) q
+                        where m.search_english @@ q
+                        and m.deleted_at is null
+                        and ($2::int4 is null or m.user_id = $2)
+                        and ($3::timestamptz is null or m.message_time >= $3)
+                        and ($4::timestamptz is null or m.message_time <= $4)
+                        and ($5::real is null or (ts_rank(m.search_english, q), m.id) < ($5, $6))
+                        and m.channel_id in (select channel_id from channel_member where user_id = $8)
+                        and (m.hidden_at is null or is_moderator($8))
+                        order by rank desc, m.id desc
+                        limit $7
+                    ) results
+                    order by rank desc, id desc
+                    "#,
+                    query,
+                    user_id,
+                    start_time,
+                    end_time,
+                    after.map(|c| c.rank),
+                    after.map(|c| c.id),
+                    args.limit(),
+                    viewer_id
+                )
+                .fetch_all(pool)
+                .await?;
+                for row in rows.iter_mut() {
+                    row.headline = mark(&row.headline);
+                }
+                rows
+            }
+            SearchLanguage::Japanese => {
+                let mut rows = query_as!(
+                    SearchRow,
+                    r#"
+                    select m.id, m.user_id, m.channel_id, m.message, m.parent_id, m.message_time, m.edited_at, m.deleted_at, m.hidden_at,
+                        ts_rank(m.search_japanese, q) as "rank!",
+                        m.message as headline
+                    from message m, plainto_tsquery('simple', message_ngrams($1)) q
+                    where m.search_japanese @@ q
+                    and m.deleted_at is null
+                    and ($2::int4 is null or m.user_id = $2)
+                    and ($3::timestamptz is null or m.message_time >= $3)
+                    and ($4::timestamptz is null or m.message_time <= $4)
+                    and ($5::real is null or (ts_rank(m.search_japanese, q), m.id) < ($5, $6))
+                    and m.channel_id in (select channel_id from channel_member where user_id = $8)
+                    and (m.hidden_at is null or is_moderator($8))
+                    order by ts_rank(m.search_japanese, q) desc, m.id desc
+                    limit $7
+                    "#,
+                    query,
+                    user_id,
+                    start_time,
+                    end_time,
+                    after.map(|c| c.rank),
+                    after.map(|c| c.id),
+                    args.limit(),
+                    viewer_id
+                )
+                .fetch_all(pool)
+                .await?;
+                for row in rows.iter_mut() {
+                    row.headline = highlight(&row.message, &query);
+                }
+                rows
+            }
+        };
+
+        let rows = rows
+            .into_iter()
+            .map(|row| MessageSearchResult {
+                message: MessageModelResponse {
+                    id: row.id,
+                    user_id: row.user_id,
+                    channel_id: row.channel_id,
+                    message: row.message,
+                    parent_id: row.parent_id,
+                    message_time: row.message_time,
+                    edited_at: row.edited_at,
+                    deleted_at: row.deleted_at,
+                    hidden_at: row.hidden_at,
+                },
+                rank: row.rank,
+                headline: row.headline,
+            })
+            .collect();
+
+        Ok(Page::new(rows, &args))
+    }
+}
+
+// Escape a headline from ts_headline and turn its selections into <mark> tags.
+fn mark(headline: &str) -> String {
+    escape_html(headline)
+        .replace(START_SEL, "<mark>")
+        .replace(STOP_SEL, "</mark>")
+}
+
+fn escape_html(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+// ts_headline only marks whole words, and Japanese text is not split into words,
+// so every occurrence of each query term is marked instead.
+fn highlight(message: &str, query: &str) -> String {
+    let terms: Vec<String> = query
+        .split_whitespace()
+        .map(|term| term.to_lowercase())
+        .collect();
+    // Lowercasing can change the byte length of a char, so keep the offset in the
+    // message of each lowercase byte that starts a char of the message.
+    let mut lower = String::with_capacity(message.len());
+    let mut offsets = Vec::with_capacity(message.len() + 1);
+    for (i, c) in message.char_indices() {
+        offsets.resize(lower.len(), None);
+        offsets.push(Some(i));
+        lower.extend(c.to_lowercase());
+    }
+    offsets.resize(lower.len(), None);
+    offsets.push(Some(message.len()));
+    let offset = |i: usize| offsets[i].expect("Offset is not a char boundary.");
+
+    let mut headline = String::new();
+    let mut position = 0;
+    while position < lower.len() {
+        let hit = terms
+            .iter()
+            .filter_map(|term| find_term(&lower, &offsets, position, term).map(|i| (i, term.len())))
+            .min_by_key(|(i, len)| (*i, std::cmp::Reverse(*len)));
+        match hit {
+            Some((start, len)) => {
+                headline.push_str(&escape_html(&message[offset(position)..offset(start)]));
+                headline.push_str("<mark>");
+                headline.push_str(&escape_html(&message[offset(start)..offset(start + len)]));
+                headline.push_str("</mark>");
+                position = start + len;
+            }
+            None => {
+                headline.push_str(&escape_html(&message[offset(position)..]));
+                break;
+            }
+        }
+    }
+    headline
+}
+
+// Find the first occurrence of the term from `position` that starts and ends on
+// chars of the message, so it can be marked there.
+fn find_term(lower: &str, offsets: &[Option<usize>], position: usize, term: &str) -> Option<usize> {
+    let mut position = position;
+    while let Some(i) = lower[position..].find(term) {
+        let start = position + i;
+        if offsets[start].is_some() && offsets[start + term.len()].is_some() {
+            return Some(start);
+        }
+        position = start + lower[start..].chars().next().map_or(1, char::len_utf8);
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::{
+        audit::AuditContext,
+        channel::ChannelModel,
+        message::MessageModel,
+        users::{Role, UsersModel},
+    };
+
+    #[sqlx::test]
+    async fn search_messages(pool: PgPool) -> Result<()> {
+        // Create users.
+        let user = UsersModel::create(
+            "test".to_string(),
+            "test@example.com".to_string(),
+            "password".to_string(),
+            &AuditContext::default(),
+            &pool,
+        )
+        .await?;
+        let other = UsersModel::create(
+            "other".to_string(),
+            "other@example.com".to_string(),
+            "password".to_string(),
+            &AuditContext::default(),
+            &pool,
+        )
+        .await?;
+        let channel =
+            ChannelModel::create("test".to_string(), user.id, &AuditContext::default(), &pool)
+                .await?;
+        ChannelModel::join(channel.id, other.id, &AuditContext::default(), &pool).await?;
+        // Create messages.
+        let best = MessageModel::create(
+            user.id,
+            channel.id,
+            "Running late, still running to the station".to_string(),
+            None,
+            &AuditContext::default(),
+            &pool,
+        )
+        .await?;
+        let good = MessageModel::create(
+            user.id,
+            channel.id,
+            "I run every morning".to_string(),
+            None,
+            &AuditContext::default(),
+            &pool,
+        )
+        .await?;
+        MessageModel::create(
+            other.id,
+            channel.id,
+            "runs are fun".to_string(),
+            None,
+            &AuditContext::default(),
+            &pool,
+        )
+        .await?;
+        MessageModel::create(
+            user.id,
+            channel.id,
+            "nothing to see here".to_string(),
+            None,
+            &AuditContext::default(),
+            &pool,
+        )
+        .await?;
+        let html = MessageModel::create(
+            other.id,
+            channel.id,
+            "<b>run</b> & \"go\" \u{1}<img src=x onerror=alert(1)".to_string(),
+            None,
+            &AuditContext::default(),
+            &pool,
+        )
+        .await?;
+        let deleted = MessageModel::create(
+            user.id,
+            channel.id,
+            "run away".to_string(),
+            None,
+            &AuditContext::default(),
+            &pool,
+        )
+        .await?;
+        MessageModel::delete(
+            deleted.id,
+            user.id,
+            Role::User,
+            &AuditContext::default(),
+            &pool,
+        )
+        .await?;
+
+        // Search with stemming, ranked and highlighted.
+        let page = SearchModel::search_messages(
+            "run".to_string(),
+            SearchLanguage::English,
+            Some(user.id),
+            None,
+            None,
+            None,
+            Some(1),
+            user.id,
+            &pool,
+        )
+        .await?;
+        assert_eq!(page.rows[0].message.id, best.id);
+        assert!(page.rows[0].headline.contains("<mark>Running</mark>"));
+        assert!(page.has_next_page);
+        // Next page.
+        let after = SearchCursor {
+            rank: page.rows[0].rank,
+            id: page.rows[0].message.id,
+        };
+        let page = SearchModel::search_messages(
+            "run".to_string(),
+            SearchLanguage::English,
+            Some(user.id),
+            None,
+            None,
+            Some(after),
+            Some(10),
+            user.id,
+            &pool,
+        )
+        .await?;
+        assert_eq!(
+            page.rows.iter().map(|r| r.message.id).collect::<Vec<_>>(),
+            vec![good.id]
+        );
+        assert!(!page.has_next_page);
+
+        // Messages are escaped in headlines.
+        let page = SearchModel::search_messages(
+            "run".to_string(),
+            SearchLanguage::English,
+            Some(other.id),
+            None,
+            None,
+            None,
+            None,
+            user.id,
+            &pool,
+        )
+        .await?;
+        let headline = &page
+            .rows
+            .iter()
+            .find(|r| r.message.id == html.id)
+            .expect("Missing result.")
+            .headline;
+        // ts_headline drops complete tags, but not unfinished ones.
+        assert_eq!(
+            headline,
+            " <mark>run</mark>  &amp; &quot;go&quot; &lt;img src=x onerror=alert(1)"
+        );
+
+        // Empty query.
+        let result = SearchModel::search_messages(
+            " ".to_string(),
+            SearchLanguage::English,
+            None,
+            None,
+            None,
+            None,
+            None,
+            user.id,
+            &pool,
+        )
+        .await;
+        assert!(result.is_err());
+        Ok(())
+    }
+
+    #[sqlx::test]
+    async fn search_messages_japanese(pool: PgPool) -> Result<()> {
+        // Create user.
+        let user = UsersModel::create(
+            "test".to_string(),
+            "test@example.com".to_string(),
+            "password".to_string(),
+            &AuditContext::default(),
+            &pool,
+        )
+        .await?;
+        let channel =
+            ChannelModel::create("test".to_string(), user.id, &AuditContext::default(), &pool)
+                .await?;
+        // Create messages.
+        let hit = MessageModel::create(
+            user.id,
+            channel.id,
+            "今日は良い天気です".to_string(),
+            None,
+            &AuditContext::default(),
+            &pool,
+        )
+        .await?;
+        MessageModel::create(
+            user.id,
+            channel.id,
+            "明日は雨です".to_string(),
+            None,
+            &AuditContext::default(),
+            &pool,
+        )
+        .await?;
+
+        // Search inside a run of Japanese text.
+        let page = SearchModel::search_messages(
+            "天気".to_string(),
+            SearchLanguage::Japanese,
+            None,
+            None,
+            None,
+            None,
+            None,
+            user.id,
+            &pool,
+        )
+        .await?;
+        assert_eq!(page.rows.len(), 1);
+        assert_eq!(page.rows[0].message.id, hit.id);
+        assert_eq!(page.rows[0].headline, "今日は良い<mark>天気</mark>です");
+        assert_eq!(
+            highlight("<b>天気</b>'s", "天気"),
+            "&lt;b&gt;<mark>天気</mark>&lt;/b&gt;&#39;s"
+        );
+        // Lowercasing changes the byte length of each char.
+        assert_eq!(highlight("İẞ", "ß"), "İ<mark>ẞ</mark>");
+        assert_eq!(highlight("İẞ", "i"), "İẞ");
+        Ok(())
+    }
+}
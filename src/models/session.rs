@@ -0,0 +1,382 @@
+use anyhow::Result;
+use argon2::password_hash::rand_core::{OsRng, RngCore};
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use chrono::{Duration, Utc};
+use serde_json::json;
+use sha2::{Digest, Sha256};
+use sqlx::{query, PgConnection, PgPool, Postgres, Transaction};
+
+use super::{
+    audit::AuditContext,
+    error::ModelError,
+    users::{encode_token, Role},
+};
+
+const REFRESH_TOKEN_TTL_DAYS: i64 = 30;
+
+pub struct SessionModel;
+
+#[derive(Debug, serde::Deserialize, serde::Serialize)]
+pub struct TokenPair {
+    #[serde(rename = "accessToken")]
+    pub access_token: String,
+    #[serde(rename = "refreshToken")]
+    pub refresh_token: String,
+}
+
+impl SessionModel {
+    // Start a new session and issue its first token pair.
+    pub async fn create(user_id: i32, pool: &PgPool) -> Result<TokenPair, ModelError> {
+        let mut tx = pool.begin().await?;
+        let row = query!(
+            r#"
+            insert into user_session (user_id)
+            values ($1)
+            returning id, (select role from users where id = $1) as "role!: Role"
+            "#,
+            user_id
+        )
+        .fetch_one(&mut *tx)
+        .await?;
+        let refresh_token = issue_refresh_token(row.id, &mut tx).await?;
+        tx.commit().await?;
+
+        Ok(TokenPair {
+            access_token: encode_token(user_id, row.id, row.role)?,
+            refresh_token,
+        })
+    }
+
+    // Exchange a refresh token for a new token pair.
+    // Presenting an already rotated token revokes the whole session.
+    // The refresh is recorded as made by the session's user.
+    pub async fn refresh(
+        refresh_token: String,
+        audit: &AuditContext,
+        pool: &PgPool,
+    ) -> Result<TokenPair, ModelError> {
+        let mut tx = pool.begin().await?;
+        let row = query!(
+            r#"
+            select r.id, r.session_id, r.used_at, r.expires_at, s.user_id, s.revoked_at,
+                u.role as "role: Role"
+            from refresh_token r
+            inner join user_session s on s.id = r.session_id
+            inner join users u on u.id = s.user_id
+            where r.token_hash = $1
+            for update of r, s
+            "#,
+            hash_token(&refresh_token)
+        )
+        .fetch_optional(&mut *tx)
+        .await?;
+
+        let row = match row {
+            Some(row) => row,
+            None => return Err(ModelError::unauthenticated("Invalid refresh token.")),
+        };
+        if row.revoked_at.is_some() {
+            return Err(ModelError::unauthenticated("Session revoked."));
+        }
+        if row.used_at.is_some() {
+            revoke_session(row.session_id, &mut tx).await?;
+            tx.commit().await?;
+            return Err(ModelError::unauthenticated("Refresh token reuse detected."));
+        }
+        if row.expires_at < Utc::now() {
+            return Err(ModelError::unauthenticated("Refresh token expired."));
+        }
+
+        query!(
+            r#"
+            update refresh_token
+            set used_at = now()
+            where id = $1
+            "#,
+            row.id
+        )
+        .execute(&mut *tx)
+        .await?;
+        let refresh_token = issue_refresh_token(row.session_id, &mut tx).await?;
+        let audit = AuditContext {
+            actor_id: Some(row.user_id),
+            ..audit.clone()
+        };
+        audit
+            .record("refreshToken", Some(row.session_id), None, None, &mut tx)
+            .await?;
+        tx.commit().await?;
+
+        Ok(TokenPair {
+            access_token: encode_token(row.user_id, row.session_id, row.role)?,
+            refresh_token,
+        })
+    }
+
+    // Revoke one session of the user.
+    pub async fn revoke(
+        id: i32,
+        user_id: i32,
+        audit: &AuditContext,
+        pool: &PgPool,
+    ) -> Result<(), ModelError> {
+        let mut tx = pool.begin().await?;
+        query!(
+            r#"
+            update user_session
+            set revoked_at = now()
+            where id = $1 and user_id = $2 and revoked_at is null
+            "#,
+            id,
+            user_id
+        )
+        .execute(&mut *tx)
+        .await?;
+        audit
+            .record("logout", Some(id), None, None, &mut tx)
+            .await?;
+        tx.commit().await?;
+
+        Ok(())
+    }
+
+    // Revoke every session of the user. Returns the number of sessions revoked.
+    pub async fn revoke_all(
+        user_id: i32,
+        audit: &AuditContext,
+        pool: &PgPool,
+    ) -> Result<u64, ModelError> {
+        let mut tx = pool.begin().await?;
+        let revoked = revoke_user_sessions(user_id, &mut tx).await?;
+        let after = json!({ "revoked": revoked });
+        audit
+            .record(
+                "logoutAllSessions",
+                Some(user_id),
+                None,
+                Some(after),
+                &mut tx,
+            )
+            .await?;
+        tx.commit().await?;
+
+        Ok(revoked)
+    }
+
+    // The current role of the session's user, or None if the session is revoked.
+    pub async fn find_active_role(id: i32, pool: &PgPool) -> Result<Option<Role>, ModelError> {
+        let row = query!(
+            r#"
+            select u.role as "role: Role"
+            from user_session s
+            inner join users u on u.id = s.user_id
+            where s.id = $1 and s.revoked_at is null
+            "#,
+            id
+        )
+        .fetch_optional(pool)
+        .await?;
+
+        Ok(row.map(|row| row.role))
+    }
+}
+
+async fn issue_refresh_token(
+    session_id: i32,
+    tx: &mut Transaction<'_, Postgres>,
+) -> Result<String, ModelError> {
+    let token = generate_token();
+
+    query!(
+        r#"
+        insert into refresh_token (session_id, token_hash, expires_at)
+        values ($1, $2, $3)
+        "#,
+        session_id,
+        hash_token(&token),
+        Utc::now() + Duration::days(REFRESH_TOKEN_TTL_DAYS)
+    )
+    .execute(&mut **tx)
+    .await?;
+
+    Ok(token)
+}
+
+async fn revoke_session(id: i32, tx: &mut Transaction<'_, Postgres>) -> Result<(), ModelError> {
+    query!(
+        r#"
+        update user_session
+        set revoked_at = now()
+        where id = $1
+        "#,
+        id
+    )
+    .execute(&mut **tx)
+    .await?;
+
+    Ok(())
+}
+
+// Revoke every session of the user as part of a larger change.
+pub(super) async fn revoke_user_sessions(
+    user_id: i32,
+    conn: &mut PgConnection,
+) -> Result<u64, ModelError> {
+    let result = query!(
+        r#"
+        update user_session
+        set revoked_at = now()
+        where user_id = $1 and revoked_at is null
+        "#,
+        user_id
+    )
+    .execute(conn)
+    .await?;
+
+    Ok(result.rows_affected())
+}
+
+// A random URL-safe token. Only its hash is stored.
+pub(super) fn generate_token() -> String {
+    let mut bytes = [0u8; 32];
+    OsRng.fill_bytes(&mut bytes);
+    URL_SAFE_NO_PAD.encode(bytes)
+}
+
+pub(super) fn hash_token(token: &str) -> String {
+    format!("{:x}", Sha256::digest(token.as_bytes()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::{
+        audit::ClientInfo,
+        users::{verify_token, UsersModel},
+    };
+
+    async fn login(pool: &PgPool) -> Result<TokenPair> {
+        let email = "test@example.com";
+        let password = "password";
+        UsersModel::create(
+            "test".to_string(),
+            email.to_string(),
+            password.to_string(),
+            &AuditContext::default(),
+            pool,
+        )
+        .await?;
+        Ok(UsersModel::login(
+            email.to_string(),
+            password.to_string(),
+            &ClientInfo::default(),
+            pool,
+        )
+        .await?
+        .unwrap_tokens())
+    }
+
+    #[sqlx::test]
+    async fn refresh(pool: PgPool) -> Result<()> {
+        let tokens = login(&pool).await?;
+        // Refresh with invalid token.
+        assert!(
+            SessionModel::refresh("dummy token".to_string(), &AuditContext::default(), &pool)
+                .await
+                .is_err()
+        );
+        // Refresh.
+        let refreshed = SessionModel::refresh(
+            tokens.refresh_token.clone(),
+            &AuditContext::default(),
+            &pool,
+        )
+        .await?;
+        assert_ne!(refreshed.refresh_token, tokens.refresh_token);
+        let identity = verify_token(&refreshed.access_token, &pool).await?;
+        assert_eq!(
+            identity.session_id,
+            verify_token(&tokens.access_token, &pool).await?.session_id
+        );
+        // Refresh again with the new token.
+        SessionModel::refresh(refreshed.refresh_token, &AuditContext::default(), &pool).await?;
+        Ok(())
+    }
+
+    #[sqlx::test]
+    async fn refresh_reuse(pool: PgPool) -> Result<()> {
+        let tokens = login(&pool).await?;
+        let refreshed = SessionModel::refresh(
+            tokens.refresh_token.clone(),
+            &AuditContext::default(),
+            &pool,
+        )
+        .await?;
+        // Reuse the rotated token.
+        let result =
+            SessionModel::refresh(tokens.refresh_token, &AuditContext::default(), &pool).await;
+        assert_eq!(
+            result.unwrap_err().to_string(),
+            "Refresh token reuse detected."
+        );
+        // The whole chain is revoked.
+        assert!(
+            SessionModel::refresh(refreshed.refresh_token, &AuditContext::default(), &pool)
+                .await
+                .is_err()
+        );
+        assert!(verify_token(&refreshed.access_token, &pool).await.is_err());
+        Ok(())
+    }
+
+    #[sqlx::test]
+    async fn revoke(pool: PgPool) -> Result<()> {
+        let tokens = login(&pool).await?;
+        let identity = verify_token(&tokens.access_token, &pool).await?;
+        // Revoke as another user.
+        SessionModel::revoke(
+            identity.session_id,
+            identity.user_id + 1,
+            &AuditContext::default(),
+            &pool,
+        )
+        .await?;
+        assert!(verify_token(&tokens.access_token, &pool).await.is_ok());
+        // Revoke.
+        SessionModel::revoke(
+            identity.session_id,
+            identity.user_id,
+            &AuditContext::default(),
+            &pool,
+        )
+        .await?;
+        assert!(verify_token(&tokens.access_token, &pool).await.is_err());
+        assert!(
+            SessionModel::refresh(tokens.refresh_token, &AuditContext::default(), &pool)
+                .await
+                .is_err()
+        );
+        Ok(())
+    }
+
+    #[sqlx::test]
+    async fn revoke_all(pool: PgPool) -> Result<()> {
+        let first = login(&pool).await?;
+        let second = UsersModel::login(
+            "test@example.com".to_string(),
+            "password".to_string(),
+            &ClientInfo::default(),
+            &pool,
+        )
+        .await?
+        .unwrap_tokens();
+        let identity = verify_token(&first.access_token, &pool).await?;
+        // Revoke all.
+        let revoked =
+            SessionModel::revoke_all(identity.user_id, &AuditContext::default(), &pool).await?;
+        assert_eq!(revoked, 2);
+        assert!(verify_token(&first.access_token, &pool).await.is_err());
+        assert!(verify_token(&second.access_token, &pool).await.is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,477 @@
+use anyhow::Result;
+use chrono::{DateTime, Duration, Utc};
+use sqlx::{query, PgConnection, PgPool};
+
+use super::{
+    audit::AuditContext,
+    error::ModelError,
+    session::{generate_token, hash_token},
+    users::hash_password,
+};
+
+const PASSWORD_RESET_TTL_MINUTES: i64 = 60;
+const EMAIL_VERIFICATION_TTL_HOURS: i64 = 24;
+const LOGIN_CHALLENGE_TTL_MINUTES: i64 = 5;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, sqlx::Type)]
+#[sqlx(type_name = "user_token_kind", rename_all = "snake_case")]
+pub enum TokenKind {
+    PasswordReset,
+    EmailVerification,
+    LoginChallenge,
+}
+
+// A token to send to the user. Only its hash is stored.
+#[derive(Debug, Clone)]
+pub struct IssuedToken {
+    pub user_id: i32,
+    pub name: String,
+    pub email: String,
+    pub token: String,
+}
+
+// Returned by login instead of tokens when a second factor is needed.
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct MfaChallenge {
+    pub challenge: String,
+    #[serde(rename = "expiresAt")]
+    pub expires_at: DateTime<Utc>,
+}
+
+pub struct UserTokenModel;
+
+impl UserTokenModel {
+    // Returns None for unknown emails, so callers can respond the same either way.
+    // Issuing a token invalidates the user's earlier ones.
+    pub async fn issue_password_reset(
+        email: &str,
+        audit: &AuditContext,
+        pool: &PgPool,
+    ) -> Result<Option<IssuedToken>, ModelError> {
+        let mut tx = pool.begin().await?;
+        let row = query!(
+            r#"
+            select id, name, email
+            from users
+            where email = $1
+            "#,
+            email.trim()
+        )
+        .fetch_optional(&mut *tx)
+        .await?;
+        let issued = match row {
+            Some(row) => {
+                let token = issue(
+                    row.id,
+                    TokenKind::PasswordReset,
+                    None,
+                    Duration::minutes(PASSWORD_RESET_TTL_MINUTES),
+                    &mut tx,
+                )
+                .await?;
+                Some(IssuedToken {
+                    user_id: row.id,
+                    name: row.name,
+                    email: row.email,
+                    token,
+                })
+            }
+            None => None,
+        };
+        let target_id = issued.as_ref().map(|issued| issued.user_id);
+        audit
+            .record("requestPasswordReset", target_id, None, None, &mut tx)
+            .await?;
+        tx.commit().await?;
+
+        Ok(issued)
+    }
+
+    // The token is bound to the user's current email. For tokens sent as part of
+    // another change, such as a new account.
+    pub async fn issue_email_verification(
+        user_id: i32,
+        pool: &PgPool,
+    ) -> Result<IssuedToken, ModelError> {
+        let mut tx = pool.begin().await?;
+        let issued = issue_email_verification(user_id, &mut tx).await?;
+        tx.commit().await?;
+
+        Ok(issued)
+    }
+
+    // Send a new verification token at the user's request.
+    pub async fn resend_email_verification(
+        user_id: i32,
+        audit: &AuditContext,
+        pool: &PgPool,
+    ) -> Result<IssuedToken, ModelError> {
+        let mut tx = pool.begin().await?;
+        let issued = issue_email_verification(user_id, &mut tx).await?;
+        audit
+            .record("sendVerificationEmail", Some(user_id), None, None, &mut tx)
+            .await?;
+        tx.commit().await?;
+
+        Ok(issued)
+    }
+
+    // Set a new password and log out every session. Proves ownership of the email too.
+    // Returns the id of the user.
+    pub async fn reset_password(
+        token: &str,
+        new_password: &str,
+        audit: &AuditContext,
+        pool: &PgPool,
+    ) -> Result<i32, ModelError> {
+        if new_password.is_empty() {
+            return Err(ModelError::validation("Password must not be empty."));
+        }
+        let mut tx = pool.begin().await?;
+        let row = query!(
+            r#"
+            update user_token
+            set used_at = now()
+            where token_hash = $1
+            and kind = $2
+            and used_at is null
+            and expires_at > now()
+            returning user_id
+            "#,
+            hash_token(token),
+            TokenKind::PasswordReset as TokenKind
+        )
+        .fetch_optional(&mut *tx)
+        .await?
+        .ok_or_else(|| ModelError::validation("Invalid or expired token."))?;
+
+        query!(
+            r#"
+            update users
+            set password = $2, email_verified_at = coalesce(email_verified_at, now()), updated_at = now()
+            where id = $1
+            "#,
+            row.user_id,
+            hash_password(new_password)
+        )
+        .execute(&mut *tx)
+        .await?;
+        query!(
+            r#"
+            update user_session
+            set revoked_at = now()
+            where user_id = $1 and revoked_at is null
+            "#,
+            row.user_id
+        )
+        .execute(&mut *tx)
+        .await?;
+        audit
+            .record("resetPassword", Some(row.user_id), None, None, &mut tx)
+            .await?;
+        tx.commit().await?;
+
+        Ok(row.user_id)
+    }
+
+    pub async fn issue_login_challenge(
+        user_id: i32,
+        pool: &PgPool,
+    ) -> Result<MfaChallenge, ModelError> {
+        let ttl = Duration::minutes(LOGIN_CHALLENGE_TTL_MINUTES);
+        let mut tx = pool.begin().await?;
+        let challenge = issue(user_id, TokenKind::LoginChallenge, None, ttl, &mut tx).await?;
+        tx.commit().await?;
+        Ok(MfaChallenge {
+            challenge,
+            expires_at: Utc::now() + ttl,
+        })
+    }
+
+    // The challenge stays valid after a wrong code, so the user can try again.
+    // Returns the id of the user.
+    pub async fn find_login_challenge(challenge: &str, pool: &PgPool) -> Result<i32, ModelError> {
+        let row = query!(
+            r#"
+            select user_id
+            from user_token
+            where token_hash = $1
+            and kind = $2
+            and used_at is null
+            and expires_at > now()
+            "#,
+            hash_token(challenge),
+            TokenKind::LoginChallenge as TokenKind
+        )
+        .fetch_optional(pool)
+        .await?
+        .ok_or_else(|| ModelError::unauthenticated("Invalid or expired challenge."))?;
+
+        Ok(row.user_id)
+    }
+
+    pub async fn consume_login_challenge(challenge: &str, pool: &PgPool) -> Result<(), ModelError> {
+        let result = query!(
+            r#"
+            update user_token
+            set used_at = now()
+            where token_hash = $1
+            and kind = $2
+            and used_at is null
+            and expires_at > now()
+            "#,
+            hash_token(challenge),
+            TokenKind::LoginChallenge as TokenKind
+        )
+        .execute(pool)
+        .await?;
+        if result.rows_affected() == 0 {
+            return Err(ModelError::unauthenticated("Invalid or expired challenge."));
+        }
+        Ok(())
+    }
+
+    // Tokens sent to an address the user has since changed are rejected.
+    // Returns the id of the user.
+    pub async fn verify_email(
+        token: &str,
+        audit: &AuditContext,
+        pool: &PgPool,
+    ) -> Result<i32, ModelError> {
+        let mut tx = pool.begin().await?;
+        let row = query!(
+            r#"
+            with consumed as (
+                update user_token
+                set used_at = now()
+                where token_hash = $1
+                and kind = $2
+                and used_at is null
+                and expires_at > now()
+                returning user_id, email
+            )
+            update users u
+            set email_verified_at = coalesce(u.email_verified_at, now())
+            from consumed c
+            where u.id = c.user_id
+            and u.email = c.email
+            returning u.id
+            "#,
+            hash_token(token),
+            TokenKind::EmailVerification as TokenKind
+        )
+        .fetch_optional(&mut *tx)
+        .await?
+        .ok_or_else(|| ModelError::validation("Invalid or expired token."))?;
+        audit
+            .record("verifyEmail", Some(row.id), None, None, &mut tx)
+            .await?;
+        tx.commit().await?;
+
+        Ok(row.id)
+    }
+}
+
+async fn issue_email_verification(
+    user_id: i32,
+    conn: &mut PgConnection,
+) -> Result<IssuedToken, ModelError> {
+    let row = query!(
+        r#"
+        select name, email, email_verified_at
+        from users
+        where id = $1
+        "#,
+        user_id
+    )
+    .fetch_optional(&mut *conn)
+    .await?
+    .ok_or_else(|| ModelError::not_found("User not found."))?;
+    if row.email_verified_at.is_some() {
+        return Err(ModelError::conflict("Email already verified."));
+    }
+
+    let token = issue(
+        user_id,
+        TokenKind::EmailVerification,
+        Some(&row.email),
+        Duration::hours(EMAIL_VERIFICATION_TTL_HOURS),
+        conn,
+    )
+    .await?;
+    Ok(IssuedToken {
+        user_id,
+        name: row.name,
+        email: row.email,
+        token,
+    })
+}
+
+async fn issue(
+    user_id: i32,
+    kind: TokenKind,
+    email: Option<&str>,
+    ttl: Duration,
+    conn: &mut PgConnection,
+) -> Result<String, ModelError> {
+    let token = generate_token();
+    query!(
+        r#"
+        update user_token
+        set used_at = now()
+        where user_id = $1 and kind = $2 and used_at is null
+        "#,
+        user_id,
+        kind as TokenKind
+    )
+    .execute(&mut *conn)
+    .await?;
+    query!(
+        r#"
+        insert into user_token (user_id, kind, token_hash, email, expires_at)
+        values ($1, $2, $3, $4, $5)
+        "#,
+        user_id,
+        kind as TokenKind,
+        hash_token(&token),
+        email,
+        Utc::now() + ttl
+    )
+    .execute(conn)
+    .await?;
+
+    Ok(token)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::{
+        audit::ClientInfo,
+        users::{verify_token, UsersModel},
+    };
+
+    async fn create_user(pool: &PgPool) -> Result<i32> {
+        let user = UsersModel::create(
+            "test".to_string(),
+            "test@example.com".to_string(),
+            "password".to_string(),
+            &AuditContext::default(),
+            pool,
+        )
+        .await?;
+        Ok(user.id)
+    }
+
+    #[sqlx::test]
+    async fn password_reset(pool: PgPool) -> Result<()> {
+        let user_id = create_user(&pool).await?;
+        let session = UsersModel::login(
+            "test@example.com".to_string(),
+            "password".to_string(),
+            &ClientInfo::default(),
+            &pool,
+        )
+        .await?
+        .unwrap_tokens();
+        assert!(UserTokenModel::issue_password_reset(
+            "unknown@example.com",
+            &AuditContext::default(),
+            &pool
+        )
+        .await?
+        .is_none());
+
+        // Only the latest token works, and only once.
+        let first = UserTokenModel::issue_password_reset(
+            "test@example.com",
+            &AuditContext::default(),
+            &pool,
+        )
+        .await?
+        .unwrap();
+        let second = UserTokenModel::issue_password_reset(
+            "test@example.com",
+            &AuditContext::default(),
+            &pool,
+        )
+        .await?
+        .unwrap();
+        let result = UserTokenModel::reset_password(
+            &first.token,
+            "new password",
+            &AuditContext::default(),
+            &pool,
+        )
+        .await;
+        assert!(matches!(result, Err(ModelError::Validation(_))));
+        assert_eq!(
+            UserTokenModel::reset_password(
+                &second.token,
+                "new password",
+                &AuditContext::default(),
+                &pool
+            )
+            .await?,
+            user_id
+        );
+        let result =
+            UserTokenModel::reset_password(&second.token, "again", &AuditContext::default(), &pool)
+                .await;
+        assert!(matches!(result, Err(ModelError::Validation(_))));
+
+        // Sessions are logged out and the new password works.
+        assert!(verify_token(&session.access_token, &pool).await.is_err());
+        UsersModel::login(
+            "test@example.com".to_string(),
+            "new password".to_string(),
+            &ClientInfo::default(),
+            &pool,
+        )
+        .await?
+        .unwrap_tokens();
+        Ok(())
+    }
+
+    #[sqlx::test]
+    async fn email_verification(pool: PgPool) -> Result<()> {
+        let user_id = create_user(&pool).await?;
+
+        // Tokens sent before an email change are rejected.
+        let stale = UserTokenModel::issue_email_verification(user_id, &pool).await?;
+        UsersModel::update_profile(
+            user_id,
+            None,
+            Some("new@example.com".to_string()),
+            &AuditContext::default(),
+            &pool,
+        )
+        .await?;
+        let result =
+            UserTokenModel::verify_email(&stale.token, &AuditContext::default(), &pool).await;
+        assert!(matches!(result, Err(ModelError::Validation(_))));
+
+        let issued = UserTokenModel::issue_email_verification(user_id, &pool).await?;
+        assert_eq!(issued.email, "new@example.com");
+        assert_eq!(
+            UserTokenModel::verify_email(&issued.token, &AuditContext::default(), &pool).await?,
+            user_id
+        );
+        let user = &UsersModel::find_by_ids(&[user_id], &pool).await?[0];
+        assert!(user.email_verified_at.is_some());
+        let result = UserTokenModel::issue_email_verification(user_id, &pool).await;
+        assert!(matches!(result, Err(ModelError::Conflict(_))));
+
+        // Changing the email again needs a new verification.
+        let user = UsersModel::update_profile(
+            user_id,
+            None,
+            Some("other@example.com".to_string()),
+            &AuditContext::default(),
+            &pool,
+        )
+        .await?;
+        assert!(user.email_verified_at.is_none());
+        Ok(())
+    }
+}
@@ -0,0 +1,467 @@
+use argon2::password_hash::rand_core::{OsRng, RngCore};
+use chrono::Utc;
+use hmac::{Hmac, Mac};
+use percent_encoding::{utf8_percent_encode, NON_ALPHANUMERIC};
+use sha1::Sha1;
+use sqlx::{query, PgPool};
+
+use super::{audit::AuditContext, error::ModelError, session::hash_token};
+
+const ISSUER: &str = "Pocket Change";
+const SECRET_LENGTH: usize = 20;
+const STEP_SECONDS: i64 = 30;
+const DIGITS: u32 = 6;
+// Codes from the previous and next time step are accepted to allow for clock drift.
+const ALLOWED_DRIFT_STEPS: i64 = 1;
+const RECOVERY_CODE_COUNT: usize = 10;
+const BASE32_ALPHABET: &[u8; 32] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZ234567";
+
+// What an authenticator app needs to add the account.
+#[derive(Debug, Clone)]
+pub struct TotpEnrollment {
+    // Base32, for entering by hand.
+    pub secret: String,
+    // otpauth:// URI, usually shown as a QR code.
+    pub uri: String,
+}
+
+pub struct TotpModel;
+
+impl TotpModel {
+    // Start over with a new secret until the enrollment is confirmed.
+    pub async fn enroll(
+        user_id: i32,
+        audit: &AuditContext,
+        pool: &PgPool,
+    ) -> Result<TotpEnrollment, ModelError> {
+        if Self::is_enabled(user_id, pool).await? {
+            return Err(ModelError::conflict(
+                "Two-factor authentication is already enabled.",
+            ));
+        }
+        let user = query!(
+            r#"
+            select email
+            from users
+            where id = $1
+            "#,
+            user_id
+        )
+        .fetch_optional(pool)
+        .await?
+        .ok_or_else(|| ModelError::not_found("User not found."))?;
+
+        let mut secret = [0u8; SECRET_LENGTH];
+        OsRng.fill_bytes(&mut secret);
+        let mut tx = pool.begin().await?;
+        query!(
+            r#"
+            insert into user_totp (user_id, secret)
+            values ($1, $2)
+            on conflict (user_id) do update
+            set secret = excluded.secret, last_used_step = null, created_at = now()
+            "#,
+            user_id,
+            &secret[..]
+        )
+        .execute(&mut *tx)
+        .await?;
+        audit
+            .record("enrollTotp", Some(user_id), None, None, &mut tx)
+            .await?;
+        tx.commit().await?;
+
+        let secret = base32(&secret);
+        let uri = format!(
+            "otpauth://totp/{issuer}:{email}?secret={secret}&issuer={issuer}&algorithm=SHA1&digits={digits}&period={period}",
+            issuer = utf8_percent_encode(ISSUER, NON_ALPHANUMERIC),
+            email = utf8_percent_encode(&user.email, NON_ALPHANUMERIC),
+            secret = secret,
+            digits = DIGITS,
+            period = STEP_SECONDS,
+        );
+        Ok(TotpEnrollment { secret, uri })
+    }
+
+    // Enable two-factor authentication once the user proves their app has the secret.
+    // Returns the recovery codes, which are only shown this once.
+    pub async fn confirm(
+        user_id: i32,
+        code: &str,
+        audit: &AuditContext,
+        pool: &PgPool,
+    ) -> Result<Vec<String>, ModelError> {
+        let row = query!(
+            r#"
+            select confirmed_at
+            from user_totp
+            where user_id = $1
+            "#,
+            user_id
+        )
+        .fetch_optional(pool)
+        .await?
+        .ok_or_else(|| ModelError::validation("Enroll before confirming."))?;
+        if row.confirmed_at.is_some() {
+            return Err(ModelError::conflict(
+                "Two-factor authentication is already enabled.",
+            ));
+        }
+        if !verify_code(user_id, code, pool).await? {
+            return Err(ModelError::validation("Invalid code."));
+        }
+
+        let codes = (0..RECOVERY_CODE_COUNT)
+            .map(|_| generate_recovery_code())
+            .collect::<Vec<_>>();
+        let hashes = codes
+            .iter()
+            .map(|code| hash_token(&normalize_recovery_code(code)))
+            .collect::<Vec<_>>();
+        let mut tx = pool.begin().await?;
+        query!(
+            r#"
+            update user_totp
+            set confirmed_at = now()
+            where user_id = $1
+            "#,
+            user_id
+        )
+        .execute(&mut *tx)
+        .await?;
+        query!(
+            r#"
+            delete from totp_recovery_code
+            where user_id = $1
+            "#,
+            user_id
+        )
+        .execute(&mut *tx)
+        .await?;
+        query!(
+            r#"
+            insert into totp_recovery_code (user_id, code_hash)
+            select $1, unnest($2::varchar[])
+            "#,
+            user_id,
+            &hashes
+        )
+        .execute(&mut *tx)
+        .await?;
+        audit
+            .record("confirmTotp", Some(user_id), None, None, &mut tx)
+            .await?;
+        tx.commit().await?;
+
+        Ok(codes)
+    }
+
+    // Takes a code or a recovery code, so a stolen session alone cannot turn it off.
+    pub async fn disable(
+        user_id: i32,
+        code: &str,
+        audit: &AuditContext,
+        pool: &PgPool,
+    ) -> Result<(), ModelError> {
+        if !Self::is_enabled(user_id, pool).await? {
+            return Err(ModelError::validation(
+                "Two-factor authentication is not enabled.",
+            ));
+        }
+        if !Self::verify(user_id, code, pool).await? {
+            return Err(ModelError::validation("Invalid code."));
+        }
+
+        let mut tx = pool.begin().await?;
+        query!(
+            r#"
+            delete from totp_recovery_code
+            where user_id = $1
+            "#,
+            user_id
+        )
+        .execute(&mut *tx)
+        .await?;
+        query!(
+            r#"
+            delete from user_totp
+            where user_id = $1
+            "#,
+            user_id
+        )
+        .execute(&mut *tx)
+        .await?;
+        audit
+            .record("disableTotp", Some(user_id), None, None, &mut tx)
+            .await?;
+        tx.commit().await?;
+        Ok(())
+    }
+
+    pub async fn is_enabled(user_id: i32, pool: &PgPool) -> Result<bool, ModelError> {
+        let row = query!(
+            r#"
+            select exists (
+                select 1
+                from user_totp
+                where user_id = $1 and confirmed_at is not null
+            ) as "enabled!"
+            "#,
+            user_id
+        )
+        .fetch_one(pool)
+        .await?;
+        Ok(row.enabled)
+    }
+
+    // Check a code from the app, or use up a recovery code.
+    pub async fn verify(user_id: i32, code: &str, pool: &PgPool) -> Result<bool, ModelError> {
+        let code = code.trim();
+        if code.len() == DIGITS as usize && code.bytes().all(|b| b.is_ascii_digit()) {
+            return verify_code(user_id, code, pool).await;
+        }
+
+        let row = query!(
+            r#"
+            update totp_recovery_code
+            set used_at = now()
+            where id = (
+                select id
+                from totp_recovery_code
+                where user_id = $1 and code_hash = $2 and used_at is null
+                limit 1
+            )
+            and used_at is null
+            returning id
+            "#,
+            user_id,
+            hash_token(&normalize_recovery_code(code))
+        )
+        .fetch_optional(pool)
+        .await?;
+        Ok(row.is_some())
+    }
+}
+
+// Accept a code for a time step after the last accepted one, so codes cannot be replayed.
+async fn verify_code(user_id: i32, code: &str, pool: &PgPool) -> Result<bool, ModelError> {
+    let row = query!(
+        r#"
+        select secret, last_used_step
+        from user_totp
+        where user_id = $1
+        "#,
+        user_id
+    )
+    .fetch_optional(pool)
+    .await?;
+    let row = match row {
+        Some(row) => row,
+        None => return Ok(false),
+    };
+
+    let current = Utc::now().timestamp() / STEP_SECONDS;
+    let step = (current - ALLOWED_DRIFT_STEPS..=current + ALLOWED_DRIFT_STEPS)
+        .filter(|step| row.last_used_step.is_none_or(|last| *step > last))
+        .find(|step| format_code(totp(&row.secret, *step)) == code);
+    let step = match step {
+        Some(step) => step,
+        None => return Ok(false),
+    };
+
+    // Guard against the same code being accepted by two requests at once.
+    let result = query!(
+        r#"
+        update user_totp
+        set last_used_step = $2
+        where user_id = $1
+        and (last_used_step is null or last_used_step < $2)
+        "#,
+        user_id,
+        step
+    )
+    .execute(pool)
+    .await?;
+    Ok(result.rows_affected() > 0)
+}
+
+// HOTP (RFC 4226) of the time step.
+fn totp(secret: &[u8], step: i64) -> u32 {
+    let mut mac = Hmac::<Sha1>::new_from_slice(secret).expect("HMAC accepts any key length.");
+    mac.update(&step.to_be_bytes());
+    let hash = mac.finalize().into_bytes();
+    let offset = (hash[hash.len() - 1] & 0x0f) as usize;
+    let value = u32::from_be_bytes([
+        hash[offset],
+        hash[offset + 1],
+        hash[offset + 2],
+        hash[offset + 3],
+    ]) & 0x7fff_ffff;
+    value % 10u32.pow(DIGITS)
+}
+
+fn format_code(code: u32) -> String {
+    format!("{:0width$}", code, width = DIGITS as usize)
+}
+
+// Base32 (RFC 4648) without padding, as authenticator apps expect.
+fn base32(bytes: &[u8]) -> String {
+    let mut encoded = String::new();
+    let mut buffer = 0u32;
+    let mut bits = 0;
+    for &byte in bytes {
+        buffer = (buffer << 8) | byte as u32;
+        bits += 8;
+        while bits >= 5 {
+            bits -= 5;
+            encoded.push(BASE32_ALPHABET[((buffer >> bits) & 0x1f) as usize] as char);
+        }
+    }
+    if bits > 0 {
+        encoded.push(BASE32_ALPHABET[((buffer << (5 - bits)) & 0x1f) as usize] as char);
+    }
+    encoded
+}
+
+// Ten base32 characters, e.g. "ABCDE-FGH23".
+fn generate_recovery_code() -> String {
+    let mut bytes = [0u8; 8];
+    OsRng.fill_bytes(&mut bytes);
+    let code = base32(&bytes);
+    format!("{}-{}", &code[..5], &code[5..10])
+}
+
+fn normalize_recovery_code(code: &str) -> String {
+    code.chars()
+        .filter(|c| c.is_ascii_alphanumeric())
+        .map(|c| c.to_ascii_uppercase())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::{
+        audit::ClientInfo,
+        users::{verify_token, LoginOutcome, UsersModel},
+    };
+    use anyhow::Result;
+
+    #[test]
+    fn rfc_6238_codes() {
+        // Test vectors from RFC 6238, truncated to six digits.
+        let secret = b"12345678901234567890";
+        assert_eq!(format_code(totp(secret, 59 / STEP_SECONDS)), "287082");
+        assert_eq!(
+            format_code(totp(secret, 1111111109 / STEP_SECONDS)),
+            "081804"
+        );
+        assert_eq!(
+            format_code(totp(secret, 2000000000 / STEP_SECONDS)),
+            "279037"
+        );
+        assert_eq!(base32(b"foobar"), "MZXW6YTBOI");
+    }
+
+    async fn current_code(user_id: i32, pool: &PgPool) -> Result<String> {
+        let row = query!("select secret from user_totp where user_id = $1", user_id)
+            .fetch_one(pool)
+            .await?;
+        let step = Utc::now().timestamp() / STEP_SECONDS;
+        Ok(format_code(totp(&row.secret, step)))
+    }
+
+    #[sqlx::test]
+    async fn enroll_and_verify(pool: PgPool) -> Result<()> {
+        let user = UsersModel::create(
+            "test".to_string(),
+            "test@example.com".to_string(),
+            "password".to_string(),
+            &AuditContext::default(),
+            &pool,
+        )
+        .await?;
+        let enrollment = TotpModel::enroll(user.id, &AuditContext::default(), &pool).await?;
+        assert_eq!(enrollment.secret.len(), 32);
+        assert!(enrollment
+            .uri
+            .starts_with("otpauth://totp/Pocket%20Change:test%40example%2Ecom?secret="));
+        assert!(!TotpModel::is_enabled(user.id, &pool).await?);
+
+        let result = TotpModel::confirm(user.id, "000000", &AuditContext::default(), &pool).await;
+        assert!(matches!(result, Err(ModelError::Validation(_))));
+        let code = current_code(user.id, &pool).await?;
+        let recovery_codes =
+            TotpModel::confirm(user.id, &code, &AuditContext::default(), &pool).await?;
+        assert_eq!(recovery_codes.len(), RECOVERY_CODE_COUNT);
+        assert!(TotpModel::is_enabled(user.id, &pool).await?);
+        let result = TotpModel::enroll(user.id, &AuditContext::default(), &pool).await;
+        assert!(matches!(result, Err(ModelError::Conflict(_))));
+
+        // Codes cannot be replayed.
+        assert!(!TotpModel::verify(user.id, &code, &pool).await?);
+
+        // Recovery codes work once, in any case.
+        let recovery_code = recovery_codes[0].to_lowercase();
+        assert!(TotpModel::verify(user.id, &recovery_code, &pool).await?);
+        assert!(!TotpModel::verify(user.id, &recovery_code, &pool).await?);
+
+        let result = TotpModel::disable(user.id, "wrong", &AuditContext::default(), &pool).await;
+        assert!(matches!(result, Err(ModelError::Validation(_))));
+        TotpModel::disable(user.id, &recovery_codes[1], &AuditContext::default(), &pool).await?;
+        assert!(!TotpModel::is_enabled(user.id, &pool).await?);
+        Ok(())
+    }
+
+    #[sqlx::test]
+    async fn two_step_login(pool: PgPool) -> Result<()> {
+        let user = UsersModel::create(
+            "test".to_string(),
+            "test@example.com".to_string(),
+            "password".to_string(),
+            &AuditContext::default(),
+            &pool,
+        )
+        .await?;
+        TotpModel::enroll(user.id, &AuditContext::default(), &pool).await?;
+        let recovery_codes = TotpModel::confirm(
+            user.id,
+            &current_code(user.id, &pool).await?,
+            &AuditContext::default(),
+            &pool,
+        )
+        .await?;
+
+        let client = ClientInfo::default();
+        let login = || {
+            UsersModel::login(
+                "test@example.com".to_string(),
+                "password".to_string(),
+                &client,
+                &pool,
+            )
+        };
+        let challenge = match login().await? {
+            LoginOutcome::MfaRequired(challenge) => challenge.challenge,
+            LoginOutcome::Tokens(_) => panic!("Expected an MFA challenge."),
+        };
+        let result =
+            UsersModel::complete_login(challenge.clone(), "wrong".to_string(), &client, &pool)
+                .await;
+        assert!(matches!(result, Err(ModelError::Unauthenticated(_))));
+
+        // The challenge can only be used once.
+        let tokens = UsersModel::complete_login(
+            challenge.clone(),
+            recovery_codes[0].clone(),
+            &client,
+            &pool,
+        )
+        .await?;
+        verify_token(&tokens.access_token, &pool).await?;
+        let result =
+            UsersModel::complete_login(challenge, recovery_codes[1].clone(), &client, &pool).await;
+        assert!(matches!(result, Err(ModelError::Unauthenticated(_))));
+        Ok(())
+    }
+}
@@ -1,10 +1,27 @@
-use anyhow::Error;
 use argon2::{
     password_hash::{rand_core::OsRng, PasswordHash, PasswordHasher, SaltString},
     Argon2, PasswordVerifier,
 };
 use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::sync::OnceLock;
+
+use super::{
+    audit::{AuditContext, AuditEntry, AuditEventModel, ClientInfo},
+    channel,
+    error::{is_unique_violation, ModelError},
+    events, jwt,
+    login_failure::LoginFailureModel,
+    message,
+    oidc::{ExternalIdentity, OidcModel},
+    session::{self, SessionModel, TokenPair},
+    token::{MfaChallenge, UserTokenModel},
+    totp::TotpModel,
+};
+
+const ACCESS_TOKEN_TTL_MINUTES: i64 = 15;
+const MAX_FIELD_LENGTH: usize = 255;
 
 #[derive(Debug, serde::Deserialize, serde::Serialize)]
 pub struct UsersModel {
@@ -18,36 +35,96 @@ pub struct UsersModel {
     pub updated_at: DateTime<Utc>,
 }
 
-#[derive(Debug, serde::Deserialize, serde::Serialize)]
+#[derive(Debug, Clone, serde::Deserialize, serde::Serialize)]
 pub struct UsersModelResponse {
     pub id: i32,
     pub name: String,
     pub email: String,
+    #[serde(rename = "emailVerifiedAt")]
+    pub email_verified_at: Option<DateTime<Utc>>,
+}
+
+// Roles are ordered, so a role includes the permissions of the roles before it.
+#[derive(
+    Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize, sqlx::Type,
+)]
+#[serde(rename_all = "lowercase")]
+#[sqlx(type_name = "user_role", rename_all = "lowercase")]
+pub enum Role {
+    #[default]
+    User,
+    Moderator,
+    Admin,
+}
+
+// What happens to the messages of a deleted account.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum DeletionPolicy {
+    // Keep the messages without an author.
+    #[default]
+    Anonymize,
+    // Erase the messages. Messages with replies stay as tombstones so threads stay intact.
+    Delete,
+}
+
+impl std::str::FromStr for DeletionPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "anonymize" => Ok(DeletionPolicy::Anonymize),
+            "delete" => Ok(DeletionPolicy::Delete),
+            _ => Err(format!("Unknown deletion policy: {}", s)),
+        }
+    }
 }
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct Claims {
     pub sub: String,
+    pub sid: i32,
+    // Tokens issued before roles existed are treated as plain users.
+    #[serde(default)]
+    pub role: Role,
     pub exp: usize,
 }
 
+#[derive(Debug)]
+pub enum LoginOutcome {
+    Tokens(TokenPair),
+    // Users with two-factor authentication finish with `complete_login`.
+    MfaRequired(MfaChallenge),
+}
+
+#[cfg(test)]
+impl LoginOutcome {
+    pub fn unwrap_tokens(self) -> TokenPair {
+        match self {
+            LoginOutcome::Tokens(tokens) => tokens,
+            LoginOutcome::MfaRequired(_) => panic!("Expected tokens, got an MFA challenge."),
+        }
+    }
+}
+
+// The user and session an access token was issued for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Identity {
+    pub user_id: i32,
+    pub session_id: i32,
+    pub role: Role,
+}
+
 impl UsersModel {
     pub async fn create(
         name: String,
         email: String,
         password: String,
+        audit: &AuditContext,
         pool: &sqlx::PgPool,
-    ) -> Result<UsersModelResponse, Error> {
-        // Hash password
-        let salt = SaltString::generate(&mut OsRng);
-        let argon2 = Argon2::default();
-        let password_hash = argon2
-            .hash_password(password.as_bytes(), &salt)
-            .expect("Unable to hash password.")
-            .to_string();
-        let parsed_hash = PasswordHash::new(&password_hash).expect("Unable to parse hash.");
-        println!("parsed_hash: {}", parsed_hash);
+    ) -> Result<UsersModelResponse, ModelError> {
+        let password_hash = hash_password(&password);
 
+        let mut tx = pool.begin().await?;
         let row = sqlx::query_as!(
             UsersModel,
             r#"
@@ -59,21 +136,277 @@ impl UsersModel {
             email,
             password_hash
         )
-        .fetch_one(pool)
-        .await?;
-
-        Ok(UsersModelResponse {
+        .fetch_one(&mut *tx)
+        .await
+        .map_err(|e| {
+            if is_unique_violation(&e) {
+                ModelError::conflict("Email already registered.")
+            } else {
+                e.into()
+            }
+        })?;
+        channel::join_default(row.id, &mut tx).await?;
+        let user = UsersModelResponse {
             id: row.id,
             name: row.name,
             email: row.email,
-        })
+            email_verified_at: None,
+        };
+        audit
+            .record(
+                "createUser",
+                Some(user.id),
+                None,
+                Some(json!(user)),
+                &mut tx,
+            )
+            .await?;
+        tx.commit().await?;
+
+        Ok(user)
     }
 
+    pub async fn find_by_ids(
+        ids: &[i32],
+        pool: &sqlx::PgPool,
+    ) -> Result<Vec<UsersModelResponse>, ModelError> {
+        let rows = sqlx::query_as!(
+            UsersModelResponse,
+            r#"
+            SELECT id, name, email, email_verified_at
+            FROM users
+            WHERE id = ANY($1)
+            "#,
+            ids
+        )
+        .fetch_all(pool)
+        .await?;
+
+        Ok(rows)
+    }
+
+    // Change the name and/or email. Omitted fields are kept.
+    // A new email has to be verified again.
+    pub async fn update_profile(
+        id: i32,
+        name: Option<String>,
+        email: Option<String>,
+        audit: &AuditContext,
+        pool: &sqlx::PgPool,
+    ) -> Result<UsersModelResponse, ModelError> {
+        let name = name.map(|name| name.trim().to_string());
+        let email = email.map(|email| email.trim().to_string());
+        let is_valid = |value: &Option<String>| {
+            value
+                .as_ref()
+                .is_none_or(|value| !value.is_empty() && value.chars().count() <= MAX_FIELD_LENGTH)
+        };
+        if !is_valid(&name) {
+            return Err(ModelError::validation(
+                "Name must be between 1 and 255 characters.",
+            ));
+        }
+        if !is_valid(&email) {
+            return Err(ModelError::validation(
+                "Email must be between 1 and 255 characters.",
+            ));
+        }
+
+        let mut tx = pool.begin().await?;
+        let before = sqlx::query_as!(
+            UsersModelResponse,
+            r#"
+            SELECT id, name, email, email_verified_at
+            FROM users
+            WHERE id = $1
+            FOR UPDATE
+            "#,
+            id
+        )
+        .fetch_optional(&mut *tx)
+        .await?
+        .ok_or_else(|| ModelError::not_found("User not found."))?;
+        let row = sqlx::query_as!(
+            UsersModelResponse,
+            r#"
+            UPDATE users
+            SET name = coalesce($2, name),
+                email = coalesce($3, email),
+                email_verified_at = CASE WHEN $3 IS NULL OR $3 = email THEN email_verified_at END,
+                updated_at = now()
+            WHERE id = $1
+            RETURNING id, name, email, email_verified_at
+            "#,
+            id,
+            name,
+            email
+        )
+        .fetch_one(&mut *tx)
+        .await
+        .map_err(|e| {
+            if is_unique_violation(&e) {
+                ModelError::conflict("Email already registered.")
+            } else {
+                e.into()
+            }
+        })?;
+        audit
+            .record(
+                "updateProfile",
+                Some(id),
+                Some(json!(before)),
+                Some(json!(row)),
+                &mut tx,
+            )
+            .await?;
+        tx.commit().await?;
+
+        Ok(row)
+    }
+
+    // Every existing session is revoked. Returns the tokens of a new session.
+    pub async fn change_password(
+        id: i32,
+        current_password: String,
+        new_password: String,
+        audit: &AuditContext,
+        pool: &sqlx::PgPool,
+    ) -> Result<TokenPair, ModelError> {
+        check_current_password(id, &current_password, pool).await?;
+        if new_password.is_empty() {
+            return Err(ModelError::validation("Password must not be empty."));
+        }
+
+        let mut tx = pool.begin().await?;
+        sqlx::query!(
+            r#"
+            UPDATE users
+            SET password = $2, updated_at = now()
+            WHERE id = $1
+            "#,
+            id,
+            hash_password(&new_password)
+        )
+        .execute(&mut *tx)
+        .await?;
+        session::revoke_user_sessions(id, &mut tx).await?;
+        audit
+            .record("changePassword", Some(id), None, None, &mut tx)
+            .await?;
+        tx.commit().await?;
+        let tokens = SessionModel::create(id, pool).await?;
+        Ok(tokens)
+    }
+
+    // Remove the account, its sessions, memberships, reactions and notifications.
+    // Messages are kept or erased according to the policy.
+    pub async fn delete_account(
+        id: i32,
+        password: String,
+        policy: DeletionPolicy,
+        audit: &AuditContext,
+        pool: &sqlx::PgPool,
+    ) -> Result<(), ModelError> {
+        check_current_password(id, &password, pool).await?;
+
+        let mut tx = pool.begin().await?;
+        let before = sqlx::query_as!(
+            UsersModelResponse,
+            r#"
+            SELECT id, name, email, email_verified_at
+            FROM users
+            WHERE id = $1
+            FOR UPDATE
+            "#,
+            id
+        )
+        .fetch_one(&mut *tx)
+        .await?;
+        let deletions = match policy {
+            DeletionPolicy::Delete => message::erase_by_user(id, &mut tx).await?,
+            DeletionPolicy::Anonymize => Vec::new(),
+        };
+        // Remaining messages, revisions and moderation records lose their author.
+        sqlx::query!(
+            r#"
+            DELETE FROM users
+            WHERE id = $1
+            "#,
+            id
+        )
+        .execute(&mut *tx)
+        .await?;
+        audit
+            .record(
+                "deleteAccount",
+                Some(id),
+                Some(json!(before)),
+                None,
+                &mut tx,
+            )
+            .await?;
+        tx.commit().await?;
+
+        for deletion in deletions {
+            events::publish(deletion, pool).await;
+        }
+
+        Ok(())
+    }
+
+    // The new role applies to the user's next request.
+    pub async fn set_role(
+        id: i32,
+        role: Role,
+        audit: &AuditContext,
+        pool: &sqlx::PgPool,
+    ) -> Result<Role, ModelError> {
+        let mut tx = pool.begin().await?;
+        let before = sqlx::query!(
+            r#"
+            SELECT role AS "role: Role"
+            FROM users
+            WHERE id = $1
+            FOR UPDATE
+            "#,
+            id
+        )
+        .fetch_optional(&mut *tx)
+        .await?
+        .ok_or_else(|| ModelError::not_found("User not found."))?;
+        let row = sqlx::query!(
+            r#"
+            UPDATE users
+            SET role = $1, updated_at = now()
+            WHERE id = $2
+            RETURNING role AS "role: Role"
+            "#,
+            role as Role,
+            id
+        )
+        .fetch_one(&mut *tx)
+        .await?;
+        audit
+            .record(
+                "setUserRole",
+                Some(id),
+                Some(json!({ "role": before.role })),
+                Some(json!({ "role": row.role })),
+                &mut tx,
+            )
+            .await?;
+        tx.commit().await?;
+
+        Ok(row.role)
+    }
+
+    // Every attempt is recorded in the audit log. Failed attempts have no actor.
     pub async fn login(
         email: String,
         password: String,
+        client: &ClientInfo,
         pool: &sqlx::PgPool,
-    ) -> Result<String, Error> {
+    ) -> Result<LoginOutcome, ModelError> {
         let row = sqlx::query_as!(
             UsersModel,
             r#"
@@ -83,41 +416,274 @@ impl UsersModel {
             "#,
             email
         )
-        .fetch_one(pool)
+        .fetch_optional(pool)
         .await?;
+        let user_id = row.as_ref().map(|row| row.id);
+        let ip = client.ip.as_deref();
+        let result = match LoginFailureModel::check(&email, ip, pool).await {
+            Ok(()) => match row {
+                Some(row) => check_password(row, &password, pool).await,
+                // Unknown emails get the same error after the same work as wrong passwords.
+                None => {
+                    verify_password(&password, dummy_hash());
+                    Err(ModelError::unauthenticated("Invalid email or password."))
+                }
+            },
+            Err(e) => Err(e),
+        };
+        // Failures are only cleared once the second factor is checked too.
+        match &result {
+            Ok(LoginOutcome::Tokens(_)) => LoginFailureModel::clear(&email, pool).await?,
+            Ok(LoginOutcome::MfaRequired(_)) => {}
+            Err(ModelError::Unauthenticated(_)) => {
+                LoginFailureModel::record_failure(&email, ip, pool).await?
+            }
+            Err(_) => {}
+        }
 
-        // Check password
-        let argon2 = Argon2::default();
-        let parsed_hash = PasswordHash::new(&row.password).expect("Unable to parse hash.");
-        if argon2
-            .verify_password(password.as_bytes(), &parsed_hash)
-            .is_ok()
-        {
-            let token = encode_token(row.id)?;
-            Ok(token)
-        } else {
-            Err(anyhow::anyhow!("Invalid password."))
+        let succeeded = matches!(result, Ok(LoginOutcome::Tokens(_)));
+        AuditEventModel::record(
+            AuditEntry {
+                actor_id: user_id.filter(|_| succeeded),
+                action: "login".to_string(),
+                target_id: user_id,
+                before: None,
+                after: Some(json!({
+                    "email": email,
+                    "succeeded": succeeded,
+                    "mfaRequired": matches!(result, Ok(LoginOutcome::MfaRequired(_))),
+                    "locked": matches!(result, Err(ModelError::Locked(_))),
+                })),
+            },
+            client,
+            pool,
+        )
+        .await?;
+        result
+    }
+
+    // Finish a login with two-factor authentication by exchanging the challenge and a
+    // code, or a recovery code, for tokens. Wrong codes count as failed logins.
+    pub async fn complete_login(
+        challenge: String,
+        code: String,
+        client: &ClientInfo,
+        pool: &sqlx::PgPool,
+    ) -> Result<TokenPair, ModelError> {
+        let user_id = UserTokenModel::find_login_challenge(&challenge, pool).await?;
+        let row = sqlx::query!(
+            r#"
+            SELECT email
+            FROM users
+            WHERE id = $1
+            "#,
+            user_id
+        )
+        .fetch_one(pool)
+        .await?;
+        let ip = client.ip.as_deref();
+        let result = match LoginFailureModel::check(&row.email, ip, pool).await {
+            Ok(()) => check_code(user_id, &challenge, &code, pool).await,
+            Err(e) => Err(e),
+        };
+        match &result {
+            Ok(_) => LoginFailureModel::clear(&row.email, pool).await?,
+            Err(ModelError::Unauthenticated(_)) => {
+                LoginFailureModel::record_failure(&row.email, ip, pool).await?
+            }
+            Err(_) => {}
         }
+
+        AuditEventModel::record(
+            AuditEntry {
+                actor_id: Some(user_id).filter(|_| result.is_ok()),
+                action: "completeLogin".to_string(),
+                target_id: Some(user_id),
+                before: None,
+                after: Some(json!({
+                    "succeeded": result.is_ok(),
+                    "locked": matches!(result, Err(ModelError::Locked(_))),
+                })),
+            },
+            client,
+            pool,
+        )
+        .await?;
+        result
+    }
+
+    // Log in with an account at the OpenID Connect provider. Two-factor authentication
+    // still applies. Every attempt is recorded in the audit log.
+    pub async fn login_with_identity(
+        identity: ExternalIdentity,
+        client: &ClientInfo,
+        pool: &sqlx::PgPool,
+    ) -> Result<LoginOutcome, ModelError> {
+        let user = OidcModel::find_or_create_user(&identity, pool).await;
+        let user_id = user.as_ref().ok().map(|(user_id, _)| *user_id);
+        let link = user.as_ref().ok().map(|(_, link)| *link);
+        let result = match user {
+            Ok((user_id, _)) => start_session(user_id, pool).await,
+            Err(e) => Err(e),
+        };
+
+        let succeeded = matches!(result, Ok(LoginOutcome::Tokens(_)));
+        AuditEventModel::record(
+            AuditEntry {
+                actor_id: user_id.filter(|_| succeeded),
+                action: "oidcLogin".to_string(),
+                target_id: user_id,
+                before: None,
+                after: Some(json!({
+                    "issuer": identity.issuer,
+                    "subject": identity.subject,
+                    "link": link,
+                    "succeeded": succeeded,
+                    "mfaRequired": matches!(result, Ok(LoginOutcome::MfaRequired(_))),
+                })),
+            },
+            client,
+            pool,
+        )
+        .await?;
+        result
+    }
+}
+
+async fn check_password(
+    row: UsersModel,
+    password: &str,
+    pool: &sqlx::PgPool,
+) -> Result<LoginOutcome, ModelError> {
+    if !verify_password(password, &row.password) {
+        return Err(ModelError::unauthenticated("Invalid email or password."));
+    }
+    start_session(row.id, pool).await
+}
+
+// Issue tokens to a user who proved their identity, or a challenge for the second factor.
+async fn start_session(user_id: i32, pool: &sqlx::PgPool) -> Result<LoginOutcome, ModelError> {
+    if is_suspended(user_id, pool).await? {
+        return Err(ModelError::forbidden("Account suspended."));
+    }
+    if TotpModel::is_enabled(user_id, pool).await? {
+        let challenge = UserTokenModel::issue_login_challenge(user_id, pool).await?;
+        return Ok(LoginOutcome::MfaRequired(challenge));
+    }
+    let tokens = SessionModel::create(user_id, pool).await?;
+    Ok(LoginOutcome::Tokens(tokens))
+}
+
+async fn check_code(
+    user_id: i32,
+    challenge: &str,
+    code: &str,
+    pool: &sqlx::PgPool,
+) -> Result<TokenPair, ModelError> {
+    if !TotpModel::verify(user_id, code, pool).await? {
+        return Err(ModelError::unauthenticated("Invalid code."));
     }
+    if is_suspended(user_id, pool).await? {
+        return Err(ModelError::forbidden("Account suspended."));
+    }
+    UserTokenModel::consume_login_challenge(challenge, pool).await?;
+    let tokens = SessionModel::create(user_id, pool).await?;
+    Ok(tokens)
 }
 
-fn encode_token(user_id: i32) -> Result<String, Error> {
+// Require the user's password before changing their credentials.
+async fn check_current_password(
+    id: i32,
+    password: &str,
+    pool: &sqlx::PgPool,
+) -> Result<(), ModelError> {
+    let row = sqlx::query!(
+        r#"
+        SELECT password
+        FROM users
+        WHERE id = $1
+        "#,
+        id
+    )
+    .fetch_optional(pool)
+    .await?
+    .ok_or_else(|| ModelError::not_found("User not found."))?;
+    if verify_password(password, &row.password) {
+        Ok(())
+    } else {
+        Err(ModelError::forbidden("Invalid password."))
+    }
+}
+
+pub(super) fn hash_password(password: &str) -> String {
+    let salt = SaltString::generate(&mut OsRng);
+    Argon2::default()
+        .hash_password(password.as_bytes(), &salt)
+        .expect("Unable to hash password.")
+        .to_string()
+}
+
+// Hash to verify against for unknown emails, so they take as long as known ones.
+fn dummy_hash() -> &'static str {
+    static DUMMY_HASH: OnceLock<String> = OnceLock::new();
+    DUMMY_HASH.get_or_init(|| hash_password("dummy password"))
+}
+
+fn verify_password(password: &str, password_hash: &str) -> bool {
+    let parsed_hash = PasswordHash::new(password_hash).expect("Unable to parse hash.");
+    Argon2::default()
+        .verify_password(password.as_bytes(), &parsed_hash)
+        .is_ok()
+}
+
+async fn is_suspended(id: i32, pool: &sqlx::PgPool) -> Result<bool, ModelError> {
+    let row = sqlx::query!(
+        r#"
+        SELECT suspended_at IS NOT NULL AS "suspended!"
+        FROM users
+        WHERE id = $1
+        "#,
+        id
+    )
+    .fetch_one(pool)
+    .await?;
+
+    Ok(row.suspended)
+}
+
+// Verify an access token and check that its session has not been revoked.
+pub async fn verify_token(token: &str, pool: &sqlx::PgPool) -> Result<Identity, ModelError> {
+    let claims = jwt::keys().decode::<Claims>(token)?;
+    // The role is read with the session rather than taken from the token, so a
+    // role change applies to tokens issued before it.
+    let role = SessionModel::find_active_role(claims.sid, pool)
+        .await?
+        .ok_or_else(|| ModelError::unauthenticated("Session revoked."))?;
+    Ok(Identity {
+        user_id: claims
+            .sub
+            .parse::<i32>()
+            .map_err(|_| ModelError::unauthenticated("Invalid token."))?,
+        session_id: claims.sid,
+        role,
+    })
+}
+
+pub fn encode_token(user_id: i32, session_id: i32, role: Role) -> Result<String, ModelError> {
     let claims = Claims {
         sub: user_id.to_string(),
-        exp: (Utc::now() + chrono::Duration::hours(24)).timestamp() as usize,
+        sid: session_id,
+        role,
+        exp: (Utc::now() + chrono::Duration::minutes(ACCESS_TOKEN_TTL_MINUTES)).timestamp()
+            as usize,
     };
-    let encoding_key = std::env::var("ENCODING_KEY").expect("Failed to get encoding key.");
-    let token = jsonwebtoken::encode(
-        &jsonwebtoken::Header::default(),
-        &claims,
-        &jsonwebtoken::EncodingKey::from_secret(encoding_key.as_bytes()),
-    )?;
-    Ok(token)
+    jwt::keys().encode(&claims)
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::models::{channel::ChannelModel, events::MessageEventKind, message::MessageModel};
     use anyhow::Result;
     use argon2::PasswordVerifier;
     use sqlx::{query_as, PgPool};
@@ -132,6 +698,7 @@ mod tests {
             name.to_string(),
             email.to_string(),
             password.to_string(),
+            &AuditContext::default(),
             &pool,
         )
         .await?;
@@ -155,6 +722,17 @@ mod tests {
             .verify_password(password.as_bytes(), &parsed_hash)
             .is_ok());
 
+        // Create with the same email.
+        let result = UsersModel::create(
+            name.to_string(),
+            email.to_string(),
+            password.to_string(),
+            &AuditContext::default(),
+            &pool,
+        )
+        .await;
+        assert!(matches!(result, Err(ModelError::Conflict(_))));
+
         Ok(())
     }
 
@@ -168,11 +746,343 @@ mod tests {
             name.to_string(),
             email.to_string(),
             password.to_string(),
+            &AuditContext::default(),
+            &pool,
+        )
+        .await?;
+        let tokens = UsersModel::login(
+            email.to_string(),
+            password.to_string(),
+            &ClientInfo::default(),
+            &pool,
+        )
+        .await?
+        .unwrap_tokens();
+        assert!(!tokens.access_token.is_empty());
+        assert!(!tokens.refresh_token.is_empty());
+        // Login with wrong password and unknown email.
+        let result = UsersModel::login(
+            email.to_string(),
+            "wrong".to_string(),
+            &ClientInfo::default(),
+            &pool,
+        )
+        .await;
+        assert!(matches!(result, Err(ModelError::Unauthenticated(_))));
+        let result = UsersModel::login(
+            "unknown".to_string(),
+            password.to_string(),
+            &ClientInfo::default(),
+            &pool,
+        )
+        .await;
+        assert!(matches!(result, Err(ModelError::Unauthenticated(_))));
+
+        Ok(())
+    }
+
+    #[sqlx::test]
+    async fn verify_token(pool: PgPool) -> Result<()> {
+        let email = "example.example.com";
+        let password = "password";
+
+        let user = UsersModel::create(
+            "test".to_string(),
+            email.to_string(),
+            password.to_string(),
+            &AuditContext::default(),
+            &pool,
+        )
+        .await?;
+        let tokens = UsersModel::login(
+            email.to_string(),
+            password.to_string(),
+            &ClientInfo::default(),
+            &pool,
+        )
+        .await?
+        .unwrap_tokens();
+        let identity = super::verify_token(&tokens.access_token, &pool).await?;
+        assert_eq!(identity.user_id, user.id);
+        assert_eq!(identity.role, Role::User);
+        // A role change applies to tokens issued before it, both ways.
+        UsersModel::set_role(user.id, Role::Moderator, &AuditContext::default(), &pool).await?;
+        let identity = super::verify_token(&tokens.access_token, &pool).await?;
+        assert_eq!(identity.role, Role::Moderator);
+        UsersModel::set_role(user.id, Role::User, &AuditContext::default(), &pool).await?;
+        let identity = super::verify_token(&tokens.access_token, &pool).await?;
+        assert_eq!(identity.role, Role::User);
+        assert!(Role::Admin > Role::Moderator);
+        // Verify invalid token.
+        assert!(super::verify_token("dummy token", &pool).await.is_err());
+
+        Ok(())
+    }
+
+    #[sqlx::test]
+    async fn find_by_ids(pool: PgPool) -> Result<()> {
+        let first = UsersModel::create(
+            "first".to_string(),
+            "first@example.com".to_string(),
+            "password".to_string(),
+            &AuditContext::default(),
+            &pool,
+        )
+        .await?;
+        let second = UsersModel::create(
+            "second".to_string(),
+            "second@example.com".to_string(),
+            "password".to_string(),
+            &AuditContext::default(),
+            &pool,
+        )
+        .await?;
+
+        let rows = UsersModel::find_by_ids(&[first.id, second.id, 0], &pool).await?;
+        assert_eq!(rows.len(), 2);
+
+        Ok(())
+    }
+
+    #[sqlx::test]
+    async fn set_role(pool: PgPool) -> Result<()> {
+        let user = UsersModel::create(
+            "test".to_string(),
+            "example.example.com".to_string(),
+            "password".to_string(),
+            &AuditContext::default(),
+            &pool,
+        )
+        .await?;
+        assert_eq!(
+            UsersModel::set_role(user.id, Role::Admin, &AuditContext::default(), &pool).await?,
+            Role::Admin
+        );
+        let row = sqlx::query!(
+            r#"SELECT role AS "role: Role" FROM users WHERE id = $1"#,
+            user.id
+        )
+        .fetch_one(&pool)
+        .await?;
+        assert_eq!(row.role, Role::Admin);
+        // Set role of missing user.
+        let result = UsersModel::set_role(0, Role::Admin, &AuditContext::default(), &pool).await;
+        assert!(matches!(result, Err(ModelError::NotFound(_))));
+
+        Ok(())
+    }
+
+    #[sqlx::test]
+    async fn update_profile_and_password(pool: PgPool) -> Result<()> {
+        let user = UsersModel::create(
+            "test".to_string(),
+            "test@example.com".to_string(),
+            "password".to_string(),
+            &AuditContext::default(),
+            &pool,
+        )
+        .await?;
+        UsersModel::create(
+            "other".to_string(),
+            "other@example.com".to_string(),
+            "password".to_string(),
+            &AuditContext::default(),
+            &pool,
+        )
+        .await?;
+
+        // Update the name only, then a taken and an empty email.
+        let row = UsersModel::update_profile(
+            user.id,
+            Some(" renamed ".to_string()),
+            None,
+            &AuditContext::default(),
+            &pool,
+        )
+        .await?;
+        assert_eq!(
+            (row.name.as_str(), row.email.as_str()),
+            ("renamed", "test@example.com")
+        );
+        let result = UsersModel::update_profile(
+            user.id,
+            None,
+            Some("other@example.com".to_string()),
+            &AuditContext::default(),
+            &pool,
+        )
+        .await;
+        assert!(matches!(result, Err(ModelError::Conflict(_))));
+        let result = UsersModel::update_profile(
+            user.id,
+            None,
+            Some(" ".to_string()),
+            &AuditContext::default(),
+            &pool,
+        )
+        .await;
+        assert!(matches!(result, Err(ModelError::Validation(_))));
+
+        // Changing the password needs the current one and logs out other sessions.
+        let old = UsersModel::login(
+            "test@example.com".to_string(),
+            "password".to_string(),
+            &ClientInfo::default(),
+            &pool,
+        )
+        .await?
+        .unwrap_tokens();
+        let result = UsersModel::change_password(
+            user.id,
+            "wrong".to_string(),
+            "new password".to_string(),
+            &AuditContext::default(),
+            &pool,
+        )
+        .await;
+        assert!(matches!(result, Err(ModelError::Forbidden(_))));
+        let new = UsersModel::change_password(
+            user.id,
+            "password".to_string(),
+            "new password".to_string(),
+            &AuditContext::default(),
+            &pool,
+        )
+        .await?;
+        assert!(super::verify_token(&old.access_token, &pool).await.is_err());
+        assert!(super::verify_token(&new.access_token, &pool).await.is_ok());
+        UsersModel::login(
+            "test@example.com".to_string(),
+            "new password".to_string(),
+            &ClientInfo::default(),
+            &pool,
+        )
+        .await?
+        .unwrap_tokens();
+
+        Ok(())
+    }
+
+    #[sqlx::test]
+    async fn delete_account(pool: PgPool) -> Result<()> {
+        let mut ids = vec![];
+        for name in ["alice", "bob", "carol"] {
+            let user = UsersModel::create(
+                name.to_string(),
+                format!("{}@example.com", name),
+                "password".to_string(),
+                &AuditContext::default(),
+                &pool,
+            )
+            .await?;
+            ids.push(user.id);
+        }
+        let (alice, bob, carol) = (ids[0], ids[1], ids[2]);
+        let channel =
+            ChannelModel::create("test".to_string(), alice, &AuditContext::default(), &pool)
+                .await?;
+        ChannelModel::join(channel.id, bob, &AuditContext::default(), &pool).await?;
+        ChannelModel::join(channel.id, carol, &AuditContext::default(), &pool).await?;
+        let kept = MessageModel::create(
+            alice,
+            channel.id,
+            "kept".to_string(),
+            None,
+            &AuditContext::default(),
+            &pool,
+        )
+        .await?;
+        let replied = MessageModel::create(
+            bob,
+            channel.id,
+            "replied".to_string(),
+            None,
+            &AuditContext::default(),
+            &pool,
+        )
+        .await?;
+        let reply = MessageModel::create(
+            alice,
+            channel.id,
+            "reply".to_string(),
+            Some(replied.id),
+            &AuditContext::default(),
+            &pool,
+        )
+        .await?;
+        let alone = MessageModel::create(
+            bob,
+            channel.id,
+            "alone".to_string(),
+            None,
+            &AuditContext::default(),
+            &pool,
+        )
+        .await?;
+
+        // Anonymize keeps the messages without an author.
+        let result = UsersModel::delete_account(
+            alice,
+            "wrong".to_string(),
+            DeletionPolicy::Anonymize,
+            &AuditContext::default(),
+            &pool,
+        )
+        .await;
+        assert!(matches!(result, Err(ModelError::Forbidden(_))));
+        UsersModel::delete_account(
+            alice,
+            "password".to_string(),
+            DeletionPolicy::Anonymize,
+            &AuditContext::default(),
+            &pool,
+        )
+        .await?;
+        let rows = MessageModel::find_by_ids(&[kept.id, reply.id], &pool).await?;
+        assert!(rows
+            .iter()
+            .all(|row| row.user_id.is_none() && row.deleted_at.is_none()));
+
+        // Delete erases the messages, keeping tombstones of the replied ones, and
+        // announces their deletion.
+        let mut receiver = events::subscribe();
+        UsersModel::delete_account(
+            bob,
+            "password".to_string(),
+            DeletionPolicy::Delete,
+            &AuditContext::default(),
             &pool,
         )
         .await?;
-        let token = UsersModel::login(email.to_string(), password.to_string(), &pool).await?;
-        assert!(!token.is_empty());
+        let rows = MessageModel::find_by_ids(&[replied.id, alone.id, reply.id], &pool).await?;
+        let tombstone = rows.iter().find(|row| row.id == replied.id).unwrap();
+        assert!(tombstone.deleted_at.is_some() && tombstone.message.is_empty());
+        assert!(rows.iter().all(|row| row.id != alone.id));
+        // Other tests publish to the same channel, so only look at this test's messages.
+        let mut deleted = Vec::new();
+        while deleted.len() < 2 {
+            let event =
+                tokio::time::timeout(std::time::Duration::from_secs(5), receiver.recv()).await??;
+            if event.kind == MessageEventKind::Deleted
+                && [replied.id, alone.id].contains(&event.message.id)
+                && event.message.message.is_empty()
+            {
+                deleted.push((
+                    event.message.id,
+                    event.message.user_id,
+                    event.thread_root_id,
+                ));
+            }
+        }
+        deleted.sort();
+        assert_eq!(
+            deleted,
+            vec![(replied.id, None, replied.id), (alone.id, None, alone.id)]
+        );
+        assert!(UsersModel::find_by_ids(&[alice, bob], &pool)
+            .await?
+            .is_empty());
+        assert_eq!(UsersModel::find_by_ids(&[carol], &pool).await?.len(), 1);
 
         Ok(())
     }
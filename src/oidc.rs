@@ -0,0 +1,669 @@
+use crate::{
+    gql::{audit::client_info, error::model_request_error},
+    models::{
+        error::ModelError,
+        oidc::{ExternalIdentity, OidcModel, PendingLogin, LOGIN_TTL_MINUTES},
+        users::{LoginOutcome, UsersModel},
+    },
+};
+use actix_web::{
+    cookie::{time, Cookie, SameSite},
+    guard,
+    http::{header, StatusCode},
+    web, HttpRequest, HttpResponse,
+};
+use anyhow::{bail, Result};
+use async_graphql::Response;
+use jsonwebtoken::{
+    jwk::{Jwk, JwkSet},
+    Algorithm, DecodingKey, Validation,
+};
+use serde::Deserialize;
+use sqlx::PgPool;
+use std::{env, time::Duration};
+use tokio::sync::{OnceCell, RwLock};
+use url::Url;
+
+const STATE_COOKIE: &str = "oidc_state";
+const REQUEST_TIMEOUT_SECONDS: u64 = 10;
+// Symmetric algorithms are not accepted, since they would be keyed with the client secret.
+const ID_TOKEN_ALGORITHMS: [Algorithm; 9] = [
+    Algorithm::RS256,
+    Algorithm::RS384,
+    Algorithm::RS512,
+    Algorithm::PS256,
+    Algorithm::PS384,
+    Algorithm::PS512,
+    Algorithm::ES256,
+    Algorithm::ES384,
+    Algorithm::EdDSA,
+];
+
+// The OpenID Connect provider to log in with.
+#[derive(Debug, Clone)]
+pub struct OidcConfig {
+    // The provider's endpoints are discovered from `{issuer}/.well-known/openid-configuration`.
+    pub issuer: String,
+    pub client_id: String,
+    // Public clients have none and rely on PKCE alone.
+    pub client_secret: Option<String>,
+    // Where the provider sends the user back to, i.e. `/auth/oidc/callback` on this server.
+    pub redirect_uri: String,
+    pub scopes: String,
+}
+
+impl OidcConfig {
+    // Login through a provider is enabled by `OIDC_ISSUER`, which needs `OIDC_CLIENT_ID`
+    // and `OIDC_REDIRECT_URI` too.
+    pub fn from_env() -> Option<Self> {
+        let issuer = env::var("OIDC_ISSUER").ok()?;
+        Some(OidcConfig {
+            issuer,
+            client_id: env::var("OIDC_CLIENT_ID").expect("Failed to get OIDC_CLIENT_ID."),
+            client_secret: env::var("OIDC_CLIENT_SECRET").ok(),
+            redirect_uri: env::var("OIDC_REDIRECT_URI").expect("Failed to get OIDC_REDIRECT_URI."),
+            scopes: env::var("OIDC_SCOPES").unwrap_or_else(|_| "openid email profile".to_string()),
+        })
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct ProviderMetadata {
+    issuer: String,
+    authorization_endpoint: String,
+    token_endpoint: String,
+    jwks_uri: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct TokenResponse {
+    id_token: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct IdTokenClaims {
+    sub: String,
+    nonce: Option<String>,
+    email: Option<String>,
+    #[serde(default)]
+    email_verified: bool,
+    name: Option<String>,
+}
+
+// Client of the provider. Its metadata is fetched on first use, and its keys again
+// whenever a token names an unknown one, so the provider can rotate them.
+pub struct OidcClient {
+    config: OidcConfig,
+    http: reqwest::Client,
+    metadata: OnceCell<ProviderMetadata>,
+    jwks: RwLock<JwkSet>,
+}
+
+impl OidcClient {
+    pub fn new(config: OidcConfig) -> Self {
+        OidcClient {
+            config,
+            http: reqwest::Client::builder()
+                .timeout(Duration::from_secs(REQUEST_TIMEOUT_SECONDS))
+                .build()
+                .expect("Failed to build HTTP client."),
+            metadata: OnceCell::new(),
+            jwks: RwLock::new(JwkSet { keys: vec![] }),
+        }
+    }
+
+    async fn metadata(&self) -> Result<&ProviderMetadata> {
+        self.metadata
+            .get_or_try_init(|| async {
+                let url = format!(
+                    "{}/.well-known/openid-configuration",
+                    self.config.issuer.trim_end_matches('/')
+                );
+                let metadata = self
+                    .http
+                    .get(url)
+                    .send()
+                    .await?
+                    .error_for_status()?
+                    .json::<ProviderMetadata>()
+                    .await?;
+                if metadata.issuer != self.config.issuer {
+                    bail!(
+                        "Provider issuer {} does not match OIDC_ISSUER.",
+                        metadata.issuer
+                    );
+                }
+                Ok(metadata)
+            })
+            .await
+    }
+
+    async fn authorization_url(&self, login: &PendingLogin) -> Result<Url> {
+        let metadata = self.metadata().await?;
+        let code_challenge = login.code_challenge();
+        Ok(Url::parse_with_params(
+            &metadata.authorization_endpoint,
+            [
+                ("response_type", "code"),
+                ("client_id", self.config.client_id.as_str()),
+                ("redirect_uri", self.config.redirect_uri.as_str()),
+                ("scope", self.config.scopes.as_str()),
+                ("state", login.state.as_str()),
+                ("nonce", login.nonce.as_str()),
+                ("code_challenge", code_challenge.as_str()),
+                ("code_challenge_method", "S256"),
+            ],
+        )?)
+    }
+
+    // Exchange the authorization code for an ID token.
+    async fn exchange_code(&self, code: &str, login: &PendingLogin) -> Result<String, ModelError> {
+        let metadata = self.metadata().await?;
+        let mut form = vec![
+            ("grant_type", "authorization_code"),
+            ("code", code),
+            ("redirect_uri", self.config.redirect_uri.as_str()),
+            ("client_id", self.config.client_id.as_str()),
+            ("code_verifier", login.code_verifier.as_str()),
+        ];
+        if let Some(client_secret) = &self.config.client_secret {
+            form.push(("client_secret", client_secret));
+        }
+        let response = self
+            .http
+            .post(&metadata.token_endpoint)
+            .form(&form)
+            .send()
+            .await
+            .map_err(anyhow::Error::from)?;
+        let status = response.status();
+        // E.g. an invalid or reused code.
+        if status.is_client_error() {
+            let body = response.text().await.unwrap_or_default();
+            log::warn!(
+                "Provider rejected the authorization code: {} {}",
+                status,
+                body
+            );
+            return Err(ModelError::unauthenticated(
+                "The identity provider rejected the login.",
+            ));
+        }
+        let response = response
+            .error_for_status()
+            .map_err(anyhow::Error::from)?
+            .json::<TokenResponse>()
+            .await
+            .map_err(anyhow::Error::from)?;
+        Ok(response.id_token)
+    }
+
+    async fn verify_id_token(
+        &self,
+        id_token: &str,
+        login: &PendingLogin,
+    ) -> Result<ExternalIdentity, ModelError> {
+        let invalid = || ModelError::unauthenticated("Invalid ID token.");
+        let header = jsonwebtoken::decode_header(id_token).map_err(|_| invalid())?;
+        if !ID_TOKEN_ALGORITHMS.contains(&header.alg) {
+            return Err(invalid());
+        }
+        let key = self
+            .decoding_key(header.kid.as_deref())
+            .await?
+            .ok_or_else(invalid)?;
+        let mut validation = Validation::new(header.alg);
+        validation.set_issuer(&[&self.config.issuer]);
+        validation.set_audience(&[&self.config.client_id]);
+        validation.set_required_spec_claims(&["exp", "iss", "aud", "sub"]);
+        let claims = jsonwebtoken::decode::<IdTokenClaims>(id_token, &key, &validation)
+            .map_err(|_| invalid())?
+            .claims;
+        if claims.nonce.as_deref() != Some(login.nonce.as_str()) {
+            return Err(invalid());
+        }
+        Ok(ExternalIdentity {
+            issuer: self.config.issuer.clone(),
+            subject: claims.sub,
+            email: claims.email,
+            email_verified: claims.email_verified,
+            name: claims.name,
+        })
+    }
+
+    async fn decoding_key(&self, kid: Option<&str>) -> Result<Option<DecodingKey>, ModelError> {
+        if let Some(jwk) = find_key(&*self.jwks.read().await, kid) {
+            return Ok(Some(DecodingKey::from_jwk(jwk)?));
+        }
+        let jwks = self.fetch_jwks().await?;
+        let key = find_key(&jwks, kid)
+            .map(DecodingKey::from_jwk)
+            .transpose()?;
+        *self.jwks.write().await = jwks;
+        Ok(key)
+    }
+
+    async fn fetch_jwks(&self) -> Result<JwkSet> {
+        let metadata = self.metadata().await?;
+        Ok(self
+            .http
+            .get(&metadata.jwks_uri)
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?)
+    }
+}
+
+// Tokens without a `kid` are only accepted from providers with a single key.
+fn find_key<'a>(jwks: &'a JwkSet, kid: Option<&str>) -> Option<&'a Jwk> {
+    match kid {
+        Some(kid) => jwks.find(kid),
+        None if jwks.keys.len() == 1 => jwks.keys.first(),
+        None => None,
+    }
+}
+
+pub fn routes(cfg: &mut web::ServiceConfig) {
+    cfg.service(
+        web::resource("/auth/oidc/start")
+            .guard(guard::Get())
+            .to(start),
+    )
+    .service(
+        web::resource("/auth/oidc/callback")
+            .guard(guard::Get())
+            .to(callback),
+    );
+}
+
+// Send the user to the provider. The state is kept in a cookie too, so the login can
+// only be finished in the browser that started it.
+async fn start(oidc: web::Data<OidcClient>, pool: web::Data<PgPool>) -> HttpResponse {
+    start_login(&oidc, &pool)
+        .await
+        .unwrap_or_else(error_response)
+}
+
+async fn start_login(oidc: &OidcClient, pool: &PgPool) -> Result<HttpResponse, ModelError> {
+    let login = OidcModel::start_login(pool).await?;
+    let url = oidc.authorization_url(&login).await?;
+    Ok(HttpResponse::Found()
+        .insert_header((header::LOCATION, url.as_str()))
+        .cookie(state_cookie(&oidc.config, login.state))
+        .finish())
+}
+
+#[derive(Debug, Deserialize)]
+struct CallbackQuery {
+    code: Option<String>,
+    state: Option<String>,
+    error: Option<String>,
+    error_description: Option<String>,
+}
+
+// Finish the login and respond with tokens, or a challenge for users with two-factor
+// authentication, like the `login` query.
+async fn callback(
+    oidc: web::Data<OidcClient>,
+    pool: web::Data<PgPool>,
+    req: HttpRequest,
+    query: web::Query<CallbackQuery>,
+) -> HttpResponse {
+    let mut response = finish_login(&oidc, &pool, &req, query.into_inner())
+        .await
+        .unwrap_or_else(error_response);
+    // The state cannot be used again either way.
+    let _ = response.add_removal_cookie(&state_cookie(&oidc.config, String::new()));
+    response
+}
+
+async fn finish_login(
+    oidc: &OidcClient,
+    pool: &PgPool,
+    req: &HttpRequest,
+    query: CallbackQuery,
+) -> Result<HttpResponse, ModelError> {
+    if let Some(error) = query.error {
+        return Err(ModelError::Unauthenticated(format!(
+            "The identity provider declined the login: {}",
+            query.error_description.unwrap_or(error)
+        )));
+    }
+    let (code, state) = query
+        .code
+        .zip(query.state)
+        .ok_or_else(|| ModelError::validation("Missing code or state."))?;
+    let cookie = req.cookie(STATE_COOKIE);
+    if cookie.as_ref().map(|cookie| cookie.value()) != Some(state.as_str()) {
+        return Err(ModelError::unauthenticated(
+            "The login was started in another browser.",
+        ));
+    }
+
+    let login = OidcModel::finish_login(&state, pool).await?;
+    let id_token = oidc.exchange_code(&code, &login).await?;
+    let identity = oidc.verify_id_token(&id_token, &login).await?;
+    let mut response = HttpResponse::Ok();
+    response.insert_header((header::CACHE_CONTROL, "no-store"));
+    Ok(
+        match UsersModel::login_with_identity(identity, &client_info(req), pool).await? {
+            LoginOutcome::Tokens(tokens) => response.json(tokens),
+            LoginOutcome::MfaRequired(challenge) => response.json(challenge),
+        },
+    )
+}
+
+fn state_cookie(config: &OidcConfig, state: String) -> Cookie<'static> {
+    Cookie::build(STATE_COOKIE, state)
+        .path("/auth/oidc")
+        .http_only(true)
+        .secure(config.redirect_uri.starts_with("https:"))
+        // Lax, so it is sent when the provider redirects back.
+        .same_site(SameSite::Lax)
+        .max_age(time::Duration::minutes(LOGIN_TTL_MINUTES))
+        .finish()
+}
+
+fn error_response(e: ModelError) -> HttpResponse {
+    let status = match &e {
+        ModelError::NotFound(_) => StatusCode::NOT_FOUND,
+        ModelError::Unauthenticated(_) => StatusCode::UNAUTHORIZED,
+        ModelError::Forbidden(_) => StatusCode::FORBIDDEN,
+        ModelError::Validation(_) => StatusCode::BAD_REQUEST,
+        ModelError::Conflict(_) => StatusCode::CONFLICT,
+        ModelError::Locked(_) => StatusCode::TOO_MANY_REQUESTS,
+        ModelError::Internal(_) => StatusCode::INTERNAL_SERVER_ERROR,
+    };
+    HttpResponse::build(status).json(Response::from_errors(vec![model_request_error(&e)]))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::{jwt::JwtKeys, session::TokenPair, users::verify_token};
+    use actix_web::{dev::ServiceResponse, test, App, HttpServer};
+    use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+    use chrono::Utc;
+    use serde_json::json;
+    use sha2::{Digest, Sha256};
+    use std::{collections::HashMap, net::TcpListener, sync::Mutex, thread};
+
+    const CLIENT_ID: &str = "client";
+
+    // A provider where everyone is logged in as Alice already.
+    struct MockProvider {
+        issuer: String,
+        keys: JwtKeys,
+        // Whether the provider has verified Alice's email.
+        email_verified: bool,
+        // Nonce and PKCE challenge by authorization code.
+        codes: Mutex<HashMap<String, (String, String)>>,
+    }
+
+    // Run the provider on its own thread, since the tests block on the responses.
+    fn start_provider(email_verified: bool) -> String {
+        let (sender, receiver) = std::sync::mpsc::channel();
+        thread::spawn(move || {
+            actix_web::rt::System::new().block_on(async move {
+                let listener = TcpListener::bind("127.0.0.1:0")?;
+                let issuer = format!("http://{}", listener.local_addr()?);
+                let provider = web::Data::new(MockProvider {
+                    issuer: issuer.clone(),
+                    keys: JwtKeys::generate().expect("Failed to generate keys."),
+                    email_verified,
+                    codes: Mutex::new(HashMap::new()),
+                });
+                let server = HttpServer::new(move || {
+                    App::new()
+                        .app_data(provider.clone())
+                        .route(
+                            "/.well-known/openid-configuration",
+                            web::get().to(discovery),
+                        )
+                        .route("/authorize", web::get().to(authorize))
+                        .route("/token", web::post().to(token))
+                        .route("/jwks", web::get().to(jwks))
+                })
+                .workers(1)
+                .listen(listener)?
+                .run();
+                sender.send(issuer).expect("Failed to send issuer.");
+                server.await
+            })
+        });
+        receiver.recv().expect("Failed to start provider.")
+    }
+
+    async fn discovery(provider: web::Data<MockProvider>) -> HttpResponse {
+        HttpResponse::Ok().json(json!({
+            "issuer": provider.issuer,
+            "authorization_endpoint": format!("{}/authorize", provider.issuer),
+            "token_endpoint": format!("{}/token", provider.issuer),
+            "jwks_uri": format!("{}/jwks", provider.issuer),
+        }))
+    }
+
+    async fn authorize(
+        provider: web::Data<MockProvider>,
+        query: web::Query<HashMap<String, String>>,
+    ) -> HttpResponse {
+        assert_eq!(query["client_id"], CLIENT_ID);
+        assert_eq!(query["code_challenge_method"], "S256");
+        let code = format!("code-{}", provider.codes.lock().unwrap().len());
+        provider.codes.lock().unwrap().insert(
+            code.clone(),
+            (query["nonce"].clone(), query["code_challenge"].clone()),
+        );
+        let redirect = Url::parse_with_params(
+            &query["redirect_uri"],
+            [("code", code.as_str()), ("state", query["state"].as_str())],
+        )
+        .unwrap();
+        HttpResponse::Found()
+            .insert_header((header::LOCATION, redirect.as_str()))
+            .finish()
+    }
+
+    async fn token(
+        provider: web::Data<MockProvider>,
+        form: web::Form<HashMap<String, String>>,
+    ) -> HttpResponse {
+        let code = provider.codes.lock().unwrap().remove(&form["code"]);
+        let (nonce, challenge) = match code {
+            Some(code) => code,
+            None => return HttpResponse::BadRequest().json(json!({"error": "invalid_grant"})),
+        };
+        let verifier_challenge = URL_SAFE_NO_PAD.encode(Sha256::digest(&form["code_verifier"]));
+        if verifier_challenge != challenge || form["client_id"] != CLIENT_ID {
+            return HttpResponse::BadRequest().json(json!({"error": "invalid_grant"}));
+        }
+        let id_token = provider
+            .keys
+            .encode(&json!({
+                "iss": provider.issuer,
+                "sub": "alice",
+                "aud": CLIENT_ID,
+                "exp": Utc::now().timestamp() + 300,
+                "nonce": nonce,
+                "email": "alice@example.com",
+                "email_verified": provider.email_verified,
+                "name": "Alice",
+            }))
+            .unwrap();
+        HttpResponse::Ok().json(json!({
+            "access_token": "provider-token",
+            "token_type": "Bearer",
+            "id_token": id_token,
+        }))
+    }
+
+    async fn jwks(provider: web::Data<MockProvider>) -> HttpResponse {
+        HttpResponse::Ok().json(provider.keys.jwks())
+    }
+
+    // Follow the redirect of `/auth/oidc/start` to the provider, which sends the browser
+    // back with a code. Returns the callback path.
+    async fn log_in_at_provider(
+        browser: &reqwest::Client,
+        response: &ServiceResponse,
+    ) -> anyhow::Result<String> {
+        let location = response
+            .headers()
+            .get(header::LOCATION)
+            .expect("Missing location.")
+            .to_str()?;
+        let response = browser.get(location).send().await?;
+        let callback = response
+            .headers()
+            .get(reqwest::header::LOCATION)
+            .expect("Missing location.")
+            .to_str()?;
+        let callback = Url::parse(callback)?;
+        assert_eq!(callback.path(), "/auth/oidc/callback");
+        Ok(format!(
+            "{}?{}",
+            callback.path(),
+            callback.query().unwrap_or_default()
+        ))
+    }
+
+    fn client(issuer: String) -> OidcClient {
+        OidcClient::new(OidcConfig {
+            issuer,
+            client_id: CLIENT_ID.to_string(),
+            client_secret: None,
+            redirect_uri: "http://localhost:8000/auth/oidc/callback".to_string(),
+            scopes: "openid email profile".to_string(),
+        })
+    }
+
+    #[sqlx::test]
+    async fn mock_provider(pool: PgPool) -> anyhow::Result<()> {
+        let oidc = client(start_provider(true));
+        let app = test::init_service(
+            App::new()
+                .app_data(web::Data::new(pool.clone()))
+                .app_data(web::Data::new(oidc))
+                .configure(routes),
+        )
+        .await;
+        let browser = reqwest::Client::builder()
+            .redirect(reqwest::redirect::Policy::none())
+            .build()?;
+
+        let mut user_ids = vec![];
+        let mut callbacks = vec![];
+        for _ in 0..2 {
+            let response = test::call_service(
+                &app,
+                test::TestRequest::get()
+                    .uri("/auth/oidc/start")
+                    .to_request(),
+            )
+            .await;
+            assert_eq!(response.status(), StatusCode::FOUND);
+            let cookie = response
+                .response()
+                .cookies()
+                .find(|cookie| cookie.name() == STATE_COOKIE)
+                .expect("Missing state cookie.")
+                .into_owned();
+            let uri = log_in_at_provider(&browser, &response).await?;
+
+            let response = test::call_service(
+                &app,
+                test::TestRequest::get()
+                    .uri(&uri)
+                    .cookie(cookie.clone())
+                    .to_request(),
+            )
+            .await;
+            assert_eq!(response.status(), StatusCode::OK);
+            let tokens: TokenPair = test::read_body_json(response).await;
+            user_ids.push(verify_token(&tokens.access_token, &pool).await?.user_id);
+            callbacks.push((uri, cookie));
+        }
+        // The second login finds the user created by the first.
+        assert_eq!(user_ids[0], user_ids[1]);
+
+        // Callbacks cannot be replayed.
+        let (uri, cookie) = &callbacks[0];
+        let response = test::call_service(
+            &app,
+            test::TestRequest::get()
+                .uri(uri)
+                .cookie(cookie.clone())
+                .to_request(),
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+
+        // Nor finished in another browser.
+        let response = test::call_service(
+            &app,
+            test::TestRequest::get()
+                .uri("/auth/oidc/start")
+                .to_request(),
+        )
+        .await;
+        let uri = log_in_at_provider(&browser, &response).await?;
+        let response =
+            test::call_service(&app, test::TestRequest::get().uri(&uri).to_request()).await;
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        Ok(())
+    }
+
+    #[sqlx::test]
+    async fn mock_provider_unverified_email(pool: PgPool) -> anyhow::Result<()> {
+        let oidc = client(start_provider(false));
+        let app = test::init_service(
+            App::new()
+                .app_data(web::Data::new(pool.clone()))
+                .app_data(web::Data::new(oidc))
+                .configure(routes),
+        )
+        .await;
+        let browser = reqwest::Client::builder()
+            .redirect(reqwest::redirect::Policy::none())
+            .build()?;
+
+        let response = test::call_service(
+            &app,
+            test::TestRequest::get()
+                .uri("/auth/oidc/start")
+                .to_request(),
+        )
+        .await;
+        let cookie = response
+            .response()
+            .cookies()
+            .find(|cookie| cookie.name() == STATE_COOKIE)
+            .expect("Missing state cookie.")
+            .into_owned();
+        let uri = log_in_at_provider(&browser, &response).await?;
+        let response = test::call_service(
+            &app,
+            test::TestRequest::get()
+                .uri(&uri)
+                .cookie(cookie)
+                .to_request(),
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+
+        // The email stays free for its owner.
+        let row = sqlx::query!(
+            r#"
+            select count(*) as "count!"
+            from users
+            where email = 'alice@example.com'
+            "#
+        )
+        .fetch_one(&pool)
+        .await?;
+        assert_eq!(row.count, 0);
+        Ok(())
+    }
+}
@@ -0,0 +1,162 @@
+use std::{
+    collections::HashMap,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+// Buckets are pruned once there are this many, so the map cannot grow without bound.
+const MAX_BUCKETS: usize = 10_000;
+
+// Token bucket per client. Every request takes a token, and tokens refill at
+// `per_second` up to `burst`.
+pub struct RateLimiter {
+    burst: f64,
+    per_second: f64,
+    max_buckets: usize,
+    buckets: Mutex<HashMap<String, Bucket>>,
+}
+
+struct Bucket {
+    tokens: f64,
+    updated_at: Instant,
+}
+
+impl RateLimiter {
+    pub fn new(burst: u32, per_second: f64) -> Self {
+        Self::with_max_buckets(burst, per_second, MAX_BUCKETS)
+    }
+
+    fn with_max_buckets(burst: u32, per_second: f64, max_buckets: usize) -> Self {
+        assert!(burst > 0 && per_second > 0.0, "Invalid rate limit.");
+        RateLimiter {
+            burst: burst as f64,
+            per_second,
+            max_buckets,
+            buckets: Mutex::new(HashMap::new()),
+        }
+    }
+
+    // Take a token for the client, or return how long until the next one.
+    pub fn check(&self, key: &str) -> Result<(), Duration> {
+        self.check_at(key, Instant::now())
+    }
+
+    fn check_at(&self, key: &str, now: Instant) -> Result<(), Duration> {
+        let mut buckets = self.buckets.lock().expect("Rate limiter lock poisoned.");
+        if buckets.len() >= self.max_buckets {
+            self.prune(&mut buckets, now);
+        }
+
+        let bucket = buckets.entry(key.to_string()).or_insert(Bucket {
+            tokens: self.burst,
+            updated_at: now,
+        });
+        bucket.tokens = self.tokens(bucket, now);
+        bucket.updated_at = now;
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            Ok(())
+        } else {
+            Err(Duration::from_secs_f64(
+                (1.0 - bucket.tokens) / self.per_second,
+            ))
+        }
+    }
+
+    // Drop full buckets, which are the same as missing ones, then the least recently used
+    // until at most half are left. Pruning then runs at most once per `max_buckets / 2`
+    // new clients.
+    fn prune(&self, buckets: &mut HashMap<String, Bucket>, now: Instant) {
+        buckets.retain(|_, bucket| self.tokens(bucket, now) < self.burst);
+        let keep = self.max_buckets / 2;
+        if buckets.len() <= keep {
+            return;
+        }
+        let mut by_age: Vec<(Instant, String)> = buckets
+            .iter()
+            .map(|(key, bucket)| (bucket.updated_at, key.clone()))
+            .collect();
+        let evict = by_age.len() - keep;
+        by_age.select_nth_unstable(evict - 1);
+        for (_, key) in &by_age[..evict] {
+            buckets.remove(key);
+        }
+    }
+
+    fn tokens(&self, bucket: &Bucket, now: Instant) -> f64 {
+        let elapsed = now.saturating_duration_since(bucket.updated_at);
+        (bucket.tokens + elapsed.as_secs_f64() * self.per_second).min(self.burst)
+    }
+}
+
+// Every request is limited per IP address, and authenticated requests also per user.
+pub fn ip_key(ip: Option<&str>) -> String {
+    format!("ip:{}", ip.unwrap_or("unknown"))
+}
+
+pub fn user_key(user_id: i32) -> String {
+    format!("user:{}", user_id)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn token_bucket() {
+        let limiter = RateLimiter::new(2, 1.0);
+        let now = Instant::now();
+        assert!(limiter.check_at("a", now).is_ok());
+        assert!(limiter.check_at("a", now).is_ok());
+        assert_eq!(limiter.check_at("a", now), Err(Duration::from_secs(1)));
+
+        // Other clients have their own bucket.
+        assert!(limiter.check_at("b", now).is_ok());
+
+        // Tokens refill over time, up to the burst.
+        let later = now + Duration::from_millis(500);
+        assert_eq!(
+            limiter.check_at("a", later),
+            Err(Duration::from_millis(500))
+        );
+        assert!(limiter.check_at("a", now + Duration::from_secs(1)).is_ok());
+        let much_later = now + Duration::from_secs(60);
+        assert!(limiter.check_at("a", much_later).is_ok());
+        assert!(limiter.check_at("a", much_later).is_ok());
+        assert!(limiter.check_at("a", much_later).is_err());
+
+        assert_eq!(user_key(1), "user:1");
+        assert_eq!(ip_key(Some("127.0.0.1")), "ip:127.0.0.1");
+    }
+
+    #[test]
+    fn prune() {
+        let limiter = RateLimiter::with_max_buckets(2, 1.0, 4);
+        let now = Instant::now();
+        for (i, key) in ["a", "b", "c", "d"].iter().enumerate() {
+            let at = now + Duration::from_millis(i as u64);
+            assert!(limiter.check_at(key, at).is_ok());
+            assert!(limiter.check_at(key, at).is_ok());
+        }
+
+        // None of the buckets are full, so the least recently used are dropped.
+        let at = now + Duration::from_millis(10);
+        assert!(limiter.check_at("e", at).is_ok());
+        {
+            let buckets = limiter.buckets.lock().unwrap();
+            let mut keys: Vec<&str> = buckets.keys().map(String::as_str).collect();
+            keys.sort_unstable();
+            assert_eq!(keys, ["c", "d", "e"]);
+        }
+        assert!(limiter.check_at("d", at).is_err());
+
+        // Full buckets are dropped first.
+        let much_later = now + Duration::from_secs(60);
+        assert!(limiter.check_at("f", much_later).is_ok());
+        assert!(limiter.check_at("g", much_later).is_ok());
+        let buckets = limiter.buckets.lock().unwrap();
+        let mut keys: Vec<&str> = buckets.keys().map(String::as_str).collect();
+        keys.sort_unstable();
+        assert_eq!(keys, ["f", "g"]);
+    }
+}